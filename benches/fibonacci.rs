@@ -3,11 +3,9 @@ extern crate criterion;
 extern crate gene;
 
 use criterion::Criterion;
-use criterion::black_box;
 
 use gene::compiler2::Compiler;
 use gene::parser::Parser;
-use gene::types::Value;
 use gene::vm::VirtualMachine;
 
 pub fn criterion_benchmark(c: &mut Criterion) {
@@ -23,7 +21,7 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     ");
     let parsed = parser.parse();
     let mut compiler = Compiler::new();
-    compiler.compile(parsed.unwrap());
+    compiler.compile(parsed.unwrap()).unwrap();
     let module = compiler.module;
     let mut vm = VirtualMachine::new();
 
@@ -0,0 +1,304 @@
+//! `#[derive(ToGene, FromGene)]` for the `gene` crate.
+//!
+//! A struct maps to a gene whose kind is the type name and whose props are the fields:
+//!
+//! ```ignore
+//! #[derive(ToGene, FromGene)]
+//! #[gene(kind = "person")]         // optional, defaults to "Person"
+//! struct Person {
+//!     #[gene(data)]                // data item 0
+//!     name: String,
+//!     #[gene(rename = "years")]    // prop "years" instead of "age"
+//!     age: i64,
+//!     email: Option<String>,       // missing prop becomes None
+//! }
+//! // (person ^years 30 ^email null "Ann")
+//! ```
+//!
+//! Tuple struct fields go to data by position and unit structs map to a gene with only a kind.
+//! Every enum variant maps to its own kind (the variant name unless `#[gene(kind = "...")]`
+//! is given) and its fields follow the same rules as structs.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, Generics, Ident,
+    Lit, Meta, NestedMeta, Result,
+};
+
+#[proc_macro_derive(ToGene, attributes(gene))]
+pub fn derive_to_gene(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_to_gene(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+#[proc_macro_derive(FromGene, attributes(gene))]
+pub fn derive_from_gene(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_gene(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+#[derive(Default)]
+struct Options {
+    kind: Option<String>,
+    rename: Option<String>,
+    data: bool,
+}
+
+fn parse_options(attrs: &[Attribute]) -> Result<Options> {
+    let mut options = Options::default();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("gene")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new_spanned(meta, "expected #[gene(...)]")),
+        };
+        for item in list.nested.iter() {
+            match item {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("kind") || nv.path.is_ident("rename") => {
+                    let value = match &nv.lit {
+                        Lit::Str(s) => s.value(),
+                        lit => return Err(Error::new_spanned(lit, "expected a string")),
+                    };
+                    if nv.path.is_ident("kind") {
+                        options.kind = Some(value);
+                    } else {
+                        options.rename = Some(value);
+                    }
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("data") => {
+                    options.data = true;
+                }
+                _ => return Err(Error::new_spanned(item, "unknown gene attribute, expected `kind = \"...\"`, `rename = \"...\"` or `data`")),
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// Where a field is stored in the gene
+enum Slot {
+    Prop(String),
+    Data(usize),
+}
+
+struct FieldInfo {
+    /// Field name for named fields, `_0`, `_1`... for tuple fields
+    binding: Ident,
+    /// Token used to access the field: `name` or `0`
+    member: TokenStream2,
+    slot: Slot,
+}
+
+fn field_infos(fields: &Fields) -> Result<Vec<FieldInfo>> {
+    let mut infos = Vec::new();
+    let mut data_index = 0;
+    for (i, field) in fields.iter().enumerate() {
+        let options = parse_options(&field.attrs)?;
+        if options.kind.is_some() {
+            return Err(Error::new_spanned(field, "`kind` is only allowed on types and enum variants"));
+        }
+        let info = match &field.ident {
+            Some(ident) => {
+                let slot = if options.data {
+                    data_index += 1;
+                    Slot::Data(data_index - 1)
+                } else {
+                    Slot::Prop(options.rename.unwrap_or_else(|| ident.to_string()))
+                };
+                FieldInfo {
+                    binding: ident.clone(),
+                    member: quote!(#ident),
+                    slot,
+                }
+            }
+            None => {
+                if options.rename.is_some() {
+                    return Err(Error::new_spanned(field, "tuple fields are stored in data, `rename` does not apply"));
+                }
+                let index = syn::Index::from(i);
+                FieldInfo {
+                    binding: format_ident!("_{}", i),
+                    member: quote!(#index),
+                    slot: Slot::Data(i),
+                }
+            }
+        };
+        infos.push(info);
+    }
+    Ok(infos)
+}
+
+fn add_bounds(generics: &Generics, bound: TokenStream2) -> Generics {
+    let mut generics = generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(#bound));
+    }
+    generics
+}
+
+/// Statements that build the gene from variables named after the fields' bindings.
+/// Generated locals start with `__` so they don't clash with the bindings.
+fn build_gene(kind: &str, fields: &[FieldInfo]) -> TokenStream2 {
+    let stmts = fields.iter().map(|field| {
+        let binding = &field.binding;
+        match &field.slot {
+            Slot::Prop(name) => quote! {
+                __gene_out.props.insert(
+                    #name.to_string(),
+                    ::gene::convert::ToGene::to_gene(#binding).map_err(|e| e.at(#name))?,
+                );
+            },
+            Slot::Data(index) => quote! {
+                __gene_out.data.push(::gene::convert::ToGene::to_gene(#binding).map_err(|e| e.at(#index))?);
+            },
+        }
+    });
+    quote! {
+        let mut __gene_out = ::gene::types::Gene::new(::gene::types::Value::Symbol(#kind.to_string()));
+        #(#stmts)*
+        Ok(::gene::types::Value::Gene(Box::new(__gene_out)))
+    }
+}
+
+/// Constructor expression for `path` that reads every field from `__gene_in`
+fn read_fields(path: TokenStream2, fields: &Fields, infos: &[FieldInfo]) -> TokenStream2 {
+    let values = infos.iter().map(|field| match &field.slot {
+        Slot::Prop(name) => quote!(::gene::convert::prop_from_gene(__gene_in, #name)?),
+        Slot::Data(index) => quote!(::gene::convert::data_from_gene(__gene_in, #index)?),
+    });
+    match fields {
+        Fields::Named(_) => {
+            let members = infos.iter().map(|field| &field.member);
+            quote!(#path { #(#members: #values),* })
+        }
+        Fields::Unnamed(_) => quote!(#path(#(#values),*)),
+        Fields::Unit => path,
+    }
+}
+
+/// Pattern that binds every field to a variable named after its binding
+fn bind_fields(path: TokenStream2, fields: &Fields, infos: &[FieldInfo]) -> TokenStream2 {
+    let bindings = infos.iter().map(|field| &field.binding);
+    match fields {
+        Fields::Named(_) => quote!(#path { #(#bindings),* }),
+        Fields::Unnamed(_) => quote!(#path(#(#bindings),*)),
+        Fields::Unit => path,
+    }
+}
+
+fn expand_to_gene(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let options = parse_options(&input.attrs)?;
+    let generics = add_bounds(&input.generics, quote!(::gene::convert::ToGene));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let kind = options.kind.unwrap_or_else(|| name.to_string());
+            let infos = field_infos(&data.fields)?;
+            let pattern = bind_fields(quote!(#name), &data.fields, &infos);
+            let build = build_gene(&kind, &infos);
+            quote! {
+                let #pattern = self;
+                #build
+            }
+        }
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            for variant in data.variants.iter() {
+                let variant_name = &variant.ident;
+                let kind = parse_options(&variant.attrs)?.kind.unwrap_or_else(|| variant_name.to_string());
+                let infos = field_infos(&variant.fields)?;
+                let pattern = bind_fields(quote!(#name::#variant_name), &variant.fields, &infos);
+                let build = build_gene(&kind, &infos);
+                arms.push(quote!(#pattern => { #build }));
+            }
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new_spanned(input, "unions can not be converted to genes")),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::gene::convert::ToGene for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn to_gene(&self) -> Result<::gene::types::Value, ::gene::convert::ConvertError> {
+                #body
+            }
+        }
+    })
+}
+
+fn expand_from_gene(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let options = parse_options(&input.attrs)?;
+    let generics = add_bounds(&input.generics, quote!(::gene::convert::FromGene));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let kind = options.kind.unwrap_or_else(|| name.to_string());
+            let infos = field_infos(&data.fields)?;
+            let construct = read_fields(quote!(#name), &data.fields, &infos);
+            quote! {
+                let __gene_in = ::gene::convert::expect_gene(value, #kind)?;
+                Ok(#construct)
+            }
+        }
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            let mut kinds = Vec::new();
+            for variant in data.variants.iter() {
+                let variant_name = &variant.ident;
+                let kind = parse_options(&variant.attrs)?.kind.unwrap_or_else(|| variant_name.to_string());
+                let infos = field_infos(&variant.fields)?;
+                let construct = read_fields(quote!(#name::#variant_name), &variant.fields, &infos);
+                let arm = match variant.fields {
+                    // A unit variant may also be written as a bare symbol
+                    Fields::Unit => quote! {
+                        Some(#kind) => {
+                            if let ::gene::types::Value::Gene(_) = value {
+                                ::gene::convert::expect_gene(value, #kind)?;
+                            }
+                            Ok(#construct)
+                        }
+                    },
+                    _ => quote! {
+                        Some(#kind) => {
+                            let __gene_in = ::gene::convert::expect_gene(value, #kind)?;
+                            Ok(#construct)
+                        }
+                    },
+                };
+                arms.push(arm);
+                kinds.push(format!("({} ...)", kind));
+            }
+            let expected = format!("one of {}", kinds.join(", "));
+            quote! {
+                match ::gene::convert::kind_of(value) {
+                    #(#arms)*
+                    _ => Err(::gene::convert::ConvertError::mismatch(#expected, value)),
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new_spanned(input, "unions can not be converted from genes")),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::gene::convert::FromGene for #name #ty_generics #where_clause {
+            fn from_gene(value: &::gene::types::Value) -> Result<Self, ::gene::convert::ConvertError> {
+                #body
+            }
+        }
+    })
+}
@@ -0,0 +1,180 @@
+use std::fmt::Write;
+
+use super::parser::{is_sep, is_whitespace};
+use super::types::{Gene, Value};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Canonical text form of a value.
+///
+/// Two values that are equal produce the same text:
+/// * map keys and gene props are written in sorted order
+/// * floats always carry a fraction part, `-0.0` is written as `0.0` and every NaN as `NaN`
+/// * `"` and `\` inside strings are escaped, symbols and keys that the parser would
+///   read differently are escaped with `\`
+/// * a stream is written as `(#stream items...)`, so that a stream of one item
+///   is not written like the item. A symbol `#stream` is escaped, no other value
+///   is written like this.
+pub fn to_canonical(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value);
+    out
+}
+
+/// Gene text of a document: the canonical form, except that the values of a
+/// stream are written one per line, so that parsing the text gives the stream back
+pub fn to_document(value: &Value) -> String {
+    match value {
+        Value::Stream(items) => items.iter().map(to_canonical).collect::<Vec<_>>().join("\n"),
+        value => to_canonical(value),
+    }
+}
+
+/// Stable 64 bit hash (FNV-1a) of the canonical form.
+///
+/// Unlike `std::hash::Hash`, the result does not depend on the Rust version or the
+/// platform, so it can be persisted and used to deduplicate or cache documents.
+pub fn content_hash(value: &Value) -> u64 {
+    to_canonical(value).bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Void => out.push_str("()"),
+        Value::Null => out.push_str("null"),
+        Value::Boolean(true) => out.push_str("true"),
+        Value::Boolean(false) => out.push_str("false"),
+        Value::Integer(v) => {
+            let _ = write!(out, "{}", v);
+        }
+        Value::Float(v) => write_float(out, v.into_inner()),
+        Value::String(v) => write_string(out, v),
+        Value::Symbol(v) => write_symbol(out, v),
+        Value::Array(v) => {
+            out.push('[');
+            write_items(out, v.iter());
+            out.push(']');
+        }
+        Value::Map(v) => {
+            out.push('{');
+            let mut keys: Vec<&String> = v.keys().collect();
+            keys.sort();
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                write_pair(out, key, &v[key]);
+            }
+            out.push('}');
+        }
+        Value::Gene(v) => write_gene(out, v),
+        Value::Stream(v) => {
+            out.push_str("(#stream");
+            if !v.is_empty() {
+                out.push(' ');
+                write_items(out, v.iter());
+            }
+            out.push(')');
+        }
+    }
+}
+
+fn write_gene(out: &mut String, gene: &Gene) {
+    out.push('(');
+    write_value(out, &gene.kind);
+    let mut keys: Vec<&String> = gene.props.keys().collect();
+    keys.sort();
+    for key in keys {
+        out.push(' ');
+        write_pair(out, key, &gene.props[key]);
+    }
+    if !gene.data.is_empty() {
+        out.push(' ');
+        write_items(out, gene.data.iter());
+    }
+    out.push(')');
+}
+
+fn write_items<'a, I: Iterator<Item = &'a Value>>(out: &mut String, items: I) {
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        write_value(out, item);
+    }
+}
+
+fn write_pair(out: &mut String, key: &str, value: &Value) {
+    out.push('^');
+    let first_is_special = key.starts_with('^') || key.starts_with('!');
+    write_word(out, key, first_is_special);
+    out.push(' ');
+    write_value(out, value);
+}
+
+fn write_float(out: &mut String, v: f64) {
+    if v.is_nan() {
+        out.push_str("NaN");
+    } else if v.is_infinite() {
+        out.push_str(if v > 0. { "Inf" } else { "-Inf" });
+    } else if v == 0. {
+        out.push_str("0.0");
+    } else {
+        // Debug output is the shortest text that reads back to the same number,
+        // but it leaves out the fraction part for exponents, e.g. "1e300".
+        let s = format!("{:?}", v);
+        if s.contains('.') {
+            out.push_str(&s);
+        } else if let Some(index) = s.find('e') {
+            out.push_str(&s[..index]);
+            out.push_str(".0");
+            out.push_str(&s[index..]);
+        } else {
+            out.push_str(&s);
+            out.push_str(".0");
+        }
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for ch in s.chars() {
+        if ch == '"' || ch == '\\' {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+    out.push('"');
+}
+
+fn write_symbol(out: &mut String, s: &str) {
+    let first_is_special = match s {
+        "" => {
+            out.push('\\');
+            return;
+        }
+        "null" | "true" | "false" => true,
+        _ => {
+            let mut chars = s.chars();
+            let first = chars.next().unwrap();
+            match first {
+                '#' | '`' | '^' | '"' | '\'' => true,
+                '+' | '-' => chars.next().is_some_and(|ch| ch.is_ascii_digit()),
+                _ => first.is_ascii_digit(),
+            }
+        }
+    };
+    write_word(out, s, first_is_special);
+}
+
+fn write_word(out: &mut String, s: &str, first_is_special: bool) {
+    for (i, ch) in s.chars().enumerate() {
+        if ch == '\\' || is_whitespace(ch) || is_sep(ch) || (i == 0 && first_is_special) {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+}
@@ -4,6 +4,7 @@ use std::mem;
 use std::any::Any;
 use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
 use std::rc::Rc;
 
@@ -23,6 +24,7 @@ impl LiteralCheck for Value {
         match self {
             Value::Symbol(_s) => false,
             Value::Array(v) => v.is_literal(),
+            Value::Map(v) => v.is_literal(),
             Value::Gene(g) => g.is_literal(),
             _ => true
         }
@@ -191,7 +193,7 @@ impl Compiler {
                 self.reg_trackers.insert(body_id.clone(), Vec::new());
 
                 let borrowed = data[1].clone();
-                let matcher =  Matcher::from(&borrowed);
+                let matcher = Matcher::try_from(&borrowed).unwrap();
 
                 self.compile_statements(&mut body, &data[2..]);
                 body.add_instr(Instruction::CallEnd);
@@ -200,7 +202,8 @@ impl Compiler {
                 let mut module = self.module.borrow_mut();
                 module.add_block(body);
 
-                (*block).add_instr(Instruction::Function(name, matcher, body_id));
+                (*block).add_instr(Instruction::Function(name.clone(), matcher, body_id));
+                (*block).add_instr(Instruction::DefMember(name));
             }
             Value::Symbol(ref s) if s == "if" => {
                 self.compile_if(block, data);
@@ -292,8 +295,8 @@ impl Compiler {
         let end_index = block.instructions.len();
 
         let else_start = then_jump_index + 1;
-        mem::replace(&mut (*block).instructions[cond_jump_index], Instruction::JumpIfFalse(else_start as i16));
-        mem::replace(&mut (*block).instructions[then_jump_index], Instruction::Jump(end_index as i16));
+        block.instructions[cond_jump_index] = Instruction::JumpIfFalse(else_start as i16);
+        block.instructions[then_jump_index] = Instruction::Jump(end_index as i16);
     }
 
     fn compile_while(&mut self, block: &mut Block, mut data: Vec<Value>) {
@@ -313,7 +316,14 @@ impl Compiler {
         (*block).add_instr(Instruction::LoopEnd);
 
         let end_index = block.instructions.len();
-        mem::replace(&mut (*block).instructions[jump_index], Instruction::JumpIfFalse(end_index as i16));
+        block.instructions[jump_index] = Instruction::JumpIfFalse(end_index as i16);
+
+        // Breaks of nested loops are replaced already, the ones left are ours
+        for instr in block.instructions[start_index..end_index].iter_mut() {
+            if let Instruction::Break = instr {
+                *instr = Instruction::Jump(end_index as i16);
+            }
+        }
     }
 
     /// 1. find and return available register
@@ -323,13 +333,13 @@ impl Compiler {
         for i in 0..16 {
             let mut available = true;
             for tracker in trackers.iter() {
-                if *tracker == i as u16 {
+                if *tracker == i {
                     available = false;
                 }
             }
             if available {
                 trackers.push(i);
-                return i as u16;
+                return i;
             }
         }
         16 + random::<u16>()
@@ -343,12 +353,15 @@ impl Compiler {
     }
 }
 
-pub struct Statements(Vec<Value>);
+pub struct Statements(pub Vec<Value>);
 
 #[derive(Debug)]
 pub struct Module {
     pub id: String,
     pub blocks: HashMap<String, Rc<Block>>,
+    /// Names of the top level members used by the module, indexed by the ids
+    /// in DefGlobal / GetGlobal / SetGlobal
+    pub globals: Vec<String>,
     default_block_id: String,
 }
 
@@ -357,6 +370,7 @@ impl Module {
         Module {
             id: new_uuidv4(),
             blocks: HashMap::new(),
+            globals: Vec::new(),
             default_block_id: "".to_string(),
         }
     }
@@ -374,13 +388,48 @@ impl Module {
     pub fn add_block(&mut self, block: Block) {
         self.blocks.insert(block.id.clone(), Rc::new(block));
     }
+
+    /// The id of the top level member `name` in this module
+    pub fn global_id(&mut self, name: &str) -> usize {
+        match self.globals.iter().position(|global| global == name) {
+            Some(id) => id,
+            None => {
+                self.globals.push(name.to_string());
+                self.globals.len() - 1
+            }
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Block {
     pub id: String,
     pub name: String,
     pub instructions: Vec<Instruction>,
+    /// Names of the local variables of a function body, the slot of a
+    /// variable is its index
+    pub locals: Rc<Vec<String>>,
+    /// Exception handlers, inner ones first
+    pub handlers: Vec<Handler>,
+}
+
+/// An error raised by the instructions in `start..end` jumps to `target`,
+/// with the error in the default register
+#[derive(Clone, Debug, PartialEq)]
+pub struct Handler {
+    pub start: usize,
+    pub end: usize,
+    pub target: usize,
+}
+
+impl Handler {
+    pub fn new(start: usize, end: usize, target: usize) -> Self {
+        Handler {
+            start,
+            end,
+            target,
+        }
+    }
 }
 
 impl Block {
@@ -390,6 +439,8 @@ impl Block {
             id: new_uuidv4(),
             name,
             instructions,
+            locals: Rc::new(Vec::new()),
+            handlers: Vec::new(),
         }
     }
 
@@ -404,6 +455,11 @@ impl Block {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// The handler for an error raised at `pos`
+    pub fn handler_at(&self, pos: usize) -> Option<&Handler> {
+        self.handlers.iter().find(|handler| handler.start <= pos && pos < handler.end)
+    }
 }
 
 impl fmt::Display for Block {
@@ -412,16 +468,19 @@ impl fmt::Display for Block {
         fmt.write_str(&self.name)?;
         fmt.write_str("\n")?;
         for (i, instr) in self.instructions.iter().enumerate() {
-            fmt.write_str(&*format!("{: >5} ", i))?;
+            fmt.write_str(&format!("{: >5} ", i))?;
             fmt.write_str(&instr.to_string())?;
             fmt.write_str("\n")?;
         }
+        for handler in self.handlers.iter() {
+            fmt.write_str(&format!("      Handler {}..{} {}\n", handler.start, handler.end, handler.target))?;
+        }
         fmt.write_str(")")?;
         Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Instruction {
     Dummy,
     Init,
@@ -437,50 +496,126 @@ pub enum Instruction {
     GetMember(String),
     SetMember(String),
 
+    /// Variables resolved by the compiler, see `vm::types::Scope`
+    /// DefLocal(slot)
+    DefLocal(usize),
+    GetLocal(usize),
+    SetLocal(usize),
+    /// GetUpvalue(depth, slot)
+    /// A local variable of the function `depth` levels out
+    GetUpvalue(usize, usize),
+    SetUpvalue(usize, usize),
+    /// Top level members, see `vm::types::Namespace`
+    /// DefGlobal(id)
+    /// The id is an index into `Module::globals` until the module is loaded
+    DefGlobal(usize),
+    GetGlobal(usize),
+    SetGlobal(usize),
+
     /// GetItem(target reg, index)
+    /// Items of arrays and data of genes, the item is stored in default reg
     GetItem(u16, usize),
-    // /// GetItemDynamic(target reg, index reg)
-    // GetItemDynamic(String, String),
-    /// SetItem(target reg, index, value reg)
+    /// SetItem(target reg, index)
+    /// The value is in default reg
     SetItem(u16, usize),
-    // /// SetItemDynamic(target reg, index reg, value reg)
-    // SetItemDynamic(String, String, String),
-
-    // /// GetProp(target reg, name)
-    // GetProp(String, String),
-    // /// GetPropDynamic(target reg, name reg)
-    // GetPropDynamic(String, String),
-    /// SetProp(target reg, name, value reg)
+
+    /// GetProp(target reg, name)
+    /// Props of maps and genes, the prop is stored in default reg
+    GetProp(u16, String),
+    /// SetProp(target reg, name)
+    /// The value is in default reg
     SetProp(u16, String),
-    // /// SetPropDynamic(target reg, name reg, value reg)
-    // SetPropDynamic(String, String, String),
+    /// SetKind(target reg)
+    /// Set the kind of a gene to the value in default reg
+    SetKind(u16),
+
+    /// GetDynamic(target reg, key reg)
+    /// Like GetItem for an integer key, negative ones count from the end,
+    /// and like GetProp for a string or symbol key
+    GetDynamic(u16, u16),
+    /// SetDynamic(target reg, key reg)
+    /// The value is in default reg
+    SetDynamic(u16, u16),
 
     Jump(i16),
+    /// Jump if the default register is falsy, see `Value::is_truthy`
     JumpIfFalse(i16),
+    JumpIfTrue(i16),
     /// Below are pseudo instructions that should be replaced with other jump instructions
     /// before sent to the VM to execute.
     JumpToElse,
     JumpToNextStatement,
 
+    /// Replaced with a jump to the end of the loop by the compiler
     Break,
     LoopStart,
     LoopEnd,
 
+    /// Check(reg, shape)
+    /// Store whether the value in reg has the shape in default reg
+    Check(u16, Shape),
+    /// GetKind(target reg)
+    /// The kind of a gene is stored in default reg
+    GetKind(u16),
+    /// Rest(target reg, start)
+    /// The items or gene data from `start` on are stored in default reg as an array
+    Rest(u16, usize),
+    /// NoMatch(reg)
+    /// Raise an error for the value in reg that no `match` pattern matches
+    NoMatch(u16),
+
+    /// Range(start reg, end reg)
+    /// The step is in default reg, the range is stored in default reg
+    Range(u16, u16),
+    /// Iter(reg)
+    /// Store an iterator over the value in default reg, see `vm::value::Iter`
+    Iter(u16),
+    /// PropsIter(reg)
+    /// Like Iter, over the props of a gene or map
+    PropsIter(u16),
+    /// Next(iterator reg, end pos)
+    /// Put the next item in default reg, or jump to the end when there is none
+    Next(u16, i16),
+
     /// BinaryOp(op, first reg)
     /// Second operand is in default reg
     /// Result is stored in default reg
     BinaryOp(String, u16),
+    /// Replace the default register with whether it is falsy
+    Not,
 
-    /// Function(name, args reg, block id)
+    /// Function(name, args, block id)
+    /// Create a function in default reg that closes over the current scope
     Function(String, Matcher, String),
     /// Create an argument object and store in a register
     CreateArguments(u16),
 
     /// Call(options)
     Call(u16, Option<u16>, HashMap<String, Rc<dyn Any>>),
+    /// Raise the value in default reg, an error from `Catch` is raised again as it is
+    Throw,
+    /// Replace the error in default reg with the value `catch` binds,
+    /// see `RuntimeError::to_value`
+    Catch,
+
+    /// TailCall(target reg, args reg)
+    /// A call whose result is returned right away, it reuses the frame of the caller
+    TailCall(u16, Option<u16>),
     CallEnd,
 }
 
+/// What `Instruction::Check` tests, used by `match`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    /// Array(length, whether more items are allowed)
+    Array(usize, bool),
+    Map,
+    /// Gene(data length, whether more data is allowed)
+    Gene(usize, bool),
+    /// A map or gene that has the prop
+    HasProp(String),
+}
+
 impl fmt::Display for Instruction {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.write_str("(")?;
@@ -521,6 +656,30 @@ impl fmt::Display for Instruction {
                 fmt.write_str("SetMember ")?;
                 fmt.write_str(name)?;
             }
+            Instruction::DefLocal(slot) => {
+                write!(fmt, "DefLocal {}", slot)?;
+            }
+            Instruction::GetLocal(slot) => {
+                write!(fmt, "GetLocal {}", slot)?;
+            }
+            Instruction::SetLocal(slot) => {
+                write!(fmt, "SetLocal {}", slot)?;
+            }
+            Instruction::GetUpvalue(depth, slot) => {
+                write!(fmt, "GetUpvalue {} {}", depth, slot)?;
+            }
+            Instruction::SetUpvalue(depth, slot) => {
+                write!(fmt, "SetUpvalue {} {}", depth, slot)?;
+            }
+            Instruction::DefGlobal(id) => {
+                write!(fmt, "DefGlobal {}", id)?;
+            }
+            Instruction::GetGlobal(id) => {
+                write!(fmt, "GetGlobal {}", id)?;
+            }
+            Instruction::SetGlobal(id) => {
+                write!(fmt, "SetGlobal {}", id)?;
+            }
             Instruction::GetItem(reg, index) => {
                 fmt.write_str("GetItem ")?;
                 fmt.write_str(&reg.to_string())?;
@@ -533,11 +692,20 @@ impl fmt::Display for Instruction {
                 fmt.write_str(" ")?;
                 fmt.write_str(&index.to_string())?;
             }
+            Instruction::GetProp(reg, key) => {
+                write!(fmt, "GetProp {} {}", reg, key)?;
+            }
             Instruction::SetProp(reg, key) => {
-                fmt.write_str("Get ")?;
-                fmt.write_str(&reg.to_string())?;
-                fmt.write_str(" ")?;
-                fmt.write_str(key)?;
+                write!(fmt, "SetProp {} {}", reg, key)?;
+            }
+            Instruction::SetKind(reg) => {
+                write!(fmt, "SetKind {}", reg)?;
+            }
+            Instruction::GetDynamic(reg, key_reg) => {
+                write!(fmt, "GetDynamic {} {}", reg, key_reg)?;
+            }
+            Instruction::SetDynamic(reg, key_reg) => {
+                write!(fmt, "SetDynamic {} {}", reg, key_reg)?;
             }
             Instruction::Jump(pos) => {
                 fmt.write_str("Jump ")?;
@@ -547,6 +715,10 @@ impl fmt::Display for Instruction {
                 fmt.write_str("JumpIfFalse ")?;
                 fmt.write_str(&pos.to_string())?;
             }
+            Instruction::JumpIfTrue(pos) => {
+                fmt.write_str("JumpIfTrue ")?;
+                fmt.write_str(&pos.to_string())?;
+            }
             Instruction::Break => {
                 fmt.write_str("Break")?;
             }
@@ -556,12 +728,39 @@ impl fmt::Display for Instruction {
             Instruction::LoopEnd => {
                 fmt.write_str("LoopEnd")?;
             }
+            Instruction::Check(reg, shape) => {
+                write!(fmt, "Check {} {:?}", reg, shape)?;
+            }
+            Instruction::GetKind(reg) => {
+                write!(fmt, "GetKind {}", reg)?;
+            }
+            Instruction::Rest(reg, start) => {
+                write!(fmt, "Rest {} {}", reg, start)?;
+            }
+            Instruction::NoMatch(reg) => {
+                write!(fmt, "NoMatch {}", reg)?;
+            }
+            Instruction::Range(start_reg, end_reg) => {
+                write!(fmt, "Range {} {}", start_reg, end_reg)?;
+            }
+            Instruction::Iter(reg) => {
+                write!(fmt, "Iter {}", reg)?;
+            }
+            Instruction::PropsIter(reg) => {
+                write!(fmt, "PropsIter {}", reg)?;
+            }
+            Instruction::Next(reg, pos) => {
+                write!(fmt, "Next {} {}", reg, pos)?;
+            }
             Instruction::BinaryOp(op, first) => {
                 fmt.write_str(&first.to_string())?;
                 fmt.write_str(" ")?;
                 fmt.write_str(op)?;
                 fmt.write_str(" Default")?;
             }
+            Instruction::Not => {
+                fmt.write_str("Not")?;
+            }
             Instruction::Function(name, _matcher, body_id) => {
                 fmt.write_str("Function ")?;
                 fmt.write_str(name)?;
@@ -571,6 +770,7 @@ impl fmt::Display for Instruction {
                 // fmt.write_str(" ")?;
                 fmt.write_str(body_id)?;
             }
+
             Instruction::Call(target_reg, args_reg, _options) => {
                 fmt.write_str("Call ")?;
                 fmt.write_str(&target_reg.to_string())?;
@@ -579,6 +779,20 @@ impl fmt::Display for Instruction {
                     fmt.write_str(&reg.to_string())?;
                 }
             }
+            Instruction::Throw => {
+                fmt.write_str("Throw")?;
+            }
+            Instruction::Catch => {
+                fmt.write_str("Catch")?;
+            }
+            Instruction::TailCall(target_reg, args_reg) => {
+                fmt.write_str("TailCall ")?;
+                fmt.write_str(&target_reg.to_string())?;
+                fmt.write_str(" ")?;
+                if let Some(reg) = args_reg {
+                    fmt.write_str(&reg.to_string())?;
+                }
+            }
             Instruction::CallEnd => {
                 fmt.write_str("CallEnd")?;
             }
@@ -595,8 +809,13 @@ impl fmt::Display for Instruction {
     }
 }
 
+/// `&&` and `||` are written like binary operators but only evaluate the second operand when needed
+pub fn is_logical_op(op: &str) -> bool {
+    op == "&&" || op == "||"
+}
+
 pub fn is_binary_op(op: &str) -> bool {
-    let binary_ops = vec!["+", "-", "*", "/", "<", "<=", ">", ">=", "=="];
+    let binary_ops = vec!["+", "-", "*", "/", "%", "**", "<", "<=", ">", ">=", "==", "!="];
     binary_ops.contains(&op)
 }
 
@@ -4,44 +4,152 @@ use std::any::Any;
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::mem;
 
 use rand::prelude::random;
 
-use ego_tree::{Tree, NodeRef, NodeMut};
+use ego_tree::{Tree, NodeId, NodeRef, NodeMut};
 use ordered_float::OrderedFloat;
 
 use super::types::{Value, Gene};
-use super::vm::types::{Function, Matcher};
-use super::compiler::{Module, Block, Instruction, LiteralCheck, is_binary_op};
+pub use super::parser::{SourceMap, Span};
+use super::vm::types::{DataMatcher, Function, Matcher, Pattern, PropMatcher};
+use super::vm::value::RuntimeValue;
+use super::compiler::{Module, Block, Handler, Instruction, LiteralCheck, Shape, is_binary_op, is_logical_op};
 
 pub struct Compiler {
     pub module: Module,
     reg_trackers: HashMap<String, Vec<u16>>,
+    errors: Vec<CompileError>,
+    /// Local variable names of the functions being compiled, innermost last
+    scopes: Vec<Vec<String>>,
+    /// Labels of the loops being translated in the current function, innermost last
+    loops: Vec<Option<String>>,
+    /// Loops and finally sections around the code being compiled, innermost last
+    exits: Vec<Exit>,
 }
 
+/// What `break`, `continue` and `return` jump out of
+enum Exit {
+    Loop {
+        label: Option<String>,
+        start: usize,
+        /// Jumps to patch with the end of the loop
+        breaks: Vec<usize>,
+    },
+    /// Finally(statements, gaps): the finally section of a `try` and the
+    /// copies of it made for jumps, which its handlers must not cover
+    Finally(NodeId, Vec<(usize, usize)>),
+}
+
+/// Where a variable lives, decided at compile time
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Variable {
+    Local(usize),
+    /// Upvalue(depth, slot): a local of an enclosing function
+    Upvalue(usize, usize),
+    Global(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompileError {
+    pub message: String,
+    /// The form that could not be compiled
+    pub form: Value,
+    /// Where `form` is in the source, set by `Compiler::compile_with_spans`
+    pub span: Option<Span>,
+}
+
+impl CompileError {
+    pub fn new<S: Into<String>>(message: S, form: Value) -> Self {
+        CompileError {
+            message: message.into(),
+            form,
+            span: None,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}: {}", self.message, self.form.to_canonical())?;
+        if let Some(span) = self.span {
+            write!(fmt, " at {}..{}", span.start, span.end)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CompileError {}
+
 impl Compiler {
     pub fn new() -> Self {
         Compiler {
             module: Module::new(),
             reg_trackers: HashMap::new(),
+            errors: Vec::new(),
+            scopes: Vec::new(),
+            loops: Vec::new(),
+            exits: Vec::new(),
         }
     }
 
-    pub fn compile(&mut self, value: Value) {
+    /// Compile `value` into the default block of `self.module`.
+    /// Invalid forms don't stop the compilation, all of them are reported together.
+    pub fn compile(&mut self, value: Value) -> Result<(), Vec<CompileError>> {
+        self.compile_value(&value)
+    }
+
+    /// Same as `compile`, the errors get the spans of their forms from `spans`,
+    /// which `value` was parsed with
+    pub fn compile_with_spans(&mut self, value: Value, spans: &SourceMap) -> Result<(), Vec<CompileError>> {
+        self.compile_value(&value).map_err(|mut errors| {
+            locate(&mut errors, &value, spans);
+            errors
+        })
+    }
+
+    fn compile_value(&mut self, value: &Value) -> Result<(), Vec<CompileError>> {
         let mut tree = Tree::new(Compilable::new(CompilableData::Block));
-        self.translate(&mut tree.root_mut(), &value);
-        let block = self.compile_tree(&tree, "__default__".to_string(), true);
+        self.translate(&mut tree.root_mut(), value);
+        if !self.errors.is_empty() {
+            return Err(mem::take(&mut self.errors));
+        }
+        let block = self.compile_tree(&tree, "__default__".to_string(), true).map_err(|e| vec![e])?;
         self.module.set_default_block(block);
+        Ok(())
+    }
+
+    fn error<S: Into<String>>(&mut self, message: S, form: &Value) {
+        self.errors.push(CompileError::new(message, form.clone()));
     }
 
+    /// Translate `value` into nodes under `parent`. The node made for a gene,
+    /// array or map keeps the form, for errors found while compiling the node.
     fn translate(&mut self, parent: &mut NodeMut<Compilable>, value: &Value) {
+        let last = parent.last_child().map(|node| node.id());
+        self.translate_value(parent, value);
+        if let Value::Gene(_) | Value::Array(_) | Value::Map(_) = value {
+            if let Some(mut node) = parent.last_child() {
+                if Some(node.id()) != last && node.value().form.is_none() {
+                    node.value().form = Some(value.clone());
+                }
+            }
+        }
+    }
+
+    fn translate_value(&mut self, parent: &mut NodeMut<Compilable>, value: &Value) {
         match value {
             Value::Stream(v) => {
                 for item in v {
-                    self.translate(parent, &item);
+                    self.translate(parent, item);
                 }
             }
+            Value::Void => {
+                parent.append(Compilable::new(CompilableData::Void));
+            }
             Value::Null => {
                 parent.append(Compilable::new(CompilableData::Null));
             }
@@ -96,48 +204,91 @@ impl Compiler {
                 }
             }
             Value::Gene(box v) => {
-                let Gene{ kind, data, .. } = v.normalize();
+                // Before normalizing, `+ is quoted as it is
+                if v.kind == Value::Symbol("#QUOTE".to_string()) {
+                    if v.data.len() != 1 || !v.props.is_empty() {
+                        return self.error("expected `value", value);
+                    }
+                    return self.translate_quoted(parent, &v.data[0]);
+                }
+                let Gene{ kind, props, data } = v.normalize();
                 match kind {
                     Value::Symbol(ref s) if is_binary_op(s) => {
+                        if data.len() != 2 {
+                            return self.error(format!("{} expects two operands", s), value);
+                        }
                         let mut node = parent.append(Compilable::new(CompilableData::BinaryOp(s.clone())));
                         self.translate(&mut node, &data[0]);
                         self.translate(&mut node, &data[1]);
                     }
+                    Value::Symbol(ref s) if is_logical_op(s) => {
+                        if data.len() != 2 {
+                            return self.error(format!("{} expects two operands", s), value);
+                        }
+                        let mut node = parent.append(Compilable::new(CompilableData::LogicalOp(s.clone())));
+                        self.translate(&mut node, &data[0]);
+                        self.translate(&mut node, &data[1]);
+                    }
+                    Value::Symbol(ref s) if s == "!" || s == "not" => {
+                        if data.len() != 1 {
+                            return self.error(format!("expected ({} value)", s), value);
+                        }
+                        let mut node = parent.append(Compilable::new(CompilableData::Not));
+                        self.translate(&mut node, &data[0]);
+                    }
                     Value::Symbol(ref s) if s == "=" => {
-                        if let Value::Symbol(name) = &data[0] {
-                            let mut node = parent.append(Compilable::new(CompilableData::Assignment(name.clone())));
-                            self.translate(&mut node, &data[1]);
-                        } else {
-                            unimplemented!();
+                        match data.as_slice() {
+                            [Value::Symbol(name), value] => {
+                                let mut node = parent.append(Compilable::new(CompilableData::Assignment(name.clone())));
+                                self.translate(&mut node, value);
+                            }
+                            [Value::Gene(box target), new_value] if is_access(&target.data) => {
+                                let mut node = parent.append(Compilable::new(CompilableData::AccessAssignment));
+                                self.translate_access(&mut node, &data[0], target);
+                                self.translate(&mut node, new_value);
+                            }
+                            _ => self.error("expected (name = value)", value),
                         }
                     }
                     Value::Symbol(ref s) if s == "var" => {
-                        if let Value::Symbol(name) = &data[0] {
-                            let mut node = parent.append(Compilable::new(CompilableData::Var(name.clone())));
-                            let value = data[1].clone();
-                            self.translate(&mut node, &value);
+                        match data.as_slice() {
+                            [Value::Symbol(name), value] => {
+                                let mut node = parent.append(Compilable::new(CompilableData::Var(name.clone())));
+                                self.translate(&mut node, value);
+                            }
+                            _ => self.error("expected (var name value)", value),
                         }
                     }
                     Value::Symbol(ref s) if s == "fn" => {
-                        let name = data[0].to_string();
-
-                        let borrowed = data[1].clone();
-                        let matcher =  Matcher::from(&borrowed);
-
-                        let mut tree = Tree::new(Compilable::new(CompilableData::Block));
-                        let mut stmts = Vec::new();
-                        for item in data.iter().skip(2) {
-                            stmts.push(item.clone());
+                        let name = match data.first() {
+                            Some(Value::Symbol(name)) if data.len() >= 2 => name.clone(),
+                            _ => return self.error("expected (fn name args body...)", value),
+                        };
+                        let mut matcher = match Matcher::try_from(&data[1]) {
+                            Ok(matcher) => matcher,
+                            Err(message) => return self.error(message, value),
+                        };
+                        if let Some(body_id) = self.translate_body(name.clone(), &mut matcher, &data[2..]) {
+                            parent.append(Compilable::new(CompilableData::Function(name, matcher, body_id)));
+                        }
+                    }
+                    Value::Symbol(ref s) if s == "fnx" => {
+                        if data.is_empty() {
+                            return self.error("expected (fnx args body...)", value);
+                        }
+                        let mut matcher = match Matcher::try_from(&data[0]) {
+                            Ok(matcher) => matcher,
+                            Err(message) => return self.error(message, value),
+                        };
+                        if let Some(body_id) = self.translate_body("fnx".to_string(), &mut matcher, &data[1..]) {
+                            parent.append(Compilable::new(CompilableData::Lambda(matcher, body_id)));
                         }
-                        self.translate(&mut tree.root_mut(), &Value::Stream(stmts));
-                        let body = self.compile_tree(&tree, name.clone(), false);
-                        let body_id = body.id.clone();
-                        self.module.add_block(body);
-
-                        parent.append(Compilable::new(CompilableData::Function(name, matcher, body_id)));
                     }
                     Value::Symbol(ref s) if s == "if" => {
-                        let cond = &data[0];
+                        let cond = match data.first() {
+                            Some(cond) => cond,
+                            None => return self.error("expected (if condition body... else body...)", value),
+                        };
                         let mut then_stmts = Vec::new();
                         let mut else_stmts = Vec::new();
                         let mut is_else = false;
@@ -179,91 +330,401 @@ impl Compiler {
                             }
                         }
                     }
-                    Value::Symbol(ref s) if s == "break" => {
-                        parent.append(Compilable::new(CompilableData::Break));
+                    Value::Symbol(ref s) if s == "try" => {
+                        self.translate_try(parent, value, &data);
+                    }
+                    Value::Symbol(ref s) if s == "throw" => {
+                        if data.len() != 1 {
+                            return self.error("expected (throw value)", value);
+                        }
+                        let mut node = parent.append(Compilable::new(CompilableData::Throw));
+                        self.translate(&mut node, &data[0]);
+                    }
+                    Value::Symbol(ref s) if s == "break" || s == "continue" => {
+                        let label = match data.as_slice() {
+                            [] => None,
+                            [Value::Symbol(label)] => Some(label.clone()),
+                            _ => return self.error(format!("expected ({} label)", s), value),
+                        };
+                        match &label {
+                            None if self.loops.is_empty() => {
+                                return self.error(format!("{} outside of a loop", s), value);
+                            }
+                            Some(label) if !self.loops.iter().any(|l| l.as_ref() == Some(label)) => {
+                                return self.error(format!("no loop labeled {}", label), value);
+                            }
+                            _ => (),
+                        }
+                        let data = if s == "break" { CompilableData::Break(label) } else { CompilableData::Continue(label) };
+                        parent.append(Compilable::new(data));
+                    }
+                    Value::Symbol(ref s) if s == "return" => {
+                        if data.len() > 1 {
+                            return self.error("expected (return value)", value);
+                        }
+                        if self.scopes.is_empty() {
+                            return self.error("return outside of a function", value);
+                        }
+                        let mut node = parent.append(Compilable::new(CompilableData::Return));
+                        self.translate(&mut node, data.first().unwrap_or(&Value::Void));
                     }
                     Value::Symbol(ref s) if s == "while" => {
-                        let mut node = parent.append(Compilable::new(CompilableData::While));
+                        let label = match props.get("label") {
+                            None => None,
+                            Some(Value::Symbol(label)) => Some(label.clone()),
+                            Some(_) => return self.error("expected (while ^label name condition body...)", value),
+                        };
+                        if data.is_empty() {
+                            return self.error("expected (while condition body...)", value);
+                        }
+                        let mut node = parent.append(Compilable::new(CompilableData::While(label.clone())));
+                        self.loops.push(label);
                         for stmt in data {
                             self.translate(&mut node, &stmt);
                         }
+                        self.loops.pop();
+                    }
+                    Value::Symbol(ref s) if s == "for" => {
+                        let label = match props.get("label") {
+                            None => None,
+                            Some(Value::Symbol(label)) => Some(label.clone()),
+                            Some(_) => return self.error("expected (for ^label name pattern in collection body...)", value),
+                        };
+                        let (pattern, collection, body) = match data.as_slice() {
+                            [pattern, Value::Symbol(keyword), collection, body @ ..] if keyword == "in" && is_loop_pattern(pattern) => {
+                                (pattern, collection, body)
+                            }
+                            _ => return self.error("expected (for pattern in collection body...)", value),
+                        };
+                        let iterate_props = props.get("props") == Some(&Value::Boolean(true));
+                        let mut node = parent.append(Compilable::new(CompilableData::For(label.clone(), iterate_props, pattern.clone())));
+                        self.translate(&mut node, collection);
+                        self.loops.push(label);
+                        for stmt in body {
+                            self.translate(&mut node, stmt);
+                        }
+                        self.loops.pop();
+                    }
+                    Value::Symbol(ref s) if s == "match" => {
+                        self.translate_match(parent, value, &data);
+                    }
+                    Value::Symbol(ref s) if s == "gene" => {
+                        match data.split_first() {
+                            Some((kind, data)) => self.translate_gene(parent, kind, &props, data, false),
+                            None => self.error("expected (gene kind ^prop value... data...)", value),
+                        }
+                    }
+                    Value::Symbol(ref s) if s == "range" => {
+                        if data.len() != 2 && data.len() != 3 {
+                            return self.error("expected (range start end step)", value);
+                        }
+                        let mut node = parent.append(Compilable::new(CompilableData::Range));
+                        for item in data.iter() {
+                            self.translate(&mut node, item);
+                        }
+                    }
+                    _ if is_access(&data) => {
+                        self.translate_access(parent, value, v);
                     }
                     Value::Symbol(s) => {
                         let mut node = parent.append(Compilable::new(CompilableData::Invocation));
                         node.append(Compilable::new(CompilableData::Symbol(s.to_string())));
-
-                        // if data.len() == 0 {
-                        //     // TODO: optimization
-                        //     node.append(Compilable::new(CompilableData::InvocationArguments(data.clone())));
-                        // } else if data.is_literal() {
-                        //     node.append(Compilable::new(CompilableData::InvocationArguments(data.clone())));
-                        // } else {
-                            let mut new_arr = Vec::new();
-                            // add literal values to new_arr
-                            for (i, item) in data.iter().enumerate() {
-                                if item.is_literal() {
-                                    new_arr.insert(i, item.clone());
-                                } else {
-                                    new_arr.insert(i, Value::Void);
-                                }
-                            }
-                            let mut node = node.append(Compilable::new(CompilableData::InvocationArguments(new_arr)));
-                            // compile non-literal items
-                            for (i, item) in data.iter().enumerate() {
-                                // if !item.is_literal() {
-                                    let mut node2 = node.append(Compilable::new(CompilableData::ArrayChild(i)));
-                                    self.translate(&mut node2, item);
-                                // }
-                            }
-                        // }
+                        self.translate_arguments(&mut node, &props, &data);
                     }
                     _ => {
                         let mut node = parent.append(Compilable::new(CompilableData::Invocation));
                         self.translate(&mut node, &kind);
+                        self.translate_arguments(&mut node, &props, &data);
+                    }
+                }
+            }
+        }
+    }
 
-                        // if data.len() == 0 {
-                        //     // TODO: optimization
-                        //     node.append(Compilable::new(CompilableData::InvocationArguments(data.clone())));
-                        // } else if data.is_literal() {
-                        //     node.append(Compilable::new(CompilableData::InvocationArguments(data.clone())));
-                        // } else {
-                            let mut new_arr = Vec::new();
-                            // add literal values to new_arr
-                            for (i, item) in data.iter().enumerate() {
-                                if item.is_literal() {
-                                    new_arr.insert(i, item.clone());
-                                } else {
-                                    new_arr.insert(i, Value::Void);
-                                }
-                            }
-                            let mut node = node.append(Compilable::new(CompilableData::InvocationArguments(new_arr)));
-                            // compile non-literal items
-                            for (i, item) in data.iter().enumerate() {
-                                // if !item.is_literal() {
-                                    let mut node2 = node.append(Compilable::new(CompilableData::ArrayChild(i)));
-                                    self.translate(&mut node2, item);
-                                // }
-                            }
-                        // }
+    /// Compile a function body into its own block, returns the block id.
+    /// The arguments and the variables and functions defined anywhere in the body
+    /// are local to the function, so closures created before a `var` see it too.
+    fn translate_body(&mut self, name: String, matcher: &mut Matcher, stmts: &[Value]) -> Option<String> {
+        let mut locals = Vec::new();
+        for arg in matcher.names() {
+            add_local(&mut locals, &arg);
+        }
+        for stmt in stmts.iter() {
+            collect_locals(stmt, &mut locals);
+        }
+        matcher.resolve(&mut |arg| locals.iter().position(|local| local == arg).unwrap());
+
+        self.scopes.push(locals);
+        let loops = mem::take(&mut self.loops);
+        let mut tree = Tree::new(Compilable::new(CompilableData::Block));
+        self.translate(&mut tree.root_mut(), &Value::Stream(stmts.to_vec()));
+        self.loops = loops;
+        let result = self.compile_tree(&tree, name, false);
+        let locals = self.scopes.pop().unwrap();
+
+        match result {
+            Ok(mut body) => {
+                mark_tail_calls(&mut body);
+                body.locals = Rc::new(locals);
+                let body_id = body.id.clone();
+                self.module.add_block(body);
+                Some(body_id)
+            }
+            Err(e) => {
+                self.errors.push(e);
+                None
+            }
+        }
+    }
+
+    /// (try body... catch name body... finally body...), catch or finally can be left out
+    fn translate_try(&mut self, parent: &mut NodeMut<Compilable>, value: &Value, data: &[Value]) {
+        const USAGE: &str = "expected (try body... catch name body... finally body...)";
+        let mut body = Vec::new();
+        let mut catch = None;
+        let mut finally = None;
+        let mut iter = data.iter();
+        while let Some(item) = iter.next() {
+            match item {
+                Value::Symbol(s) if s == "catch" => {
+                    if catch.is_some() || finally.is_some() {
+                        return self.error(USAGE, value);
+                    }
+                    match iter.next() {
+                        Some(Value::Symbol(name)) => catch = Some((name.clone(), Vec::new())),
+                        _ => return self.error(USAGE, value),
+                    }
+                }
+                Value::Symbol(s) if s == "finally" => {
+                    if finally.is_some() {
+                        return self.error(USAGE, value);
+                    }
+                    finally = Some(Vec::new());
+                }
+                _ => match (&mut catch, &mut finally) {
+                    (_, Some(stmts)) | (Some((_, stmts)), None) => stmts.push(item.clone()),
+                    (None, None) => body.push(item.clone()),
+                },
+            }
+        }
+        if catch.is_none() && finally.is_none() {
+            return self.error(USAGE, value);
+        }
+
+        let mut node = parent.append(Compilable::new(CompilableData::Try(
+            catch.as_ref().map(|(name, _)| name.clone()),
+            finally.is_some(),
+        )));
+        let mut sections = vec![body];
+        sections.extend(catch.map(|(_, stmts)| stmts));
+        sections.extend(finally);
+        for stmts in sections {
+            let mut stmts_node = node.append(Compilable::new(CompilableData::Statements));
+            if stmts.is_empty() {
+                stmts_node.append(Compilable::new(CompilableData::Void));
+            }
+            for stmt in stmts.iter() {
+                self.translate(&mut stmts_node, stmt);
+            }
+        }
+    }
+
+    /// (match value (pattern body...) (pattern if guard body...)...)
+    fn translate_match(&mut self, parent: &mut NodeMut<Compilable>, value: &Value, data: &[Value]) {
+        const USAGE: &str = "expected (match value (pattern body...)...)";
+        let (subject, clauses) = match data.split_first() {
+            Some((subject, clauses)) if !clauses.is_empty() => (subject, clauses),
+            _ => return self.error(USAGE, value),
+        };
+        let mut node = parent.append(Compilable::new(CompilableData::Match));
+        self.translate(&mut node, subject);
+        for clause in clauses {
+            let (form, rest) = match clause {
+                Value::Gene(box gene) if gene.props.is_empty() => (&gene.kind, &gene.data[..]),
+                _ => return self.error(USAGE, value),
+            };
+            let pattern = match Pattern::try_from(form) {
+                Ok(pattern) if has_default(&pattern) => return self.error("match patterns can't have default values", form),
+                Ok(pattern) => pattern,
+                Err(message) => return self.error(message, form),
+            };
+            let (guard, body) = match rest {
+                [Value::Symbol(s), guard, body @ ..] if s == "if" => (Some(guard), body),
+                [Value::Symbol(s)] if s == "if" => return self.error(USAGE, value),
+                _ => (None, rest),
+            };
+            let mut clause_node = node.append(Compilable::new(CompilableData::MatchClause(pattern, guard.is_some())));
+            if let Some(guard) = guard {
+                self.translate(&mut clause_node, guard);
+            }
+            let mut body_node = clause_node.append(Compilable::new(CompilableData::Statements));
+            if body.is_empty() {
+                body_node.append(Compilable::new(CompilableData::Void));
+            }
+            for stmt in body {
+                self.translate(&mut body_node, stmt);
+            }
+        }
+    }
+
+    /// A quoted value is built as it is written, except `%name` and `(% expr)`
+    /// which are replaced with the value of the variable or expression
+    fn translate_quoted(&mut self, parent: &mut NodeMut<Compilable>, value: &Value) {
+        if let Some(literal) = quoted_literal(value) {
+            parent.append(Compilable::new(CompilableData::Literal(literal)));
+            return;
+        }
+        match value {
+            Value::Symbol(s) => self.translate(parent, &Value::Symbol(s[1..].to_string())),
+            Value::Gene(box gene) if is_unquote(gene) => self.translate(parent, &gene.data[0]),
+            Value::Gene(box gene) => self.translate_gene(parent, &gene.kind, &gene.props, &gene.data, true),
+            Value::Array(items) => {
+                let literals = items.iter().map(|item| quoted_literal(item).unwrap_or(Value::Void)).collect();
+                let mut node = parent.append(Compilable::new(CompilableData::Array(literals)));
+                for (i, item) in items.iter().enumerate() {
+                    if quoted_literal(item).is_none() {
+                        let mut item_node = node.append(Compilable::new(CompilableData::ArrayChild(i)));
+                        self.translate_quoted(&mut item_node, item);
+                    }
+                }
+            }
+            Value::Map(map) => {
+                let literals = map.iter().filter_map(|(key, v)| quoted_literal(v).map(|v| (key.clone(), v))).collect();
+                let mut node = parent.append(Compilable::new(CompilableData::Map(literals)));
+                for (key, v) in sorted(map) {
+                    if quoted_literal(v).is_none() {
+                        let mut value_node = node.append(Compilable::new(CompilableData::MapChild(key.clone())));
+                        self.translate_quoted(&mut value_node, v);
                     }
                 }
-                // TODO: create Gene with literals then compile non-literal kind/prop/data
-                // let mut node = parent.append(Compilable::new(CompilableData::Gene));
-                // let mut kind_node = node.append(Compilable::new(CompilableData::GeneKind(GeneKind::Other)));
-                // self.compile_(&mut kind_node, v.kind);
-                // for (key, value) in v.props.iter() {
-                //     let mut node2 = node.append(Compilable::new(CompilableData::MapChild(key.to_string())));
-                //     self.compile_(&mut node2, value);
-                // }
-                // for item in v.data.iter() {
-                //     self.compile_(&mut node, item);
-                // }
             }
-            _ => unimplemented!()
+            _ => self.error("unexpected quoted value", value),
+        }
+    }
+
+    /// Build a gene, the literal parts go into the gene created first, the others
+    /// are set on it. Parts of a quoted gene are quoted too.
+    fn translate_gene(&mut self, parent: &mut NodeMut<Compilable>, kind: &Value, props: &HashMap<String, Value>, data: &[Value], quoted: bool) {
+        let literal = |value: &Value| {
+            if quoted {
+                quoted_literal(value)
+            } else {
+                Some(value.clone()).filter(|value| value.is_literal())
+            }
+        };
+        let gene = Gene {
+            kind: literal(kind).unwrap_or(Value::Void),
+            props: props.iter().filter_map(|(key, v)| literal(v).map(|v| (key.clone(), v))).collect(),
+            data: data.iter().map(|item| literal(item).unwrap_or(Value::Void)).collect(),
+        };
+        let mut node = parent.append(Compilable::new(CompilableData::Gene(gene)));
+
+        let mut parts = Vec::new();
+        if literal(kind).is_none() {
+            parts.push((CompilableData::GeneKind, kind));
+        }
+        for (key, v) in sorted(props) {
+            if literal(v).is_none() {
+                parts.push((CompilableData::GeneProp(key.clone()), v));
+            }
+        }
+        for (i, item) in data.iter().enumerate() {
+            if literal(item).is_none() {
+                parts.push((CompilableData::GeneDataChild(i), item));
+            }
+        }
+        for (part, v) in parts {
+            let mut part_node = node.append(Compilable::new(part));
+            if quoted {
+                self.translate_quoted(&mut part_node, v);
+            } else {
+                self.translate(&mut part_node, v);
+            }
+        }
+    }
+
+    /// (target .name .0 @ key ...), see `is_access`
+    fn translate_access(&mut self, parent: &mut NodeMut<Compilable>, value: &Value, gene: &Gene) {
+        const USAGE: &str = "expected (target .name .index @ key ...)";
+        if !gene.props.is_empty() {
+            return self.error(USAGE, value);
+        }
+        let mut node = parent.append(Compilable::new(CompilableData::Access));
+        self.translate(&mut node, &gene.kind);
+        let mut iter = gene.data.iter();
+        while let Some(item) = iter.next() {
+            match item {
+                Value::Symbol(s) if s == "@" => match iter.next() {
+                    Some(key) => {
+                        let mut key_node = node.append(Compilable::new(CompilableData::Accessor(Accessor::Dynamic)));
+                        self.translate(&mut key_node, key);
+                    }
+                    None => return self.error(USAGE, value),
+                },
+                Value::Symbol(s) if s.len() > 1 && s.starts_with('.') => match s[1..].parse::<i64>() {
+                    Ok(index) if index >= 0 => {
+                        node.append(Compilable::new(CompilableData::Accessor(Accessor::Item(index as usize))));
+                    }
+                    // Counts from the end, which only GetDynamic does
+                    Ok(index) => {
+                        let mut key_node = node.append(Compilable::new(CompilableData::Accessor(Accessor::Dynamic)));
+                        key_node.append(Compilable::new(CompilableData::Int(index)));
+                    }
+                    Err(_) => {
+                        node.append(Compilable::new(CompilableData::Accessor(Accessor::Prop(s[1..].to_string()))));
+                    }
+                },
+                _ => return self.error(USAGE, value),
+            }
+        }
+    }
+
+    fn translate_arguments(&mut self, parent: &mut NodeMut<Compilable>, props: &HashMap<String, Value>, data: &[Value]) {
+        // TODO: optimization for empty / literal data
+        let mut new_arr = Vec::new();
+        // add literal values to new_arr
+        for (i, item) in data.iter().enumerate() {
+            if item.is_literal() {
+                new_arr.insert(i, item.clone());
+            } else {
+                new_arr.insert(i, Value::Void);
+            }
+        }
+        let mut node = parent.append(Compilable::new(CompilableData::InvocationArguments(new_arr)));
+        for (i, item) in data.iter().enumerate() {
+            let mut node2 = node.append(Compilable::new(CompilableData::ArrayChild(i)));
+            self.translate(&mut node2, item);
+        }
+        let mut keys: Vec<&String> = props.keys().collect();
+        keys.sort();
+        for key in keys {
+            let mut node2 = node.append(Compilable::new(CompilableData::MapChild(key.clone())));
+            self.translate(&mut node2, &props[key]);
+        }
+    }
+
+    fn resolve(&mut self, name: &str) -> Variable {
+        for (depth, locals) in self.scopes.iter().rev().enumerate() {
+            if let Some(slot) = locals.iter().position(|local| local == name) {
+                return if depth == 0 { Variable::Local(slot) } else { Variable::Upvalue(depth, slot) };
+            }
+        }
+        Variable::Global(self.module.global_id(name))
+    }
+
+    /// Definitions always go to the innermost function, or the top level
+    fn define(&mut self, name: &str, block: &mut Block) -> Result<(), CompileError> {
+        match self.resolve(name) {
+            Variable::Local(slot) => block.add_instr(Instruction::DefLocal(slot)),
+            Variable::Global(id) => block.add_instr(Instruction::DefGlobal(id)),
+            Variable::Upvalue(..) => {
+                return Err(CompileError::new(format!("{} is not a local variable", name), Value::Symbol(name.to_string())));
+            }
         }
+        Ok(())
     }
 
-    fn compile_tree(&mut self, tree: &Tree<Compilable>, name: String, is_default: bool) -> Block {
+    fn compile_tree(&mut self, tree: &Tree<Compilable>, name: String, is_default: bool) -> Result<Block, CompileError> {
         let mut block = Block::new(name);
 
         self.reg_trackers.insert(block.id.clone(), Vec::new());
@@ -272,21 +733,243 @@ impl Compiler {
             block.add_instr(Instruction::Init);
         }
 
-        self.compile_node(&tree.root(), &mut block);
-        println!("{}", block);
+        let exits = mem::take(&mut self.exits);
+        let result = self.compile_node(&tree.root(), &mut block);
+        self.exits = exits;
+        result?;
+
+        Ok(block)
+    }
+
+    /// Compile the statements from `first` on as the body of a loop that starts
+    /// at `start`, returns the end of the loop
+    fn compile_loop(&mut self, first: Option<NodeRef<Compilable>>, label: &Option<String>, start: usize, block: &mut Block) -> Result<usize, CompileError> {
+        self.exits.push(Exit::Loop { label: label.clone(), start, breaks: Vec::new() });
+        let mut sibling = first;
+        while let Some(sibling_node) = sibling {
+            self.compile_node(&sibling_node, block)?;
+            sibling = sibling_node.next_sibling();
+        }
+        let breaks = match self.exits.pop() {
+            Some(Exit::Loop { breaks, .. }) => breaks,
+            _ => unreachable!(),
+        };
+
+        (*block).add_instr(Instruction::Jump(start as i16));
+
+        let end_pos = block.len();
+        for pos in breaks {
+            block.instructions[pos] = Instruction::Jump(end_pos as i16);
+        }
+        Ok(end_pos)
+    }
+
+    /// Bind the value in default reg to a name, `_` or an array of patterns
+    fn bind_pattern(&mut self, pattern: &Value, block: &mut Block) -> Result<(), CompileError> {
+        match pattern {
+            Value::Symbol(name) if name == "_" => (),
+            Value::Symbol(name) => self.define(name, block)?,
+            Value::Array(items) => {
+                let reg = self.get_reg(block);
+                (*block).add_instr(Instruction::CopyFromDefault(reg));
+                for (i, item) in items.iter().enumerate() {
+                    (*block).add_instr(Instruction::GetItem(reg, i));
+                    self.bind_pattern(item, block)?;
+                }
+                self.free_reg(block, reg);
+            }
+            _ => return Err(CompileError::new("invalid pattern", pattern.clone())),
+        }
+        Ok(())
+    }
+
+    /// Get `target` followed by `accessors` into default reg
+    fn compile_path(&mut self, target: &NodeRef<Compilable>, accessors: &[NodeRef<Compilable>], block: &mut Block) -> Result<(), CompileError> {
+        self.compile_node(target, block)?;
+        for accessor in accessors {
+            let reg = self.get_reg(block);
+            (*block).add_instr(Instruction::CopyFromDefault(reg));
+            match &accessor.value().data {
+                CompilableData::Accessor(Accessor::Prop(name)) => (*block).add_instr(Instruction::GetProp(reg, name.clone())),
+                CompilableData::Accessor(Accessor::Item(index)) => (*block).add_instr(Instruction::GetItem(reg, *index)),
+                CompilableData::Accessor(Accessor::Dynamic) => {
+                    self.compile_node(&accessor.first_child().unwrap(), block)?;
+                    let key_reg = self.get_reg(block);
+                    (*block).add_instr(Instruction::CopyFromDefault(key_reg));
+                    (*block).add_instr(Instruction::GetDynamic(reg, key_reg));
+                    self.free_reg(block, key_reg);
+                }
+                _ => return Err(unexpected(accessor)),
+            }
+            self.free_reg(block, reg);
+        }
+        Ok(())
+    }
+
+    /// Emit the tests of `rows` as a decision tree. A test runs once and splits
+    /// the clauses into those that can still match after it passed and after it
+    /// failed, so clauses share the tests they have in common. Leaves bind the
+    /// names of the first clause left, check its guard and jump to its body,
+    /// `bodies` collects those jumps for each clause.
+    fn compile_decision(
+        &mut self,
+        node: &NodeRef<Compilable>,
+        rows: &[Row],
+        reg: u16,
+        bodies: &mut Vec<Vec<usize>>,
+        no_match: &mut Vec<usize>,
+        block: &mut Block,
+    ) -> Result<(), CompileError> {
+        let row = match rows.first() {
+            Some(row) => row,
+            None => {
+                no_match.push(block.len());
+                (*block).add_instr(Instruction::Dummy);
+                return Ok(());
+            }
+        };
+        if let Some(test) = row.tests.first() {
+            self.compile_test(test, reg, block);
+            let branch = block.len();
+            (*block).add_instr(Instruction::Dummy);
+            let passed: Vec<Row> = rows.iter().filter_map(|row| row.assuming(test, true)).collect();
+            self.compile_decision(node, &passed, reg, bodies, no_match, block)?;
+            block.instructions[branch] = Instruction::JumpIfFalse(block.len() as i16);
+            let failed: Vec<Row> = rows.iter().filter_map(|row| row.assuming(test, false)).collect();
+            return self.compile_decision(node, &failed, reg, bodies, no_match, block);
+        }
+
+        for binding in row.bindings.iter() {
+            match binding {
+                Binding::Value(_, path) => self.compile_steps(path, reg, block),
+                Binding::Rest(_, path, start) => {
+                    let target = self.path_reg(path, reg, block);
+                    (*block).add_instr(Instruction::Rest(target, *start));
+                    if target != reg {
+                        self.free_reg(block, target);
+                    }
+                }
+            }
+            self.define(binding.name(), block)?;
+        }
+        let clause = node.children().nth(row.clause + 1).unwrap();
+        match &clause.value().data {
+            CompilableData::MatchClause(_, true) => {
+                self.compile_node(&clause.first_child().unwrap(), block)?;
+                let branch = block.len();
+                (*block).add_instr(Instruction::Dummy);
+                bodies[row.clause].push(block.len());
+                (*block).add_instr(Instruction::Dummy);
+                block.instructions[branch] = Instruction::JumpIfFalse(block.len() as i16);
+                self.compile_decision(node, &rows[1..], reg, bodies, no_match, block)
+            }
+            CompilableData::MatchClause(_, false) => {
+                bodies[row.clause].push(block.len());
+                (*block).add_instr(Instruction::Dummy);
+                Ok(())
+            }
+            _ => Err(unexpected(&clause)),
+        }
+    }
+
+    /// Leave the result of `test` on the value in `reg` in default reg
+    fn compile_test(&mut self, test: &Test, reg: u16, block: &mut Block) {
+        let target = self.path_reg(test.path(), reg, block);
+        match test {
+            Test::Shape(_, shape) => (*block).add_instr(Instruction::Check(target, shape.clone())),
+            Test::Equals(_, literal) => {
+                (*block).add_instr(Instruction::Default(literal.clone()));
+                (*block).add_instr(Instruction::BinaryOp("==".to_string(), target));
+            }
+        }
+        if target != reg {
+            self.free_reg(block, target);
+        }
+    }
 
-        block
+    /// Put the part of the value in `reg` at `path` in default reg
+    fn compile_steps(&mut self, path: &[Step], reg: u16, block: &mut Block) {
+        if path.is_empty() {
+            (*block).add_instr(Instruction::CopyToDefault(reg));
+            return;
+        }
+        let temp = if path.len() > 1 { self.get_reg(block) } else { reg };
+        for (i, step) in path.iter().enumerate() {
+            let from = if i == 0 { reg } else { temp };
+            if i > 0 {
+                (*block).add_instr(Instruction::CopyFromDefault(temp));
+            }
+            (*block).add_instr(match step {
+                Step::Item(index) => Instruction::GetItem(from, *index),
+                Step::Prop(key) => Instruction::GetProp(from, key.clone()),
+                Step::Kind => Instruction::GetKind(from),
+            });
+        }
+        if temp != reg {
+            self.free_reg(block, temp);
+        }
     }
 
-    fn compile_node(&mut self, node: &NodeRef<Compilable>, block: &mut Block) {
+    /// The register holding the part of the value in `reg` at `path`, a new one
+    /// unless the path is empty
+    fn path_reg(&mut self, path: &[Step], reg: u16, block: &mut Block) -> u16 {
+        if path.is_empty() {
+            return reg;
+        }
+        self.compile_steps(path, reg, block);
+        let target = self.get_reg(block);
+        (*block).add_instr(Instruction::CopyFromDefault(target));
+        target
+    }
+
+    /// Find the loop a `break` or `continue` jumps to, the translation made sure there is one
+    fn find_loop(&self, label: &Option<String>) -> usize {
+        self.exits.iter().rposition(|exit| match exit {
+            Exit::Loop { label: loop_label, .. } => label.is_none() || label == loop_label,
+            Exit::Finally(..) => false,
+        }).unwrap()
+    }
+
+    /// Run the finally sections of `self.exits[depth..]`, innermost first,
+    /// before jumping out of them. The default register is kept.
+    fn leave(&mut self, node: &NodeRef<Compilable>, block: &mut Block, depth: usize) -> Result<(), CompileError> {
+        for i in (depth..self.exits.len()).rev() {
+            let finally_id = match self.exits[i] {
+                Exit::Finally(id, _) => id,
+                Exit::Loop { .. } => continue,
+            };
+            // The copy is outside of its own try, a jump in it must not run it again
+            let inner = self.exits.split_off(i);
+            let start = block.len();
+            let reg = self.get_reg(block);
+            (*block).add_instr(Instruction::CopyFromDefault(reg));
+            self.compile_node(&node.tree().get(finally_id).unwrap(), block)?;
+            (*block).add_instr(Instruction::CopyToDefault(reg));
+            self.free_reg(block, reg);
+            self.exits.extend(inner);
+
+            let end = block.len();
+            for exit in self.exits[i..].iter_mut() {
+                if let Exit::Finally(_, gaps) = exit {
+                    gaps.push((start, end));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_node(&mut self, node: &NodeRef<Compilable>, block: &mut Block) -> Result<(), CompileError> {
         // dbg!(node.value().data.clone());
         match &node.value().data {
             CompilableData::Block => {
                 for child in node.children() {
-                    self.compile_node(&child, block);
+                    self.compile_node(&child, block)?;
                 }
                 block.add_instr(Instruction::CallEnd);
             }
+            CompilableData::Void => {
+                block.add_instr(Instruction::Default(Value::Void));
+            }
             CompilableData::Null => {
                 block.add_instr(Instruction::Default(Value::Null));
             }
@@ -303,7 +986,12 @@ impl Compiler {
                 block.add_instr(Instruction::Default(Value::String(v.clone())));
             }
             CompilableData::Symbol(s) => {
-                (*block).add_instr(Instruction::GetMember(s.to_string()));
+                let instr = match self.resolve(s) {
+                    Variable::Local(slot) => Instruction::GetLocal(slot),
+                    Variable::Upvalue(depth, slot) => Instruction::GetUpvalue(depth, slot),
+                    Variable::Global(id) => Instruction::GetGlobal(id),
+                };
+                (*block).add_instr(instr);
             }
             CompilableData::Array(v) => {
                 let reg = self.get_reg(block);
@@ -311,10 +999,10 @@ impl Compiler {
                 for child in node.children() {
                     match child.value().data {
                         CompilableData::ArrayChild(i) => {
-                            self.compile_node(&child.first_child().unwrap(), block);
+                            self.compile_node(&child.first_child().unwrap(), block)?;
                             (*block).add_instr(Instruction::SetItem(reg, i));
                         }
-                        _ => unimplemented!()
+                        _ => return Err(unexpected(&child)),
                     }
                 }
                 (*block).add_instr(Instruction::CopyToDefault(reg));
@@ -328,43 +1016,163 @@ impl Compiler {
                     match &child.value().data {
                         CompilableData::MapChild(key) => {
                             let value_node = child.first_child().unwrap();
-                            self.compile_node(&value_node, block);
+                            self.compile_node(&value_node, block)?;
                             (*block).add_instr(Instruction::SetProp(reg, key.clone()));
                         }
-                        _ => unimplemented!()
+                        _ => return Err(unexpected(&child)),
+                    }
+                }
+                (*block).add_instr(Instruction::CopyToDefault(reg));
+                self.free_reg(block, reg);
+            }
+            CompilableData::Match => {
+                let mut children = node.children();
+                self.compile_node(&children.next().unwrap(), block)?;
+                let reg = self.get_reg(block);
+                (*block).add_instr(Instruction::CopyFromDefault(reg));
+
+                let mut rows = Vec::new();
+                for (i, clause) in children.enumerate() {
+                    match &clause.value().data {
+                        CompilableData::MatchClause(pattern, _) => rows.push(Row::new(i, pattern)),
+                        _ => return Err(unexpected(&clause)),
+                    }
+                }
+                let mut bodies = vec![Vec::new(); rows.len()];
+                let mut no_match = Vec::new();
+                self.compile_decision(node, &rows, reg, &mut bodies, &mut no_match, block)?;
+
+                let mut ends = Vec::new();
+                for (clause, jumps) in node.children().skip(1).zip(bodies) {
+                    // A clause after one that matches everything is never reached
+                    if jumps.is_empty() {
+                        continue;
+                    }
+                    let body_pos = block.len() as i16;
+                    for pos in jumps {
+                        block.instructions[pos] = Instruction::Jump(body_pos);
                     }
+                    self.compile_node(&clause.last_child().unwrap(), block)?;
+                    ends.push(block.len());
+                    (*block).add_instr(Instruction::Dummy);
+                }
+                let no_match_pos = block.len() as i16;
+                for pos in no_match {
+                    block.instructions[pos] = Instruction::Jump(no_match_pos);
+                }
+                (*block).add_instr(Instruction::NoMatch(reg));
+
+                let end_pos = block.len() as i16;
+                for pos in ends {
+                    block.instructions[pos] = Instruction::Jump(end_pos);
+                }
+                self.free_reg(block, reg);
+            }
+            CompilableData::Literal(v) => {
+                block.add_instr(Instruction::Default(v.clone()));
+            }
+            CompilableData::Gene(gene) => {
+                let reg = self.get_reg(block);
+                (*block).add_instr(Instruction::Save(reg, Value::Gene(Box::new(gene.clone()))));
+                for child in node.children() {
+                    self.compile_node(&child.first_child().unwrap(), block)?;
+                    let instr = match &child.value().data {
+                        CompilableData::GeneKind => Instruction::SetKind(reg),
+                        CompilableData::GeneProp(key) => Instruction::SetProp(reg, key.clone()),
+                        CompilableData::GeneDataChild(i) => Instruction::SetItem(reg, *i),
+                        _ => return Err(unexpected(&child)),
+                    };
+                    (*block).add_instr(instr);
                 }
                 (*block).add_instr(Instruction::CopyToDefault(reg));
                 self.free_reg(block, reg);
             }
             CompilableData::Var(name) => {
-                self.compile_node(&node.first_child().unwrap(), block);
-                (*block).add_instr(Instruction::DefMember(name.clone()));
+                self.compile_node(&node.first_child().unwrap(), block)?;
+                self.define(name, block)?;
             }
             CompilableData::BinaryOp(op) => {
                 let first = node.first_child().unwrap();
-                self.compile_node(&first, block);
+                self.compile_node(&first, block)?;
                 let first_reg = self.get_reg(block);
                 (*block).add_instr(Instruction::CopyFromDefault(first_reg));
 
                 let second = first.next_sibling().unwrap();
-                self.compile_node(&second, block);
+                self.compile_node(&second, block)?;
 
                 (*block).add_instr(Instruction::BinaryOp(op.clone(), first_reg));
                 self.free_reg(block, first_reg);
             }
+            CompilableData::LogicalOp(op) => {
+                // The result is the last operand evaluated
+                let first = node.first_child().unwrap();
+                self.compile_node(&first, block)?;
+                let jump_pos = block.len();
+                (*block).add_instr(Instruction::Dummy);
+
+                self.compile_node(&first.next_sibling().unwrap(), block)?;
+
+                let end_pos = block.len() as i16;
+                block.instructions[jump_pos] = if op == "&&" {
+                    Instruction::JumpIfFalse(end_pos)
+                } else {
+                    Instruction::JumpIfTrue(end_pos)
+                };
+            }
+            CompilableData::Not => {
+                self.compile_node(&node.first_child().unwrap(), block)?;
+                (*block).add_instr(Instruction::Not);
+            }
             CompilableData::Assignment(name) => {
-                self.compile_node(&node.first_child().unwrap(), block);
-                (*block).add_instr(Instruction::SetMember(name.clone()));
+                self.compile_node(&node.first_child().unwrap(), block)?;
+                let instr = match self.resolve(name) {
+                    Variable::Local(slot) => Instruction::SetLocal(slot),
+                    Variable::Upvalue(depth, slot) => Instruction::SetUpvalue(depth, slot),
+                    Variable::Global(id) => Instruction::SetGlobal(id),
+                };
+                (*block).add_instr(instr);
+            }
+            CompilableData::Access => {
+                let children: Vec<_> = node.children().collect();
+                self.compile_path(&children[0], &children[1..], block)?;
+            }
+            CompilableData::AccessAssignment => {
+                let access = node.first_child().unwrap();
+                let value_node = access.next_sibling().unwrap();
+                let children: Vec<_> = access.children().collect();
+                let (last, path) = children[1..].split_last().unwrap();
+                self.compile_path(&children[0], path, block)?;
+                let reg = self.get_reg(block);
+                (*block).add_instr(Instruction::CopyFromDefault(reg));
+                match &last.value().data {
+                    CompilableData::Accessor(Accessor::Prop(name)) => {
+                        self.compile_node(&value_node, block)?;
+                        (*block).add_instr(Instruction::SetProp(reg, name.clone()));
+                    }
+                    CompilableData::Accessor(Accessor::Item(index)) => {
+                        self.compile_node(&value_node, block)?;
+                        (*block).add_instr(Instruction::SetItem(reg, *index));
+                    }
+                    CompilableData::Accessor(Accessor::Dynamic) => {
+                        self.compile_node(&last.first_child().unwrap(), block)?;
+                        let key_reg = self.get_reg(block);
+                        (*block).add_instr(Instruction::CopyFromDefault(key_reg));
+                        self.compile_node(&value_node, block)?;
+                        (*block).add_instr(Instruction::SetDynamic(reg, key_reg));
+                        self.free_reg(block, key_reg);
+                    }
+                    _ => return Err(unexpected(last)),
+                }
+                self.free_reg(block, reg);
             }
             CompilableData::If => {
                 let start_pos = block.len();
                 let pair_node = node.first_child().unwrap();
-                self.compile_node(&pair_node, block);
+                self.compile_node(&pair_node, block)?;
 
                 let else_pos = block.len();
                 if let Some(else_node) = pair_node.next_sibling() {
-                    self.compile_node(&else_node, block);
+                    self.compile_node(&else_node, block)?;
                 }
 
                 let end_pos = block.len();
@@ -373,10 +1181,10 @@ impl Compiler {
                     let instr = &block.instructions[i];
                     match instr {
                         Instruction::JumpToElse => {
-                            mem::replace(&mut (*block).instructions[i], Instruction::JumpIfFalse(else_pos as i16));
+                            block.instructions[i] = Instruction::JumpIfFalse(else_pos as i16);
                         }
                         Instruction::JumpToNextStatement => {
-                            mem::replace(&mut (*block).instructions[i], Instruction::Jump(end_pos as i16));
+                            block.instructions[i] = Instruction::Jump(end_pos as i16);
                         }
                         _ => ()
                     }
@@ -384,35 +1192,39 @@ impl Compiler {
             }
             CompilableData::IfPair => {
                 let cond_node = node.first_child().unwrap();
-                self.compile_node(&cond_node, block);
+                self.compile_node(&cond_node, block)?;
                 (*block).add_instr(Instruction::JumpToElse);
 
                 let then_node = cond_node.next_sibling().unwrap();
-                self.compile_node(&then_node, block);
+                self.compile_node(&then_node, block)?;
                 (*block).add_instr(Instruction::JumpToNextStatement);
             }
             CompilableData::IfPairCondition | CompilableData::IfPairThen | CompilableData::IfElse => {
                 let cond_node = node.first_child().unwrap();
-                self.compile_node(&cond_node, block);
+                self.compile_node(&cond_node, block)?;
             }
             CompilableData::Statements => {
                 for node in node.children() {
-                    self.compile_node(&node, block);
+                    self.compile_node(&node, block)?;
                 }
             }
             CompilableData::Function(name, matcher, body) => {
                 (*block).add_instr(Instruction::Function(name.to_string(), matcher.clone(), body.to_string()));
+                self.define(name, block)?;
+            }
+            CompilableData::Lambda(matcher, body) => {
+                (*block).add_instr(Instruction::Function("fnx".to_string(), matcher.clone(), body.to_string()));
             }
             CompilableData::Invocation => {
                 let target_node = node.first_child().unwrap();
-                self.compile_node(&target_node, block);
+                self.compile_node(&target_node, block)?;
                 let target_reg = self.get_reg(block);
                 (*block).add_instr(Instruction::CopyFromDefault(target_reg));
 
                 if let Some(args_node) = target_node.next_sibling() {
                     let args_reg = self.get_reg(block);
                     args_node.value().set_u16("reg", args_reg);
-                    self.compile_node(&args_node, block);
+                    self.compile_node(&args_node, block)?;
                     (*block).add_instr(Instruction::Call(target_reg, Some(args_reg), HashMap::new()));
                     self.free_reg(block, args_reg);
                 } else {
@@ -427,38 +1239,135 @@ impl Compiler {
                 for child in node.children() {
                     match child.value().data {
                         CompilableData::ArrayChild(i) => {
-                            self.compile_node(&child.first_child().unwrap(), block);
+                            self.compile_node(&child.first_child().unwrap(), block)?;
                             (*block).add_instr(Instruction::SetItem(reg, i));
                         }
-                        _ => unimplemented!()
+                        CompilableData::MapChild(ref key) => {
+                            self.compile_node(&child.first_child().unwrap(), block)?;
+                            (*block).add_instr(Instruction::SetProp(reg, key.clone()));
+                        }
+                        _ => return Err(unexpected(&child)),
                     }
                 }
             }
-            CompilableData::While => {
+            CompilableData::While(label) => {
                 let start_pos = block.len();
                 let cond_node = node.first_child().unwrap();
-                self.compile_node(&cond_node, block);
+                self.compile_node(&cond_node, block)?;
 
                 let jump_pos = block.len();
                 (*block).add_instr(Instruction::JumpToElse);
 
-                let mut sibling = cond_node.next_sibling();
-                while sibling.is_some() {
-                    let sibling_node = sibling.unwrap();
-                    self.compile_node(&sibling_node, block);
-                    sibling = sibling_node.next_sibling();
-                }
+                let end_pos = self.compile_loop(cond_node.next_sibling(), label, start_pos, block)?;
+                block.instructions[jump_pos] = Instruction::JumpIfFalse(end_pos as i16);
+            }
+            CompilableData::For(label, iterate_props, pattern) => {
+                let collection_node = node.first_child().unwrap();
+                self.compile_node(&collection_node, block)?;
+                let reg = self.get_reg(block);
+                (*block).add_instr(if *iterate_props { Instruction::PropsIter(reg) } else { Instruction::Iter(reg) });
 
-                (*block).add_instr(Instruction::Jump(start_pos as i16));
+                let start_pos = block.len();
+                (*block).add_instr(Instruction::Dummy);
+                self.bind_pattern(pattern, block)?;
 
-                let end_pos = block.len();
-                mem::replace(&mut (*block).instructions[jump_pos], Instruction::JumpIfFalse(end_pos as i16));
+                let end_pos = self.compile_loop(collection_node.next_sibling(), label, start_pos, block)?;
+                block.instructions[start_pos] = Instruction::Next(reg, end_pos as i16);
+                self.free_reg(block, reg);
+            }
+            CompilableData::Range => {
+                let mut children = node.children();
+                self.compile_node(&children.next().unwrap(), block)?;
+                let start_reg = self.get_reg(block);
+                (*block).add_instr(Instruction::CopyFromDefault(start_reg));
+                self.compile_node(&children.next().unwrap(), block)?;
+                let end_reg = self.get_reg(block);
+                (*block).add_instr(Instruction::CopyFromDefault(end_reg));
+                match children.next() {
+                    Some(step_node) => self.compile_node(&step_node, block)?,
+                    None => (*block).add_instr(Instruction::Default(Value::Integer(1))),
+                }
+                (*block).add_instr(Instruction::Range(start_reg, end_reg));
+                self.free_reg(block, start_reg);
+                self.free_reg(block, end_reg);
             }
-            CompilableData::Break => {
-                (*block).add_instr(Instruction::Break);
+            CompilableData::Break(label) => {
+                let depth = self.find_loop(label);
+                self.leave(node, block, depth + 1)?;
+                let pos = block.len();
+                (*block).add_instr(Instruction::Dummy);
+                if let Exit::Loop { breaks, .. } = &mut self.exits[depth] {
+                    breaks.push(pos);
+                }
+            }
+            CompilableData::Continue(label) => {
+                let depth = self.find_loop(label);
+                self.leave(node, block, depth + 1)?;
+                if let Exit::Loop { start, .. } = self.exits[depth] {
+                    (*block).add_instr(Instruction::Jump(start as i16));
+                }
+            }
+            CompilableData::Return => {
+                self.compile_node(&node.first_child().unwrap(), block)?;
+                self.leave(node, block, 0)?;
+                (*block).add_instr(Instruction::CallEnd);
+            }
+            CompilableData::Throw => {
+                self.compile_node(&node.first_child().unwrap(), block)?;
+                (*block).add_instr(Instruction::Throw);
+            }
+            CompilableData::Try(catch, finally) => {
+                let mut sections = node.children();
+                if *finally {
+                    self.exits.push(Exit::Finally(node.last_child().unwrap().id(), Vec::new()));
+                }
+                let start = block.len();
+                self.compile_node(&sections.next().unwrap(), block)?;
+                let body_end = block.len();
+                let mut jumps = vec![block.len()];
+                (*block).add_instr(Instruction::Dummy);
+
+                if let Some(name) = catch {
+                    let target = block.len();
+                    let exit = if *finally { self.exits.last() } else { None };
+                    add_handlers(block, start, body_end, target, exit);
+                    (*block).add_instr(Instruction::Catch);
+                    if name != "_" {
+                        self.define(name, block)?;
+                    }
+                    self.compile_node(&sections.next().unwrap(), block)?;
+                    jumps.push(block.len());
+                    (*block).add_instr(Instruction::Dummy);
+                }
+
+                let end_pos = if *finally {
+                    let finally_node = sections.next().unwrap();
+                    let reg = self.get_reg(block);
+                    // Errors in the body or catch run the finally code and are raised again
+                    let (end, target) = (block.len(), block.len());
+                    add_handlers(block, start, end, target, self.exits.last());
+                    self.exits.pop();
+                    (*block).add_instr(Instruction::CopyFromDefault(reg));
+                    self.compile_node(&finally_node, block)?;
+                    (*block).add_instr(Instruction::CopyToDefault(reg));
+                    (*block).add_instr(Instruction::Throw);
+                    // Otherwise the result of the body or catch is kept
+                    let normal_pos = block.len();
+                    (*block).add_instr(Instruction::CopyFromDefault(reg));
+                    self.compile_node(&finally_node, block)?;
+                    (*block).add_instr(Instruction::CopyToDefault(reg));
+                    self.free_reg(block, reg);
+                    normal_pos
+                } else {
+                    block.len()
+                };
+                for pos in jumps {
+                    block.instructions[pos] = Instruction::Jump(end_pos as i16);
+                }
             }
-            _ => unimplemented!()
+            _ => return Err(unexpected(node)),
         }
+        Ok(())
     }
 
     /// 1. find and return available register
@@ -468,13 +1377,13 @@ impl Compiler {
         for i in 0..16 {
             let mut available = true;
             for tracker in trackers.iter() {
-                if *tracker == i as u16 {
+                if *tracker == i {
                     available = false;
                 }
             }
             if available {
                 trackers.push(i);
-                return i as u16;
+                return i;
             }
         }
         16 + random::<u16>()
@@ -488,10 +1397,372 @@ impl Compiler {
     }
 }
 
-pub struct NodeWrapper<'a>(&'a mut NodeRef<'a, Compilable>);
+/// Turn calls whose result is returned right away into TailCall: the next
+/// instruction is the CallEnd of the function, or a jump to it. Calls in a
+/// `try` keep their frame, it has to be there to handle errors.
+fn mark_tail_calls(block: &mut Block) {
+    let is_end = |instructions: &[Instruction], pos: usize| match instructions.get(pos) {
+        Some(Instruction::CallEnd) => true,
+        Some(Instruction::Jump(target)) => matches!(instructions.get(*target as usize), Some(Instruction::CallEnd)),
+        _ => false,
+    };
+    for pos in 0..block.len() {
+        if let Instruction::Call(target_reg, args_reg, _) = block.instructions[pos] {
+            if is_end(&block.instructions, pos + 1) && block.handler_at(pos).is_none() {
+                block.instructions[pos] = Instruction::TailCall(target_reg, args_reg);
+            }
+        }
+    }
+}
+
+/// Protect [start, end) with a handler, except the copies of the finally
+/// section made for jumps out of the `try`
+fn add_handlers(block: &mut Block, start: usize, end: usize, target: usize, exit: Option<&Exit>) {
+    let mut from = start;
+    if let Some(Exit::Finally(_, gaps)) = exit {
+        for &(gap_start, gap_end) in gaps.iter().filter(|(gap_start, _)| *gap_start < end) {
+            if from < gap_start {
+                block.handlers.push(Handler::new(from, gap_start, target));
+            }
+            from = gap_end;
+        }
+    }
+    if from < end {
+        block.handlers.push(Handler::new(from, end, target));
+    }
+}
+
+/// Collect the names defined with `var` and `fn` in `value`, without looking
+/// into nested function bodies
+fn collect_locals(value: &Value, locals: &mut Vec<String>) {
+    match value {
+        Value::Gene(gene) => {
+            if let Value::Symbol(kind) = &gene.kind {
+                match (kind.as_str(), gene.data.first()) {
+                    ("var", Some(Value::Symbol(name))) => add_local(locals, name),
+                    ("fn", Some(Value::Symbol(name))) => return add_local(locals, name),
+                    ("fn", _) | ("fnx", _) => return,
+                    ("for", Some(pattern)) => add_pattern_names(locals, pattern),
+                    ("match", _) => {
+                        for clause in gene.data.iter().skip(1) {
+                            if let Value::Gene(clause) = clause {
+                                if let Ok(pattern) = Pattern::try_from(&clause.kind) {
+                                    pattern.names().iter().for_each(|name| add_local(locals, name));
+                                }
+                            }
+                        }
+                    }
+                    ("try", _) => {
+                        for pair in gene.data.windows(2) {
+                            if let [Value::Symbol(catch), Value::Symbol(name)] = pair {
+                                if catch == "catch" && name != "_" {
+                                    add_local(locals, name);
+                                }
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            collect_locals(&gene.kind, locals);
+            for value in gene.props.values() {
+                collect_locals(value, locals);
+            }
+            for item in gene.data.iter() {
+                collect_locals(item, locals);
+            }
+        }
+        Value::Array(items) | Value::Stream(items) => {
+            for item in items.iter() {
+                collect_locals(item, locals);
+            }
+        }
+        Value::Map(map) => {
+            for value in map.values() {
+                collect_locals(value, locals);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn add_pattern_names(locals: &mut Vec<String>, pattern: &Value) {
+    match pattern {
+        Value::Symbol(name) if name != "_" => add_local(locals, name),
+        Value::Array(items) => {
+            for item in items.iter() {
+                add_pattern_names(locals, item);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Whether a gene with `data` reads from its kind: `(target .name)`,
+/// `(target .0)` or `(target @ key)`, possibly followed by more accessors
+fn is_access(data: &[Value]) -> bool {
+    match data.first() {
+        Some(Value::Symbol(s)) => s == "@" || (s.len() > 1 && s.starts_with('.')),
+        _ => false,
+    }
+}
+
+/// A step from the value being matched to a part of it
+#[derive(Clone, Debug, PartialEq)]
+enum Step {
+    Item(usize),
+    Prop(String),
+    Kind,
+}
+
+/// A test on the part of the value being matched at a path
+#[derive(Clone, Debug, PartialEq)]
+enum Test {
+    Shape(Vec<Step>, Shape),
+    Equals(Vec<Step>, Value),
+}
+
+impl Test {
+    fn path(&self) -> &[Step] {
+        match self {
+            Test::Shape(path, _) | Test::Equals(path, _) => path,
+        }
+    }
+
+    /// Whether `other` passes when this test passes
+    fn implies(&self, other: &Test) -> bool {
+        if self == other {
+            return true;
+        }
+        match (self, other) {
+            (Test::Shape(path, Shape::Array(len, _)), Test::Shape(other_path, Shape::Array(min, true)))
+            | (Test::Shape(path, Shape::Gene(len, _)), Test::Shape(other_path, Shape::Gene(min, true))) => {
+                path == other_path && len >= min
+            }
+            (Test::Equals(path, a), Test::Equals(other_path, b)) => path == other_path && same_literal(a, b),
+            _ => false,
+        }
+    }
+
+    /// Whether `other` fails when this test passes
+    fn excludes(&self, other: &Test) -> bool {
+        if self.path() != other.path() {
+            return false;
+        }
+        match (self, other) {
+            (Test::Equals(_, a), Test::Equals(_, b)) => !same_literal(a, b),
+            (Test::Equals(_, literal), Test::Shape(..)) | (Test::Shape(..), Test::Equals(_, literal)) => {
+                !matches!(literal, Value::Array(_) | Value::Map(_) | Value::Gene(_))
+            }
+            (Test::Shape(_, a), Test::Shape(_, b)) => match (a, b) {
+                (Shape::Array(a, a_more), Shape::Array(b, b_more)) | (Shape::Gene(a, a_more), Shape::Gene(b, b_more)) => {
+                    // No length is accepted by both
+                    match (a_more, b_more) {
+                        (false, false) => a != b,
+                        (false, true) => a < b,
+                        (true, false) => b < a,
+                        (true, true) => false,
+                    }
+                }
+                (Shape::Array(..), _) | (_, Shape::Array(..)) => true,
+                (Shape::Map, Shape::Gene(..)) | (Shape::Gene(..), Shape::Map) => true,
+                _ => false,
+            },
+        }
+    }
+}
+
+fn same_literal(a: &Value, b: &Value) -> bool {
+    RuntimeValue::from(a).equals(&RuntimeValue::from(b))
+}
+
+/// A name bound by a match clause to the part of the value at a path, or to
+/// the items from `start` of the array or gene there
+#[derive(Clone, Debug)]
+enum Binding {
+    Value(String, Vec<Step>),
+    Rest(String, Vec<Step>, usize),
+}
+
+impl Binding {
+    fn name(&self) -> &str {
+        match self {
+            Binding::Value(name, _) | Binding::Rest(name, _, _) => name,
+        }
+    }
+}
+
+/// A match clause as the tests its pattern makes, a part is tested after the
+/// value it is in, and the names it binds
+#[derive(Clone, Debug)]
+struct Row {
+    clause: usize,
+    tests: Vec<Test>,
+    bindings: Vec<Binding>,
+}
+
+impl Row {
+    fn new(clause: usize, pattern: &Pattern) -> Self {
+        let mut row = Row {
+            clause,
+            tests: Vec::new(),
+            bindings: Vec::new(),
+        };
+        row.add(pattern, Vec::new());
+        row
+    }
+
+    fn add(&mut self, pattern: &Pattern, path: Vec<Step>) {
+        match pattern {
+            Pattern::Ignore => (),
+            Pattern::Name(name, _) => self.bindings.push(Binding::Value(name.clone(), path)),
+            Pattern::Literal(literal) => self.tests.push(Test::Equals(path, literal.clone())),
+            Pattern::Array(data) => {
+                self.tests.push(Test::Shape(path.clone(), Shape::Array(data.params.len(), data.rest.is_some())));
+                self.add_data(data, &path);
+            }
+            Pattern::Map(props) => {
+                self.tests.push(Test::Shape(path.clone(), Shape::Map));
+                self.add_props(props, &path);
+            }
+            Pattern::Gene(kind, props, data) => {
+                self.tests.push(Test::Shape(path.clone(), Shape::Gene(data.params.len(), data.rest.is_some())));
+                self.add(kind, child(&path, Step::Kind));
+                self.add_props(props, &path);
+                self.add_data(data, &path);
+            }
+        }
+    }
+
+    fn add_data(&mut self, data: &DataMatcher, path: &[Step]) {
+        for (i, param) in data.params.iter().enumerate() {
+            self.add(&param.pattern, child(path, Step::Item(i)));
+        }
+        if let Some(box Pattern::Name(name, _)) = &data.rest {
+            self.bindings.push(Binding::Rest(name.clone(), path.to_vec(), data.params.len()));
+        }
+    }
+
+    /// The props have to be there, even for `_`
+    fn add_props(&mut self, props: &[PropMatcher], path: &[Step]) {
+        for prop in props.iter() {
+            self.tests.push(Test::Shape(path.to_vec(), Shape::HasProp(prop.name.clone())));
+            self.add(&prop.pattern, child(path, Step::Prop(prop.name.clone())));
+        }
+    }
+
+    /// What is left of the row once `test` passed or failed, `None` if the
+    /// clause can't match any more
+    fn assuming(&self, test: &Test, passed: bool) -> Option<Row> {
+        if passed {
+            if self.tests.iter().any(|other| test.excludes(other)) {
+                return None;
+            }
+            let mut row = self.clone();
+            row.tests.retain(|other| !test.implies(other));
+            Some(row)
+        } else if self.tests.iter().any(|other| other.implies(test)) {
+            None
+        } else {
+            Some(self.clone())
+        }
+    }
+}
+
+fn child(path: &[Step], step: Step) -> Vec<Step> {
+    let mut path = path.to_vec();
+    path.push(step);
+    path
+}
+
+/// Whether a part of the pattern has a default value
+fn has_default(pattern: &Pattern) -> bool {
+    let data_has_default = |data: &DataMatcher| {
+        data.params.iter().any(|param| param.default.is_some() || has_default(&param.pattern))
+    };
+    let props_have_default = |props: &[PropMatcher]| {
+        props.iter().any(|prop| prop.default.is_some() || has_default(&prop.pattern))
+    };
+    match pattern {
+        Pattern::Ignore | Pattern::Name(..) | Pattern::Literal(_) => false,
+        Pattern::Array(data) => data_has_default(data),
+        Pattern::Map(props) => props_have_default(props),
+        Pattern::Gene(kind, props, data) => has_default(kind) || props_have_default(props) || data_has_default(data),
+    }
+}
+
+/// `%name` in a quoted value, `%%name` is the symbol `%name`
+fn is_unquote_symbol(s: &str) -> bool {
+    s.len() > 1 && s.starts_with('%') && !s.starts_with("%%")
+}
+
+/// `(% expr)` in a quoted value
+fn is_unquote(gene: &Gene) -> bool {
+    gene.kind == Value::Symbol("%".to_string()) && gene.props.is_empty() && gene.data.len() == 1
+}
+
+/// The value of a quoted value that has nothing to evaluate
+fn quoted_literal(value: &Value) -> Option<Value> {
+    match value {
+        Value::Symbol(s) if s.starts_with("%%") => Some(Value::Symbol(s[1..].to_string())),
+        Value::Symbol(s) if is_unquote_symbol(s) => None,
+        Value::Gene(box gene) if is_unquote(gene) => None,
+        Value::Gene(box gene) => Some(Value::Gene(Box::new(Gene {
+            kind: quoted_literal(&gene.kind)?,
+            props: gene.props.iter().map(|(key, v)| quoted_literal(v).map(|v| (key.clone(), v))).collect::<Option<_>>()?,
+            data: gene.data.iter().map(quoted_literal).collect::<Option<_>>()?,
+        }))),
+        Value::Array(items) => items.iter().map(quoted_literal).collect::<Option<_>>().map(Value::Array),
+        Value::Map(map) => map.iter().map(|(key, v)| quoted_literal(v).map(|v| (key.clone(), v))).collect::<Option<_>>().map(Value::Map),
+        _ => Some(value.clone()),
+    }
+}
+
+/// Entries sorted by key, so that the generated code doesn't depend on hash order
+fn sorted(map: &HashMap<String, Value>) -> Vec<(&String, &Value)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+/// A name, `_` or an array of patterns
+fn is_loop_pattern(pattern: &Value) -> bool {
+    match pattern {
+        Value::Symbol(_) => true,
+        Value::Array(items) => items.iter().all(is_loop_pattern),
+        _ => false,
+    }
+}
+
+fn add_local(locals: &mut Vec<String>, name: &str) {
+    if !locals.iter().any(|local| local == name) {
+        locals.push(name.to_string());
+    }
+}
+
+/// Error for a node the compiler should not have made, with the form of the
+/// nearest node that has one
+fn unexpected(node: &NodeRef<Compilable>) -> CompileError {
+    let form = std::iter::successors(Some(*node), |node| node.parent()).find_map(|node| node.value().form.clone());
+    let form = form.unwrap_or(Value::Void);
+    CompileError::new(format!("unexpected node {:?}", node.value().data), form)
+}
+
+/// Set the spans of `errors` to where their forms are in `root`. Errors for
+/// equal forms get the places they appear at in order.
+fn locate(errors: &mut [CompileError], root: &Value, spans: &SourceMap) {
+    for i in 0..errors.len() {
+        let (before, rest) = errors.split_at_mut(i);
+        let error = &mut rest[0];
+        let seen = before.iter().filter(|e| e.form == error.form).count();
+        error.span = spans.find(root, &error.form).get(seen).copied();
+    }
+}
+
+pub struct NodeWrapper<'a>(pub &'a mut NodeRef<'a, Compilable>);
 
 impl<'a> NodeWrapper<'a> {
-    pub fn use_member(&mut self, name: &str) -> bool {
+    pub fn use_member(&mut self, _name: &str) -> bool {
         true
     }
 
@@ -519,6 +1790,8 @@ impl<'a> NodeWrapper<'a> {
 
 pub struct Compilable {
     pub data: CompilableData,
+    /// The form this node was translated from, see `Compiler::translate`
+    pub form: Option<Value>,
     pub options: RefCell<HashMap<String, Box<dyn Any>>>,
     // pub start_pos: Option<usize>,
     // pub instr_count: Option<usize>,
@@ -528,6 +1801,7 @@ impl Compilable {
     pub fn new(data: CompilableData) -> Self {
         Compilable {
             data,
+            form: None,
             options: RefCell::new(HashMap::new()),
             // start_pos: None,
             // instr_count: None,
@@ -568,12 +1842,17 @@ pub enum CompilableData {
     ArrayChild(usize),
     Map(HashMap<String, Value>), // literal values are included
     MapChild(String),
-    Gene(GeneKind, HashMap<String, Value>, Vec<Value>), // literal values are included
+    /// A value built as it is, e.g. a quoted one
+    Literal(Value),
+    Gene(Gene), // literal values are included
     GeneKind, // the gene kind may have to be compiled, this is the indicator/parent for it
     GeneProp(String),
     GeneDataChild(usize),
     Var(String),
     BinaryOp(String),
+    /// `&&` or `||`
+    LogicalOp(String),
+    Not,
     Assignment(String),
     If,
     IfPair,
@@ -581,18 +1860,40 @@ pub enum CompilableData {
     IfPairThen,
     IfElse,
     Function(String, Matcher, String),
+    Lambda(Matcher, String),
     Invocation,
     InvocationArguments(Vec<Value>),
-    While,
-    Break,
+    /// While(label), children: condition and body
+    While(Option<String>),
+    /// Break(label)
+    Break(Option<String>),
+    /// Continue(label)
+    Continue(Option<String>),
+    /// For(label, iterate props, pattern), children: collection and body
+    For(Option<String>, bool, Value),
+    /// Children: start, end and optional step
+    Range,
+    /// Children: the target and its accessors
+    Access,
+    /// A dynamic accessor has the key as its child
+    Accessor(Accessor),
+    /// Children: an Access and the value
+    AccessAssignment,
+    /// Children: the value and the clauses
+    Match,
+    /// MatchClause(pattern, has guard), children: the guard if any and the body statements
+    MatchClause(Pattern, bool),
+    Return,
+    /// Try(catch name, has finally), children: body, catch and finally statements
+    Try(Option<String>, bool),
+    Throw,
 }
 
 #[derive(Clone, Debug)]
-pub enum GeneKind {
-    Var,
-    If,
-    Function,
-    Invocation,
+pub enum Accessor {
+    Prop(String),
+    Item(usize),
+    Dynamic,
 }
 
 trait Normalize {
@@ -610,7 +1911,7 @@ impl Normalize for Gene {
         }
 
         match self.data[0] {
-            Value::Symbol(ref s) if is_binary_op(s) || s == "=" => {
+            Value::Symbol(ref s) if is_binary_op(s) || is_logical_op(s) || s == "=" => {
                 let kind = self.data[0].clone();
                 let mut data = vec![self.kind.clone()];
                 for (i, item) in self.data.iter().enumerate() {
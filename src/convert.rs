@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use ordered_float::OrderedFloat;
+
+use super::types::{Gene, Value};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConvertError {
+    pub message: String,
+    /// Field names / indexes leading to the value that failed to convert, outermost first
+    pub path: Vec<String>,
+}
+
+impl ConvertError {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        ConvertError {
+            message: message.into(),
+            path: Vec::new(),
+        }
+    }
+
+    pub fn mismatch(expected: &str, found: &Value) -> Self {
+        ConvertError::new(format!("expected {}, found {}", expected, found.to_canonical()))
+    }
+
+    /// Record that the error happened inside `segment` (a field name or an index)
+    pub fn at<S: ToString>(mut self, segment: S) -> Self {
+        self.path.insert(0, segment.to_string());
+        self
+    }
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if !self.path.is_empty() {
+            fmt.write_str(&self.path.join("."))?;
+            fmt.write_str(": ")?;
+        }
+        fmt.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+/// Convert a Rust value to a Gene value. Fails for values Gene can't represent,
+/// e.g. integers above `i64::MAX`.
+///
+/// Can be derived with `#[derive(ToGene)]` from the `gene_derive` crate.
+pub trait ToGene {
+    fn to_gene(&self) -> Result<Value, ConvertError>;
+}
+
+/// Convert a Gene value back to a Rust value.
+///
+/// Can be derived with `#[derive(FromGene)]` from the `gene_derive` crate.
+pub trait FromGene: Sized {
+    fn from_gene(value: &Value) -> Result<Self, ConvertError>;
+
+    /// Called for a prop or data item that is absent from the gene
+    fn from_missing() -> Result<Self, ConvertError> {
+        Err(ConvertError::new("missing value"))
+    }
+}
+
+/// Check that `value` is a gene whose kind is the symbol `kind`.
+/// Used by code generated with `#[derive(FromGene)]`.
+pub fn expect_gene<'a>(value: &'a Value, kind: &str) -> Result<&'a Gene, ConvertError> {
+    match value {
+        Value::Gene(gene) if gene.kind == Value::Symbol(kind.to_string()) => Ok(gene),
+        _ => Err(ConvertError::mismatch(&format!("({} ...)", kind), value)),
+    }
+}
+
+/// Kind of a gene, or the symbol itself. Used to dispatch on enum variants.
+pub fn kind_of(value: &Value) -> Option<&str> {
+    match value {
+        Value::Gene(gene) => match &gene.kind {
+            Value::Symbol(s) => Some(s),
+            _ => None,
+        },
+        Value::Symbol(s) => Some(s),
+        _ => None,
+    }
+}
+
+/// Convert the prop `name`, falling back to `FromGene::from_missing` when it is absent
+pub fn prop_from_gene<T: FromGene>(gene: &Gene, name: &str) -> Result<T, ConvertError> {
+    match gene.props.get(name) {
+        Some(v) => T::from_gene(v),
+        None => T::from_missing(),
+    }
+    .map_err(|e| e.at(name))
+}
+
+/// Convert the data item at `index`, falling back to `FromGene::from_missing` when it is absent
+pub fn data_from_gene<T: FromGene>(gene: &Gene, index: usize) -> Result<T, ConvertError> {
+    match gene.data.get(index) {
+        Some(v) => T::from_gene(v),
+        None => T::from_missing(),
+    }
+    .map_err(|e| e.at(index))
+}
+
+impl ToGene for Value {
+    fn to_gene(&self) -> Result<Value, ConvertError> {
+        Ok(self.clone())
+    }
+}
+
+impl FromGene for Value {
+    fn from_gene(value: &Value) -> Result<Self, ConvertError> {
+        Ok(value.clone())
+    }
+
+    fn from_missing() -> Result<Self, ConvertError> {
+        Ok(Value::Void)
+    }
+}
+
+impl ToGene for Gene {
+    fn to_gene(&self) -> Result<Value, ConvertError> {
+        Ok(Value::Gene(Box::new(self.clone())))
+    }
+}
+
+impl FromGene for Gene {
+    fn from_gene(value: &Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::Gene(gene) => Ok((**gene).clone()),
+            _ => Err(ConvertError::mismatch("gene", value)),
+        }
+    }
+}
+
+impl ToGene for bool {
+    fn to_gene(&self) -> Result<Value, ConvertError> {
+        Ok(Value::Boolean(*self))
+    }
+}
+
+impl FromGene for bool {
+    fn from_gene(value: &Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::Boolean(b) => Ok(*b),
+            _ => Err(ConvertError::mismatch("boolean", value)),
+        }
+    }
+}
+
+macro_rules! integer_conversions {
+    ($($t:ty),*) => {
+        $(
+            impl ToGene for $t {
+                fn to_gene(&self) -> Result<Value, ConvertError> {
+                    i64::try_from(*self).map(Value::Integer).map_err(|_| {
+                        ConvertError::new(format!("{} is out of range for i64", self))
+                    })
+                }
+            }
+
+            impl FromGene for $t {
+                fn from_gene(value: &Value) -> Result<Self, ConvertError> {
+                    match value {
+                        Value::Integer(i) => <$t>::try_from(*i).map_err(|_| {
+                            ConvertError::new(format!("{} is out of range for {}", i, stringify!($t)))
+                        }),
+                        _ => Err(ConvertError::mismatch("integer", value)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+integer_conversions!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+impl ToGene for f64 {
+    fn to_gene(&self) -> Result<Value, ConvertError> {
+        Ok(Value::Float(OrderedFloat(*self)))
+    }
+}
+
+impl FromGene for f64 {
+    fn from_gene(value: &Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::Float(f) => Ok(f.into_inner()),
+            Value::Integer(i) => Ok(*i as f64),
+            _ => Err(ConvertError::mismatch("float", value)),
+        }
+    }
+}
+
+impl ToGene for f32 {
+    fn to_gene(&self) -> Result<Value, ConvertError> {
+        Ok(Value::Float(OrderedFloat(f64::from(*self))))
+    }
+}
+
+impl FromGene for f32 {
+    fn from_gene(value: &Value) -> Result<Self, ConvertError> {
+        f64::from_gene(value).map(|f| f as f32)
+    }
+}
+
+impl ToGene for String {
+    fn to_gene(&self) -> Result<Value, ConvertError> {
+        Ok(Value::String(self.clone()))
+    }
+}
+
+impl ToGene for str {
+    fn to_gene(&self) -> Result<Value, ConvertError> {
+        Ok(Value::String(self.to_string()))
+    }
+}
+
+impl FromGene for String {
+    fn from_gene(value: &Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::String(s) => Ok(s.clone()),
+            _ => Err(ConvertError::mismatch("string", value)),
+        }
+    }
+}
+
+impl<T: ToGene> ToGene for Vec<T> {
+    fn to_gene(&self) -> Result<Value, ConvertError> {
+        self.iter()
+            .enumerate()
+            .map(|(i, item)| item.to_gene().map_err(|e| e.at(i)))
+            .collect::<Result<_, _>>()
+            .map(Value::Array)
+    }
+}
+
+impl<T: FromGene> FromGene for Vec<T> {
+    fn from_gene(value: &Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::Array(items) => items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| T::from_gene(item).map_err(|e| e.at(i)))
+                .collect(),
+            _ => Err(ConvertError::mismatch("array", value)),
+        }
+    }
+}
+
+impl<T: ToGene> ToGene for HashMap<String, T> {
+    fn to_gene(&self) -> Result<Value, ConvertError> {
+        self.iter()
+            .map(|(k, v)| v.to_gene().map(|v| (k.clone(), v)).map_err(|e| e.at(k)))
+            .collect::<Result<_, _>>()
+            .map(Value::Map)
+    }
+}
+
+impl<T: FromGene> FromGene for HashMap<String, T> {
+    fn from_gene(value: &Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::Map(map) => map
+                .iter()
+                .map(|(k, v)| T::from_gene(v).map(|v| (k.clone(), v)).map_err(|e| e.at(k)))
+                .collect(),
+            _ => Err(ConvertError::mismatch("map", value)),
+        }
+    }
+}
+
+/// `None` is converted to `null`; `null`, `()` and a missing prop or data item become `None`
+impl<T: ToGene> ToGene for Option<T> {
+    fn to_gene(&self) -> Result<Value, ConvertError> {
+        match self {
+            Some(v) => v.to_gene(),
+            None => Ok(Value::Null),
+        }
+    }
+}
+
+impl<T: FromGene> FromGene for Option<T> {
+    fn from_gene(value: &Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::Null | Value::Void => Ok(None),
+            _ => T::from_gene(value).map(Some),
+        }
+    }
+
+    fn from_missing() -> Result<Self, ConvertError> {
+        Ok(None)
+    }
+}
+
+impl<T: ToGene> ToGene for Box<T> {
+    fn to_gene(&self) -> Result<Value, ConvertError> {
+        (**self).to_gene()
+    }
+}
+
+impl<T: FromGene> FromGene for Box<T> {
+    fn from_gene(value: &Value) -> Result<Self, ConvertError> {
+        T::from_gene(value).map(Box::new)
+    }
+}
+
+impl From<Gene> for Value {
+    fn from(gene: Gene) -> Value {
+        Value::Gene(Box::new(gene))
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+macro_rules! from_conversions {
+    ($($t:ty => $variant:ident),*) => {
+        $(
+            impl From<$t> for Value {
+                fn from(v: $t) -> Value {
+                    Value::$variant(v.into())
+                }
+            }
+        )*
+    };
+}
+
+from_conversions!(bool => Boolean, i32 => Integer, i64 => Integer, f64 => Float, String => String);
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(v: Vec<T>) -> Value {
+        Value::Array(v.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<HashMap<String, T>> for Value {
+    fn from(m: HashMap<String, T>) -> Value {
+        Value::Map(m.into_iter().map(|(k, v)| (k, v.into())).collect())
+    }
+}
+
+/// `None` becomes `null`
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Value {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+macro_rules! try_from_conversions {
+    ($($t:ty),*) => {
+        $(
+            impl TryFrom<Value> for $t {
+                type Error = ConvertError;
+
+                fn try_from(v: Value) -> Result<Self, ConvertError> {
+                    <$t>::from_gene(&v)
+                }
+            }
+        )*
+    };
+}
+
+try_from_conversions!(bool, i32, i64, f64, String, Gene);
+
+impl<T: FromGene> TryFrom<Value> for Vec<T> {
+    type Error = ConvertError;
+
+    fn try_from(v: Value) -> Result<Self, ConvertError> {
+        Vec::<T>::from_gene(&v)
+    }
+}
+
+impl<T: FromGene> TryFrom<Value> for HashMap<String, T> {
+    type Error = ConvertError;
+
+    fn try_from(v: Value) -> Result<Self, ConvertError> {
+        HashMap::<String, T>::from_gene(&v)
+    }
+}
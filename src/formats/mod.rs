@@ -0,0 +1,133 @@
+//! Conversion between Gene values and other data formats.
+//!
+//! Arrays, maps, strings, numbers and booleans map to their natural counterparts.
+//! Values that the other formats have no notion of are encoded as maps with
+//! reserved keys:
+//!
+//! * a gene is a map with the kind under `#gene`, its data under `#data` (left out
+//!   when empty) and its props as the other entries, e.g. `(port ^protocol "tcp" 80)`
+//!   becomes `{"#gene": "port", "protocol": "tcp", "#data": [80]}` (the kind is
+//!   written as a string when it is a symbol)
+//! * a symbol anywhere else is `{"#symbol": "name"}`
+//! * a stream is written as an array
+//!
+//! Keys of maps and props that start with `#` get another `#` in front, so that
+//! a map with a `#gene` key is not read back as a gene: `{^#gene 1}` becomes
+//! `{"##gene": 1}`. When decoding, one `#` is removed from keys that start with `##`.
+//!
+//! Gene maps are unordered, so keys are written in sorted order. Key order is kept when
+//! converting directly between YAML and TOML with `convert`.
+
+pub mod toml;
+pub mod yaml;
+
+use std::collections::HashMap;
+
+use super::canonical::to_document;
+use super::convert::ConvertError;
+use super::parser::Parser;
+use super::types::{Gene, Value};
+
+pub const GENE_KEY: &str = "#gene";
+pub const DATA_KEY: &str = "#data";
+pub const SYMBOL_KEY: &str = "#symbol";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Gene,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Format, ConvertError> {
+        match name.to_lowercase().as_str() {
+            "gene" => Ok(Format::Gene),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "toml" => Ok(Format::Toml),
+            _ => Err(ConvertError::new(format!("unknown format {}, expected gene, yaml or toml", name))),
+        }
+    }
+}
+
+/// Convert a document from one format to another
+pub fn convert(input: &str, from: Format, to: Format) -> Result<String, ConvertError> {
+    match (from, to) {
+        (Format::Yaml, Format::Toml) => {
+            let value: serde_yaml::Value = serde_yaml::from_str(input).map_err(yaml::error)?;
+            ::toml::to_string(&value).map_err(self::toml::error)
+        }
+        (Format::Toml, Format::Yaml) => {
+            let value: ::toml::Value = input.parse().map_err(self::toml::error)?;
+            serde_yaml::to_string(&value).map_err(yaml::error)
+        }
+        _ => {
+            let value = match from {
+                Format::Gene => Parser::new(input)
+                    .parse()
+                    .map_err(|e| ConvertError::new(e.message))?,
+                Format::Yaml => yaml::from_str(input)?,
+                Format::Toml => self::toml::from_str(input)?,
+            };
+            match to {
+                Format::Gene => Ok(to_document(&value)),
+                Format::Yaml => yaml::to_string(&value),
+                Format::Toml => self::toml::to_string(&value),
+            }
+        }
+    }
+}
+
+/// Turn a decoded map back into a gene or symbol if it uses one of the reserved keys
+fn decode_map(mut map: HashMap<String, Value>) -> Result<Value, ConvertError> {
+    if let Some(kind) = map.remove(GENE_KEY) {
+        let kind = match kind {
+            Value::String(s) => Value::Symbol(s),
+            v => v,
+        };
+        let data = match map.remove(DATA_KEY) {
+            Some(Value::Array(data)) => data,
+            Some(v) => return Err(ConvertError::mismatch("array", &v).at(DATA_KEY)),
+            None => Vec::new(),
+        };
+        Ok(Value::Gene(Box::new(Gene { kind, props: unescape_keys(map), data })))
+    } else if map.len() == 1 && map.contains_key(SYMBOL_KEY) {
+        match map.remove(SYMBOL_KEY).unwrap() {
+            Value::String(s) => Ok(Value::Symbol(s)),
+            v => Err(ConvertError::mismatch("string", &v).at(SYMBOL_KEY)),
+        }
+    } else {
+        Ok(Value::Map(unescape_keys(map)))
+    }
+}
+
+fn unescape_keys(map: HashMap<String, Value>) -> HashMap<String, Value> {
+    map.into_iter()
+        .map(|(key, value)| match key.strip_prefix('#') {
+            Some(unescaped) if unescaped.starts_with('#') => (unescaped.to_string(), value),
+            _ => (key, value),
+        })
+        .collect()
+}
+
+/// Entries used to encode a gene as a map, see the module documentation
+fn encode_gene(gene: &Gene) -> Vec<(String, Value)> {
+    let kind = match &gene.kind {
+        Value::Symbol(s) => Value::String(s.clone()),
+        v => v.clone(),
+    };
+    let mut entries = vec![(GENE_KEY.to_string(), kind)];
+    entries.extend(sorted(&gene.props));
+    if !gene.data.is_empty() {
+        entries.push((DATA_KEY.to_string(), Value::Array(gene.data.clone())));
+    }
+    entries
+}
+
+/// Entries of a map or props sorted by key, with the keys escaped, see the module documentation
+fn sorted(map: &HashMap<String, Value>) -> Vec<(String, Value)> {
+    let escape = |key: &String| if key.starts_with('#') { format!("#{}", key) } else { key.clone() };
+    let mut entries: Vec<(String, Value)> = map.iter().map(|(k, v)| (escape(k), v.clone())).collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use ordered_float::OrderedFloat;
+
+use super::super::convert::ConvertError;
+use super::super::types::Value;
+use super::{decode_map, encode_gene, sorted, SYMBOL_KEY};
+
+pub fn from_str(s: &str) -> Result<Value, ConvertError> {
+    let toml: ::toml::Value = s.parse().map_err(error)?;
+    from_toml(&toml)
+}
+
+/// A TOML document is a table, so only maps and genes can be written at the top level
+pub fn to_string(value: &Value) -> Result<String, ConvertError> {
+    match to_toml(value)? {
+        table @ ::toml::Value::Table(_) => ::toml::to_string(&table).map_err(error),
+        _ => Err(ConvertError::mismatch("map or gene at the top of a TOML document", value)),
+    }
+}
+
+/// Datetimes are converted to strings
+pub fn from_toml(toml: &::toml::Value) -> Result<Value, ConvertError> {
+    match toml {
+        ::toml::Value::Boolean(b) => Ok(Value::Boolean(*b)),
+        ::toml::Value::Integer(i) => Ok(Value::Integer(*i)),
+        ::toml::Value::Float(f) => Ok(Value::Float(OrderedFloat(*f))),
+        ::toml::Value::String(s) => Ok(Value::String(s.clone())),
+        ::toml::Value::Datetime(d) => Ok(Value::String(d.to_string())),
+        ::toml::Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| from_toml(item).map_err(|e| e.at(i)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+        ::toml::Value::Table(table) => {
+            let mut map = HashMap::new();
+            for (key, value) in table.iter() {
+                let value = from_toml(value).map_err(|e| e.at(key))?;
+                map.insert(key.clone(), value);
+            }
+            decode_map(map)
+        }
+    }
+}
+
+/// TOML has no null, so `null` and `()` can not be converted
+pub fn to_toml(value: &Value) -> Result<::toml::Value, ConvertError> {
+    match value {
+        Value::Void | Value::Null => Err(ConvertError::new("TOML can not represent null")),
+        Value::Boolean(b) => Ok(::toml::Value::Boolean(*b)),
+        Value::Integer(i) => Ok(::toml::Value::Integer(*i)),
+        Value::Float(f) => Ok(::toml::Value::Float(f.into_inner())),
+        Value::String(s) => Ok(::toml::Value::String(s.clone())),
+        Value::Symbol(s) => table(vec![(SYMBOL_KEY.to_string(), Value::String(s.clone()))]),
+        Value::Array(items) | Value::Stream(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| to_toml(item).map_err(|e| e.at(i)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(::toml::Value::Array),
+        Value::Map(map) => table(sorted(map)),
+        Value::Gene(gene) => table(encode_gene(gene)),
+    }
+}
+
+fn table(entries: Vec<(String, Value)>) -> Result<::toml::Value, ConvertError> {
+    let mut table = ::toml::value::Table::new();
+    for (key, value) in entries {
+        let value = to_toml(&value).map_err(|e| e.at(&key))?;
+        table.insert(key, value);
+    }
+    Ok(::toml::Value::Table(table))
+}
+
+pub(super) fn error<E: std::fmt::Display>(e: E) -> ConvertError {
+    ConvertError::new(format!("TOML: {}", e))
+}
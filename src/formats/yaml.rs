@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use ordered_float::OrderedFloat;
+use serde_yaml::{Mapping, Number};
+
+use super::super::convert::ConvertError;
+use super::super::types::Value;
+use super::{decode_map, encode_gene, sorted, SYMBOL_KEY};
+
+pub fn from_str(s: &str) -> Result<Value, ConvertError> {
+    let yaml: serde_yaml::Value = serde_yaml::from_str(s).map_err(error)?;
+    from_yaml(&yaml)
+}
+
+pub fn to_string(value: &Value) -> Result<String, ConvertError> {
+    serde_yaml::to_string(&to_yaml(value)?).map_err(error)
+}
+
+pub fn from_yaml(yaml: &serde_yaml::Value) -> Result<Value, ConvertError> {
+    match yaml {
+        serde_yaml::Value::Null => Ok(Value::Null),
+        serde_yaml::Value::Bool(b) => Ok(Value::Boolean(*b)),
+        serde_yaml::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Ok(Value::Integer(i))
+            } else if n.is_u64() {
+                Err(ConvertError::new(format!("{} is out of range for i64", n)))
+            } else {
+                Ok(Value::Float(OrderedFloat(n.as_f64().unwrap())))
+            }
+        }
+        serde_yaml::Value::String(s) => Ok(Value::String(s.clone())),
+        serde_yaml::Value::Sequence(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| from_yaml(item).map_err(|e| e.at(i)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+        serde_yaml::Value::Mapping(mapping) => {
+            let mut map = HashMap::new();
+            for (key, value) in mapping.iter() {
+                let key = match key {
+                    serde_yaml::Value::String(s) => s.clone(),
+                    serde_yaml::Value::Number(n) => n.to_string(),
+                    serde_yaml::Value::Bool(b) => b.to_string(),
+                    serde_yaml::Value::Null => "null".to_string(),
+                    _ => return Err(ConvertError::new("map keys must be strings, numbers or booleans")),
+                };
+                let value = from_yaml(value).map_err(|e| e.at(&key))?;
+                map.insert(key, value);
+            }
+            decode_map(map)
+        }
+    }
+}
+
+pub fn to_yaml(value: &Value) -> Result<serde_yaml::Value, ConvertError> {
+    match value {
+        Value::Void | Value::Null => Ok(serde_yaml::Value::Null),
+        Value::Boolean(b) => Ok(serde_yaml::Value::Bool(*b)),
+        Value::Integer(i) => Ok(serde_yaml::Value::Number(Number::from(*i))),
+        Value::Float(f) => Ok(serde_yaml::Value::Number(Number::from(f.into_inner()))),
+        Value::String(s) => Ok(serde_yaml::Value::String(s.clone())),
+        Value::Symbol(s) => mapping(vec![(SYMBOL_KEY.to_string(), Value::String(s.clone()))]),
+        Value::Array(items) | Value::Stream(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| to_yaml(item).map_err(|e| e.at(i)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(serde_yaml::Value::Sequence),
+        Value::Map(map) => mapping(sorted(map)),
+        Value::Gene(gene) => mapping(encode_gene(gene)),
+    }
+}
+
+fn mapping(entries: Vec<(String, Value)>) -> Result<serde_yaml::Value, ConvertError> {
+    let mut mapping = Mapping::new();
+    for (key, value) in entries {
+        let value = to_yaml(&value).map_err(|e| e.at(&key))?;
+        mapping.insert(serde_yaml::Value::String(key), value);
+    }
+    Ok(serde_yaml::Value::Mapping(mapping))
+}
+
+pub(super) fn error(e: serde_yaml::Error) -> ConvertError {
+    ConvertError::new(format!("YAML: {}", e))
+}
@@ -1,15 +1,33 @@
 extern crate gene;
 
-use gene::compiler2::Compiler;
-use gene::parser::Parser;
-use gene::types::Value;
-use gene::vm::VirtualMachine;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+use std::time::Instant;
+
+use gene::formats::{self, Format};
+use gene::runtime::Runtime;
+
+const CONVERT_USAGE: &str = "Usage: gene convert --from <gene|yaml|toml> --to <gene|yaml|toml> [FILE]
+
+Converts FILE, or stdin, and writes to stdout. Key order is kept between YAML
+and TOML. Gene maps are unordered: the Gene form is canonical, with keys sorted,
+and so is YAML or TOML converted from it.";
 
 fn main() {
-    let mut compiler = Compiler::new();
-    let mut vm = VirtualMachine::new();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("convert") {
+        if let Err(message) = convert(&args[1..]) {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+        return;
+    }
 
-    let mut parser = Parser::new("
+    let mut runtime = Runtime::new();
+    let start_time = Instant::now();
+    let result = runtime.eval("
       (fn fibonacci n
         (if (n < 2)
           n
@@ -19,11 +37,52 @@ fn main() {
       )
       (fibonacci 24)
     ");
-    let parsed = parser.parse();
-    compiler.compile(parsed.unwrap());
-    let module = compiler.module;
-    let result_temp = vm.load_module(&module);
-    let borrowed = result_temp.borrow();
-    let result = borrowed.downcast_ref::<Value>().unwrap();
-    println!("Result: {}", result);
+    match result {
+        Ok(result) => println!("Result: {}", result),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+    println!("Execution time: {:.6} seconds", start_time.elapsed().as_nanos() as f64 / 1_000_000_000.);
+}
+
+/// gene convert --from yaml --to gene [FILE]
+/// Reads from stdin when no file is given and writes to stdout.
+fn convert(args: &[String]) -> Result<(), String> {
+    let mut from = None;
+    let mut to = None;
+    let mut file = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--from" | "--to" => {
+                let name = iter.next().ok_or(CONVERT_USAGE)?;
+                let format = Format::from_name(name).map_err(|e| e.to_string())?;
+                if arg == "--from" {
+                    from = Some(format);
+                } else {
+                    to = Some(format);
+                }
+            }
+            _ if file.is_none() && !arg.starts_with("--") => file = Some(arg.clone()),
+            _ => return Err(CONVERT_USAGE.to_string()),
+        }
+    }
+    let (from, to) = match (from, to) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return Err(CONVERT_USAGE.to_string()),
+    };
+
+    let input = match file {
+        Some(path) => fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?,
+        None => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input).map_err(|e| e.to_string())?;
+            input
+        }
+    };
+    let output = formats::convert(&input, from, to).map_err(|e| e.to_string())?;
+    println!("{}", output.trim_end());
+    Ok(())
 }
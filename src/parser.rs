@@ -15,6 +15,10 @@ pub struct Parser<'a> {
     chars: CharIndices<'a>,
     pos: Option<usize>,
     chr: Option<char>,
+    /// Only collected by `parse_with_spans`
+    spans: Option<SourceMap>,
+    /// Path of the value being read
+    path: Vec<PathSegment>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -35,22 +39,28 @@ impl<'a> Parser<'a> {
             chars: str.char_indices(),
             pos: None,
             chr: None,
+            spans: None,
+            path: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<Value, Error> {
+    pub fn parse(&mut self) -> Result<Value, Error<'a>> {
         let mut result = Vec::<Value>::new();
 
         loop {
-            let item = self.read();
-            if let Some(value) = item {
-                result.push(value.unwrap());
-            } else {
-                break;
+            match self.read_at(PathSegment::Item(result.len())) {
+                Some(value) => result.push(value?),
+                // Something that can't start a value, like a `)` that closes nothing
+                None if self.chr.is_some() => return Err(Error::new("unexpected character")),
+                None => break,
             }
         }
 
         if result.len() == 1 {
+            // The only value is returned as is, so its path is empty instead of [0]
+            if let Some(spans) = &mut self.spans {
+                spans.spans = spans.spans.drain().map(|(path, span)| (path[1..].to_vec(), span)).collect();
+            }
             let first = result[0].clone();
             Ok(first)
         } else {
@@ -58,12 +68,38 @@ impl<'a> Parser<'a> {
         }
     }
 
-    pub fn read(&mut self) -> Option<Result<Value, Error>> {
+    /// Parse and also return where every value is in the source
+    pub fn parse_with_spans(&mut self) -> Result<(Value, SourceMap), Error<'a>> {
+        self.spans = Some(SourceMap::default());
+        let value = self.parse()?;
+        Ok((value, self.spans.take().unwrap_or_default()))
+    }
+
+    pub fn read(&mut self) -> Option<Result<Value, Error<'a>>> {
         self.start();
 
         // Will stop after hitting first non-whitespace char
         self.skip_whitespaces();
 
+        let start = self.pos?;
+        let result = self.read_value();
+        if let (Some(spans), Some(Ok(_))) = (&mut self.spans, &result) {
+            let end = self.pos.unwrap_or(self.str.len());
+            // A comment is read before the value that follows it, which is recorded already
+            spans.spans.entry(self.path.clone()).or_insert(Span { start, end });
+        }
+        result
+    }
+
+    /// Read the value at `segment` of the value being read
+    fn read_at(&mut self, segment: PathSegment) -> Option<Result<Value, Error<'a>>> {
+        self.path.push(segment);
+        let result = self.read();
+        self.path.pop();
+        result
+    }
+
+    fn read_value(&mut self) -> Option<Result<Value, Error<'a>>> {
         let ch = self.chr?;
         if ch == '(' {
             self.next();
@@ -74,24 +110,28 @@ impl<'a> Parser<'a> {
             loop {
                 self.skip_whitespaces();
 
-                if self.chr.unwrap() == ')' {
-                    self.next();
-                    break;
-                } else if self.chr.unwrap() == '^' {
-                    let result = self.read_pair();
-                    if let Some(v) = result {
-                        let pair = v.unwrap();
+                match self.chr {
+                    Some(')') => {
+                        self.next();
+                        break;
+                    }
+                    Some('^') => {
+                        let pair = match self.read_pair() {
+                            Ok(pair) => pair,
+                            Err(e) => return Some(Err(e)),
+                        };
                         props.insert(pair.key, pair.val);
                     }
-                } else {
-                    let result = self.read();
-                    if let Some(v) = result {
-                        let val = v.unwrap();
-                        if kind_is_set {
-                            data.push(val);
-                        } else {
-                            kind_is_set = true;
-                            kind = val;
+                    _ => {
+                        let segment = if kind_is_set { PathSegment::Item(data.len()) } else { PathSegment::Kind };
+                        match self.read_item(segment, ')') {
+                            Ok(Some(val)) if kind_is_set => data.push(val),
+                            Ok(Some(val)) => {
+                                kind_is_set = true;
+                                kind = val;
+                            }
+                            Ok(None) => (),
+                            Err(e) => return Some(Err(e)),
                         }
                     }
                 }
@@ -107,14 +147,14 @@ impl<'a> Parser<'a> {
             loop {
                 self.skip_whitespaces();
 
-                if self.chr.unwrap() == ']' {
+                if self.chr == Some(']') {
                     self.next();
                     break;
-                } else {
-                    let val = self.read();
-                    if let Some(v) = val {
-                        arr.push(v.unwrap());
-                    }
+                }
+                match self.read_item(PathSegment::Item(arr.len()), ']') {
+                    Ok(Some(val)) => arr.push(val),
+                    Ok(None) => (),
+                    Err(e) => return Some(Err(e)),
                 }
             }
             return Some(Ok(Value::Array(arr)));
@@ -124,15 +164,18 @@ impl<'a> Parser<'a> {
             loop {
                 self.skip_whitespaces();
 
-                if self.chr.unwrap() == '}' {
-                    self.next();
-                    break;
-                } else {
-                    let result = self.read_pair();
-                    if let Some(v) = result {
-                        let pair = v.unwrap();
-                        map.insert(pair.key, pair.val);
+                match self.chr {
+                    Some('}') => {
+                        self.next();
+                        break;
                     }
+                    None => return Some(Err(Error::new("unexpected end of input, expected }"))),
+                    Some(_) => match self.read_pair() {
+                        Ok(pair) => {
+                            map.insert(pair.key, pair.val);
+                        }
+                        Err(e) => return Some(Err(e)),
+                    },
                 }
             }
             return Some(Ok(Value::Map(map)));
@@ -140,28 +183,32 @@ impl<'a> Parser<'a> {
             self.next();
             return self.read_string();
         } else if ch == '#' {
-            let next_ch = self.peek().unwrap();
-            if is_whitespace(next_ch) || next_ch == '!' {
-                self.next();
-                self.advance_while(|ch| ch != '\n');
-                return self.read();
-            } else {
-                return Some(Ok(Value::Symbol(self.read_word().unwrap().unwrap())));
+            match self.peek() {
+                Some(next_ch) if !is_whitespace(next_ch) && next_ch != '!' => {
+                    return Some(Ok(Value::Symbol(self.read_word())));
+                }
+                _ => {
+                    self.next();
+                    self.advance_while(|ch| ch != '\n');
+                    return self.read();
+                }
             }
         } else if ch == '+' || ch == '-' {
-            let next = self.peek();
-            if next.is_some() && next.unwrap().is_digit(10) {
+            if self.peek().is_some_and(|next| next.is_ascii_digit()) {
                 return self.read_number();
             } else {
                 return self.read_keyword_or_symbol();
             }
-        } else if ch.is_digit(10) {
+        } else if ch.is_ascii_digit() {
             return self.read_number();
         } else if ch == '`' {
             self.next();
             let mut gene = Gene::new(Value::Symbol("#QUOTE".to_string()));
-            gene.data
-                .push(self.read().unwrap().unwrap());
+            match self.read_at(PathSegment::Item(0)) {
+                Some(Ok(value)) => gene.data.push(value),
+                Some(Err(e)) => return Some(Err(e)),
+                None => return Some(Err(Error::new("expected a value after `"))),
+            }
             return Some(Ok(Value::Gene(Box::new(gene))));
         } else if is_symbol_head(ch) {
             return self.read_keyword_or_symbol();
@@ -170,26 +217,42 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn read_number(&mut self) -> Option<Result<Value, Error>> {
+    /// Read an item of a collection that ends with `end`. `None` is returned
+    /// when there was only a comment before `end`.
+    fn read_item(&mut self, segment: PathSegment, end: char) -> Result<Option<Value>, Error<'a>> {
+        match self.read_at(segment) {
+            Some(result) => result.map(Some),
+            None => match self.chr {
+                Some(ch) if ch == end => Ok(None),
+                Some(_) => Err(Error::new("unexpected character")),
+                None => Err(Error::new("unexpected end of input")),
+            },
+        }
+    }
+
+    fn read_number(&mut self) -> Option<Result<Value, Error<'a>>> {
         let start = self.pos.unwrap();
         let end = self.advance_while(|ch| !is_whitespace(ch) && !is_sep(ch));
         let s = &self.str[start..end];
         if s.contains('.') {
-            let number = s.parse::<f64>().unwrap();
-            return Some(Ok(Value::Float(OrderedFloat(number))));
+            match s.parse::<f64>() {
+                Ok(number) => Some(Ok(Value::Float(OrderedFloat(number)))),
+                Err(_) => Some(Err(Error::new("invalid number"))),
+            }
         } else {
-            let number = s.parse::<i64>().unwrap();
-            return Some(Ok(Value::Integer(number)));
+            match s.parse::<i64>() {
+                Ok(number) => Some(Ok(Value::Integer(number))),
+                Err(_) => Some(Err(Error::new("invalid number"))),
+            }
         }
     }
 
-    fn read_string(&mut self) -> Option<Result<Value, Error>> {
+    fn read_string(&mut self) -> Option<Result<Value, Error<'a>>> {
         let mut result = String::from("");
 
         let mut escaped = false;
 
-        loop {
-            let ch = self.chr.unwrap();
+        while let Some(ch) = self.chr {
             if ch == '\\' {
                 // Do not treat whitespace, ()[]{} etc as special char
                 escaped = true;
@@ -198,28 +261,22 @@ impl<'a> Parser<'a> {
                 result.push(ch);
             } else if ch == '"' {
                 self.next();
-                break;
+                return Some(Ok(Value::String(result)));
             } else {
                 result.push(ch);
             }
 
             // Move forward
-            if self.next().is_none() {
-                break;
-            }
+            self.next();
         }
 
-        Some(Ok(Value::String(result.to_string())))
+        Some(Err(Error::new("unterminated string")))
     }
 
-    fn read_keyword_or_symbol(&mut self) -> Option<Result<Value, Error>> {
-        let is_escape = self.chr.unwrap() == '\\';
+    fn read_keyword_or_symbol(&mut self) -> Option<Result<Value, Error<'a>>> {
+        let is_escape = self.chr == Some('\\');
 
-        let mut s = String::from("");
-        let word = self.read_word();
-        if let Some(v) = word {
-            s.push_str(&v.unwrap());
-        }
+        let s = self.read_word();
 
         if is_escape {
             return Some(Ok(Value::Symbol(s)));
@@ -233,13 +290,12 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn read_word(&mut self) -> Option<Result<String, Error>> {
+    fn read_word(&mut self) -> String {
         let mut result = String::from("");
 
         let mut escaped = false;
 
-        loop {
-            let ch = self.chr.unwrap();
+        while let Some(ch) = self.chr {
             if ch == '\\' {
                 // Do not treat whitespace, ()[]{} etc as special char
                 escaped = true;
@@ -253,34 +309,33 @@ impl<'a> Parser<'a> {
             }
 
             // Move forward
-            if self.next().is_none() {
-                break;
-            }
+            self.next();
         }
 
-        Some(Ok(result.to_string()))
+        result
     }
 
-    fn read_pair(&mut self) -> Option<Result<Pair, Error>> {
-        if self.chr.unwrap() != '^' {
-            return Some(Err(Error::new("Error")));
-        } else {
-            self.next();
-            let ch = self.chr.unwrap();
-            if ch == '^' {
+    fn read_pair(&mut self) -> Result<Pair, Error<'a>> {
+        if self.chr != Some('^') {
+            return Err(Error::new("expected ^name"));
+        }
+        self.next();
+        match self.chr {
+            Some('^') => {
                 self.next();
-                let key = self.read_word().unwrap().unwrap();
-                let val = Value::Boolean(true);
-                return Some(Ok(Pair::new(key, val)));
-            } else if ch == '!' {
+                Ok(Pair::new(self.read_word(), Value::Boolean(true)))
+            }
+            Some('!') => {
                 self.next();
-                let key = self.read_word().unwrap().unwrap();
-                let val = Value::Boolean(false);
-                return Some(Ok(Pair::new(key, val)));
-            } else {
-                let key = self.read_word().unwrap().unwrap();
-                let val = self.read().unwrap().unwrap();
-                return Some(Ok(Pair::new(key, val)));
+                Ok(Pair::new(self.read_word(), Value::Boolean(false)))
+            }
+            _ => {
+                let key = self.read_word();
+                match self.read_at(PathSegment::Prop(key.clone())) {
+                    Some(Ok(val)) => Ok(Pair::new(key, val)),
+                    Some(Err(e)) => Err(e),
+                    None => Err(Error::new("expected a value after ^name")),
+                }
             }
         }
     }
@@ -332,15 +387,86 @@ impl<'a> Parser<'a> {
     // }
 }
 
+/// Byte offsets into the source text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A step from a value to one inside it
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    /// Item of a stream or an array, or data item of a gene
+    Item(usize),
+    Kind,
+    /// Prop of a gene or value in a map
+    Prop(String),
+}
+
+/// Where the parsed values are in the source, by their path from the value
+/// returned by the parser
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceMap {
+    spans: HashMap<Vec<PathSegment>, Span>,
+}
+
+impl SourceMap {
+    pub fn get(&self, path: &[PathSegment]) -> Option<Span> {
+        self.spans.get(path).copied()
+    }
+
+    /// Spans of the values equal to `form` in `root`, the value parsed with
+    /// this map, in source order
+    pub fn find(&self, root: &Value, form: &Value) -> Vec<Span> {
+        let mut found = Vec::new();
+        self.find_in(root, form, &mut Vec::new(), &mut found);
+        found.sort_by_key(|span| span.start);
+        found
+    }
+
+    fn find_in(&self, value: &Value, form: &Value, path: &mut Vec<PathSegment>, found: &mut Vec<Span>) {
+        // A value can't contain another one equal to it
+        if value == form {
+            found.extend(self.get(path));
+            return;
+        }
+        let mut visit = |segment: PathSegment, child: &Value, path: &mut Vec<PathSegment>| {
+            path.push(segment);
+            self.find_in(child, form, path, found);
+            path.pop();
+        };
+        match value {
+            Value::Stream(items) | Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    visit(PathSegment::Item(i), item, path);
+                }
+            }
+            Value::Map(map) => {
+                for (key, v) in map.iter() {
+                    visit(PathSegment::Prop(key.clone()), v, path);
+                }
+            }
+            Value::Gene(gene) => {
+                visit(PathSegment::Kind, &gene.kind, path);
+                for (key, v) in gene.props.iter() {
+                    visit(PathSegment::Prop(key.clone()), v, path);
+                }
+                for (i, item) in gene.data.iter().enumerate() {
+                    visit(PathSegment::Item(i), item, path);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
 pub fn is_whitespace(ch: char) -> bool {
     ch.is_whitespace() || ch == ','
 }
 
 pub fn is_sep(ch: char) -> bool {
-    match ch {
-        '(' | ')' | '[' | ']' | '{' | '}' => true,
-        _ => false,
-    }
+    matches!(ch, '(' | ')' | '[' | ']' | '{' | '}')
 }
 
 pub fn is_symbol_head(ch: char) -> bool {
@@ -348,12 +474,9 @@ pub fn is_symbol_head(ch: char) -> bool {
         return false;
     }
 
-    if ch.is_digit(10) {
+    if ch.is_ascii_digit() {
         return false;
     }
 
-    match ch {
-        '^' | '"' | '\'' | '(' | ')' | '[' | ']' | '{' | '}' => false,
-        _ => true,
-    }
+    !matches!(ch, '^' | '"' | '\'' | '(' | ')' | '[' | ']' | '{' | '}')
 }
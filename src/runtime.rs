@@ -0,0 +1,102 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::compiler2::{CompileError, Compiler};
+use super::parser::Parser;
+use super::types::Value;
+use super::vm::error::RuntimeError;
+use super::vm::types::Arguments;
+use super::vm::VirtualMachine;
+
+/// Entry point for embedding Gene: parses, compiles and runs source text on a VM
+/// that is kept across calls, so variables and functions defined by one `eval`
+/// can be used by the next.
+///
+/// ```
+/// use gene::runtime::Runtime;
+/// use gene::types::Value;
+///
+/// let mut runtime = Runtime::new();
+/// runtime.set_global("x", Value::Integer(1));
+/// runtime.eval("(fn inc a (a + 1))").unwrap();
+/// assert_eq!(runtime.eval("(inc x)").unwrap(), Value::Integer(2));
+/// ```
+pub struct Runtime {
+    pub vm: VirtualMachine,
+}
+
+#[derive(Debug)]
+pub enum EvalError {
+    Io(io::Error),
+    Parse(String),
+    Compile(Vec<CompileError>),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Io(e) => write!(fmt, "{}", e),
+            EvalError::Parse(message) => write!(fmt, "parse error: {}", message),
+            EvalError::Compile(errors) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                fmt.write_str(&messages.join("\n"))
+            }
+            EvalError::Runtime(e) => write!(fmt, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl From<RuntimeError> for EvalError {
+    fn from(e: RuntimeError) -> Self {
+        EvalError::Runtime(e)
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        Runtime {
+            vm: VirtualMachine::new(),
+        }
+    }
+
+    pub fn eval(&mut self, source: &str) -> Result<Value, EvalError> {
+        let (parsed, spans) = Parser::new(source)
+            .parse_with_spans()
+            .map_err(|e| EvalError::Parse(e.message.to_string()))?;
+        let mut compiler = Compiler::new();
+        compiler.compile_with_spans(parsed, &spans).map_err(EvalError::Compile)?;
+        Ok(self.vm.load_module(&compiler.module)?)
+    }
+
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Value, EvalError> {
+        let source = fs::read_to_string(path).map_err(EvalError::Io)?;
+        self.eval(&source)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.vm.get_global(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.vm.set_global(name, value);
+    }
+
+    /// Call the top level function `name`
+    pub fn call(&mut self, name: &str, data: Vec<Value>) -> Result<Value, EvalError> {
+        let function = self.vm.lookup(name).ok_or_else(|| RuntimeError::undefined_variable(name))?;
+        Ok(self.vm.call(&function, Arguments::from_data(data))?)
+    }
+
+    /// See `VirtualMachine::register_native`
+    pub fn register_native<F>(&mut self, name: &str, body: F)
+    where
+        F: Fn(&mut VirtualMachine, Arguments) -> Result<Value, RuntimeError> + 'static,
+    {
+        self.vm.register_native(name, body);
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::canonical::to_document;
+use super::parser::Parser;
+use super::types::{Gene, Value};
+
+/// A Gene document with placeholders that is rendered against a context map.
+///
+/// * `%name` is replaced with the context value `name`, `%a.b.0` walks into maps,
+///   gene props and arrays / gene data
+/// * `(%for x in %items body...)` renders `body` once per item of an array (`x` is the item),
+///   or per entry of a map (`x` is `[key value]`), `(%for [k v] in %map ...)` destructures
+///   the entry
+/// * `(%if %cond body... else body...)` renders one of the branches; `null`, `false`, `()`,
+///   `0`, `""` and empty collections are false
+/// * `(%str parts...)` joins the rendered parts into one string
+/// * `%%name` is the literal symbol `%name`
+///
+/// `%for` and `%if` can render any number of values, which are spliced into the
+/// enclosing array or gene data.
+///
+/// ```
+/// # #[macro_use] extern crate gene;
+/// # fn main() {
+/// use gene::template::Template;
+///
+/// let template = Template::parse("(service ^name %name (%for p in %ports (port %p)))").unwrap();
+/// let context = gene!({^name "web" ^ports [80 443]});
+/// assert_eq!(template.render(&context).unwrap(), gene!((service ^name "web" (port 80) (port 443))));
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Template {
+    pub root: Value,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemplateError {
+    pub message: String,
+}
+
+impl TemplateError {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        TemplateError { message: message.into() }
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl Template {
+    pub fn new(root: Value) -> Self {
+        Template { root }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, TemplateError> {
+        let root = Parser::new(s)
+            .parse()
+            .map_err(|e| TemplateError::new(e.message))?;
+        Ok(Template::new(root))
+    }
+
+    pub fn render(&self, context: &Value) -> Result<Value, TemplateError> {
+        render(&self.root, context)
+    }
+
+    /// Render and write the result as (canonical) Gene text, one line per value
+    /// when it renders to several
+    pub fn render_to_string(&self, context: &Value) -> Result<String, TemplateError> {
+        self.render(context).map(|v| to_document(&v))
+    }
+}
+
+/// Render `template` against `context`, which should be a map
+pub fn render(template: &Value, context: &Value) -> Result<Value, TemplateError> {
+    let mut renderer = Renderer {
+        context,
+        locals: Vec::new(),
+    };
+    let mut out = Vec::new();
+    renderer.render_into(template, &mut out)?;
+    match out.len() {
+        0 => Ok(Value::Void),
+        1 => Ok(out.pop().unwrap()),
+        _ => Ok(Value::Stream(out)),
+    }
+}
+
+struct Renderer<'a> {
+    context: &'a Value,
+    /// Variables bound by `%for`, innermost last
+    locals: Vec<(String, Value)>,
+}
+
+impl<'a> Renderer<'a> {
+    /// Render `value` and append the result(s) to `out`
+    fn render_into(&mut self, value: &Value, out: &mut Vec<Value>) -> Result<(), TemplateError> {
+        match value {
+            Value::Symbol(s) if s.starts_with("%%") => {
+                out.push(Value::Symbol(s[1..].to_string()));
+            }
+            Value::Symbol(s) if s.starts_with('%') => {
+                out.push(self.lookup(&s[1..])?);
+            }
+            Value::Array(items) => {
+                let mut arr = Vec::new();
+                for item in items.iter() {
+                    self.render_into(item, &mut arr)?;
+                }
+                out.push(Value::Array(arr));
+            }
+            Value::Map(map) => {
+                out.push(Value::Map(self.render_props(map)?));
+            }
+            Value::Stream(items) => {
+                for item in items.iter() {
+                    self.render_into(item, out)?;
+                }
+            }
+            Value::Gene(gene) => match &gene.kind {
+                Value::Symbol(s) if s == "%for" => self.render_for(gene, out)?,
+                Value::Symbol(s) if s == "%if" => self.render_if(gene, out)?,
+                Value::Symbol(s) if s == "%str" => {
+                    let mut result = String::new();
+                    for part in gene.data.iter() {
+                        match self.render_one(part)? {
+                            Value::String(s) => result.push_str(&s),
+                            v => result.push_str(&v.to_canonical()),
+                        }
+                    }
+                    out.push(Value::String(result));
+                }
+                _ => {
+                    let mut data = Vec::new();
+                    for item in gene.data.iter() {
+                        self.render_into(item, &mut data)?;
+                    }
+                    out.push(Value::Gene(Box::new(Gene {
+                        kind: self.render_one(&gene.kind)?,
+                        props: self.render_props(&gene.props)?,
+                        data,
+                    })));
+                }
+            },
+            _ => out.push(value.clone()),
+        }
+        Ok(())
+    }
+
+    /// Render a value in a position that needs exactly one value (a prop value or gene kind)
+    fn render_one(&mut self, value: &Value) -> Result<Value, TemplateError> {
+        let mut out = Vec::new();
+        self.render_into(value, &mut out)?;
+        match out.len() {
+            0 => Ok(Value::Void),
+            1 => Ok(out.pop().unwrap()),
+            n => Err(TemplateError::new(format!("{} rendered {} values where one is expected", value.to_canonical(), n))),
+        }
+    }
+
+    /// Props whose value renders to nothing are left out
+    fn render_props(&mut self, props: &HashMap<String, Value>) -> Result<HashMap<String, Value>, TemplateError> {
+        let mut result = HashMap::new();
+        for (key, value) in props.iter() {
+            match self.render_one(value)? {
+                Value::Void => (),
+                v => {
+                    result.insert(key.clone(), v);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn render_for(&mut self, gene: &Gene, out: &mut Vec<Value>) -> Result<(), TemplateError> {
+        let usage = "expected (%for x in collection body...)";
+        if gene.data.len() < 3 || gene.data[1] != Value::Symbol("in".to_string()) {
+            return Err(TemplateError::new(usage));
+        }
+        let names = match &gene.data[0] {
+            Value::Symbol(name) => vec![name.clone()],
+            Value::Array(names) if names.len() == 2 => {
+                let mut result = Vec::new();
+                for name in names.iter() {
+                    match name {
+                        Value::Symbol(name) => result.push(name.clone()),
+                        _ => return Err(TemplateError::new(usage)),
+                    }
+                }
+                result
+            }
+            _ => return Err(TemplateError::new(usage)),
+        };
+
+        let items = match self.render_one(&gene.data[2])? {
+            Value::Array(items) => items,
+            Value::Map(map) => {
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                keys.into_iter()
+                    .map(|key| Value::Array(vec![Value::String(key.clone()), map[key].clone()]))
+                    .collect()
+            }
+            Value::Gene(gene) => gene.data,
+            Value::Null | Value::Void => Vec::new(),
+            v => return Err(TemplateError::new(format!("can not iterate over {}", v.to_canonical()))),
+        };
+
+        for item in items {
+            let bindings = if names.len() == 1 {
+                vec![(names[0].clone(), item)]
+            } else {
+                match item {
+                    Value::Array(mut pair) if pair.len() == 2 => {
+                        let second = pair.pop().unwrap();
+                        let first = pair.pop().unwrap();
+                        vec![(names[0].clone(), first), (names[1].clone(), second)]
+                    }
+                    v => return Err(TemplateError::new(format!("can not destructure {}", v.to_canonical()))),
+                }
+            };
+            let count = bindings.len();
+            self.locals.extend(bindings);
+            let result = gene.data.iter().skip(3).try_for_each(|item| self.render_into(item, out));
+            let len = self.locals.len();
+            self.locals.truncate(len - count);
+            result?;
+        }
+        Ok(())
+    }
+
+    fn render_if(&mut self, gene: &Gene, out: &mut Vec<Value>) -> Result<(), TemplateError> {
+        if gene.data.is_empty() {
+            return Err(TemplateError::new("expected (%if condition body... else body...)"));
+        }
+        let cond = self.render_one(&gene.data[0])?;
+        let else_symbol = Value::Symbol("else".to_string());
+        let mut branches = gene.data[1..].split(|item| *item == else_symbol);
+        let then_branch = branches.next().unwrap_or(&[]);
+        let else_branch = branches.next().unwrap_or(&[]);
+        let branch = if cond.is_truthy() { then_branch } else { else_branch };
+        for item in branch.iter() {
+            self.render_into(item, out)?;
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, path: &str) -> Result<Value, TemplateError> {
+        let mut segments = path.split('.');
+        let name = segments.next().unwrap();
+        let mut value = match self.locals.iter().rev().find(|(local, _)| local == name) {
+            Some((_, v)) => v.clone(),
+            None => match self.context {
+                Value::Map(map) => map.get(name).cloned().ok_or_else(|| undefined(path))?,
+                _ => return Err(undefined(path)),
+            },
+        };
+        for segment in segments {
+            value = match (&value, segment.parse::<usize>()) {
+                (Value::Array(items), Ok(index)) => items.get(index).cloned(),
+                (Value::Gene(gene), Ok(index)) => gene.data.get(index).cloned(),
+                (Value::Map(map), _) => map.get(segment).cloned(),
+                (Value::Gene(gene), _) => gene.props.get(segment).cloned(),
+                _ => None,
+            }
+            .ok_or_else(|| undefined(path))?;
+        }
+        Ok(value)
+    }
+}
+
+fn undefined(path: &str) -> TemplateError {
+    TemplateError::new(format!("undefined placeholder %{}", path))
+}
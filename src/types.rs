@@ -2,12 +2,16 @@ extern crate ordered_float;
 
 use std::clone::Clone;
 use std::cell::{RefCell, RefMut};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
 use ordered_float::OrderedFloat;
 
+use super::canonical;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Value {
     Void, // Same as undefined, different from null, can be represented as ()
@@ -47,6 +51,87 @@ impl Clone for Value {
     }
 }
 
+impl Value {
+    /// See `canonical::to_canonical`
+    pub fn to_canonical(&self) -> String {
+        canonical::to_canonical(self)
+    }
+
+    /// See `canonical::content_hash`
+    pub fn content_hash(&self) -> u64 {
+        canonical::content_hash(self)
+    }
+
+    /// `()`, `null`, `false`, `0`, `0.0`, `""` and empty collections are false, everything else is true
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Void | Value::Null => false,
+            Value::Boolean(b) => *b,
+            Value::Integer(i) => *i != 0,
+            Value::Float(f) => f.into_inner() != 0.,
+            Value::String(s) => !s.is_empty(),
+            Value::Array(items) | Value::Stream(items) => !items.is_empty(),
+            Value::Map(map) => !map.is_empty(),
+            Value::Symbol(_) | Value::Gene(_) => true,
+        }
+    }
+
+    /// Position of the variant in the ordering of values of different types
+    fn type_rank(&self) -> u8 {
+        match self {
+            Value::Void => 0,
+            Value::Null => 1,
+            Value::Boolean(_) => 2,
+            Value::Integer(_) => 3,
+            Value::Float(_) => 4,
+            Value::String(_) => 5,
+            Value::Symbol(_) => 6,
+            Value::Array(_) => 7,
+            Value::Map(_) => 8,
+            Value::Gene(_) => 9,
+            Value::Stream(_) => 10,
+        }
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_canonical().hash(state);
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Values of different types are ordered by type, values of the same type by content.
+/// Maps and gene props are compared as lists of entries sorted by key, the same order
+/// that the canonical form uses.
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.cmp(b),
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            (Value::Symbol(a), Value::Symbol(b)) => a.cmp(b),
+            (Value::Array(a), Value::Array(b)) => a.cmp(b),
+            (Value::Stream(a), Value::Stream(b)) => a.cmp(b),
+            (Value::Map(a), Value::Map(b)) => sorted_entries(a).cmp(&sorted_entries(b)),
+            (Value::Gene(a), Value::Gene(b)) => a.cmp(b),
+            _ => self.type_rank().cmp(&other.type_rank()),
+        }
+    }
+}
+
+fn sorted_entries(map: &HashMap<String, Value>) -> Vec<(&String, &Value)> {
+    let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match &self {
@@ -66,10 +151,10 @@ impl fmt::Display for Value {
                 fmt.write_str(&v.to_string())?;
             }
             Value::String(v) => {
-                fmt.write_str(&v)?;
+                fmt.write_str(v)?;
             }
             Value::Symbol(v) => {
-                fmt.write_str(&v)?;
+                fmt.write_str(v)?;
             }
             Value::Gene(v) => {
                 fmt.write_str(&v.to_string())?;
@@ -99,6 +184,28 @@ impl Gene {
     }
 }
 
+impl Hash for Gene {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+        sorted_entries(&self.props).hash(state);
+        self.data.hash(state);
+    }
+}
+
+impl PartialOrd for Gene {
+    fn partial_cmp(&self, other: &Gene) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Gene {
+    fn cmp(&self, other: &Gene) -> Ordering {
+        self.kind.cmp(&other.kind)
+            .then_with(|| sorted_entries(&self.props).cmp(&sorted_entries(&other.props)))
+            .then_with(|| self.data.cmp(&other.data))
+    }
+}
+
 impl fmt::Display for Gene {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.write_str("(")?;
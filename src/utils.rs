@@ -20,3 +20,122 @@ macro_rules! map {
         }
     };
 }
+
+#[macro_export]
+/// Create a **Value** from Gene-like syntax
+///
+/// ```
+/// # #[macro_use] extern crate gene;
+/// # fn main() {
+/// let name = "Ann";
+/// let value = gene!((person ^age 30 ^^admin ^name #name [1 2.5 "x"] {^a null}));
+/// # }
+/// ```
+///
+/// * `null`, `true`, `false`, numbers and strings are literals, identifiers and
+///   operators such as `+` or `<=` become symbols
+/// * `(kind ^prop value data...)` creates a gene, `^^prop` / `^!prop` set a prop to true / false,
+///   quote the prop name (`^"my-key" 1`) when it is not an identifier
+/// * `#x` or `#(expression)` inserts a Rust value through `Value::from`
+/// * `-` right before a number makes it negative, `[1 -1]` or `(n - 1)` both have
+///   the data `1` and `-1`. Elsewhere, and as the kind of a gene, `-` is a symbol.
+/// * more than one item creates a stream
+macro_rules! gene {
+    (@value ($e:expr)) => ($crate::types::Value::from($e));
+    (@value $e:tt) => ($crate::types::Value::from($e));
+
+    (@key $key:ident) => (stringify!($key).to_string());
+    (@key $key:literal) => ($key.to_string());
+
+    (@items [$($out:expr),*]) => (vec![$($out),*]);
+    (@items [$($out:expr),*] - $l:literal $($rest:tt)*) => (
+        gene!(@items [$($out,)* gene!(@value (-$l))] $($rest)*)
+    );
+    (@items [$($out:expr),*] # $e:tt $($rest:tt)*) => (
+        gene!(@items [$($out,)* gene!(@value $e)] $($rest)*)
+    );
+    (@items [$($out:expr),*] $item:tt $($rest:tt)*) => (
+        gene!(@items [$($out,)* gene!($item)] $($rest)*)
+    );
+
+    (@props $props:ident) => ();
+    (@props $props:ident ^ ^ $key:tt $($rest:tt)*) => {
+        $props.insert(gene!(@key $key), $crate::types::Value::Boolean(true));
+        gene!(@props $props $($rest)*);
+    };
+    (@props $props:ident ^ ! $key:tt $($rest:tt)*) => {
+        $props.insert(gene!(@key $key), $crate::types::Value::Boolean(false));
+        gene!(@props $props $($rest)*);
+    };
+    (@props $props:ident ^ $key:tt # $e:tt $($rest:tt)*) => {
+        $props.insert(gene!(@key $key), gene!(@value $e));
+        gene!(@props $props $($rest)*);
+    };
+    (@props $props:ident ^ $key:tt - $l:literal $($rest:tt)*) => {
+        $props.insert(gene!(@key $key), gene!(@value (-$l)));
+        gene!(@props $props $($rest)*);
+    };
+    (@props $props:ident ^ $key:tt $value:tt $($rest:tt)*) => {
+        $props.insert(gene!(@key $key), gene!($value));
+        gene!(@props $props $($rest)*);
+    };
+
+    // Props and data of a gene can be mixed, props are moved to the front
+    (@body $g:ident [$($props:tt)*] [$($data:tt)*]) => {
+        #[allow(unused_mut)]
+        let mut _props = ::std::collections::HashMap::new();
+        gene!(@props _props $($props)*);
+        $g.props = _props;
+        $g.data = gene!(@items [] $($data)*);
+    };
+    (@body $g:ident [$($props:tt)*] [$($data:tt)*] ^ ^ $key:tt $($rest:tt)*) => (
+        gene!(@body $g [$($props)* ^ ^ $key] [$($data)*] $($rest)*)
+    );
+    (@body $g:ident [$($props:tt)*] [$($data:tt)*] ^ ! $key:tt $($rest:tt)*) => (
+        gene!(@body $g [$($props)* ^ ! $key] [$($data)*] $($rest)*)
+    );
+    (@body $g:ident [$($props:tt)*] [$($data:tt)*] ^ $key:tt # $e:tt $($rest:tt)*) => (
+        gene!(@body $g [$($props)* ^ $key # $e] [$($data)*] $($rest)*)
+    );
+    (@body $g:ident [$($props:tt)*] [$($data:tt)*] ^ $key:tt - $l:literal $($rest:tt)*) => (
+        gene!(@body $g [$($props)* ^ $key - $l] [$($data)*] $($rest)*)
+    );
+    (@body $g:ident [$($props:tt)*] [$($data:tt)*] ^ $key:tt $value:tt $($rest:tt)*) => (
+        gene!(@body $g [$($props)* ^ $key $value] [$($data)*] $($rest)*)
+    );
+    (@body $g:ident [$($props:tt)*] [$($data:tt)*] - $l:literal $($rest:tt)*) => (
+        gene!(@body $g [$($props)*] [$($data)* - $l] $($rest)*)
+    );
+    (@body $g:ident [$($props:tt)*] [$($data:tt)*] # $e:tt $($rest:tt)*) => (
+        gene!(@body $g [$($props)*] [$($data)* # $e] $($rest)*)
+    );
+    (@body $g:ident [$($props:tt)*] [$($data:tt)*] $item:tt $($rest:tt)*) => (
+        gene!(@body $g [$($props)*] [$($data)* $item] $($rest)*)
+    );
+
+    (null) => ($crate::types::Value::Null);
+    (()) => ($crate::types::Value::Void);
+    ((# $kind:tt $($body:tt)*)) => {{
+        let mut _gene = $crate::types::Gene::new(gene!(@value $kind));
+        gene!(@body _gene [] [] $($body)*);
+        $crate::types::Value::Gene(Box::new(_gene))
+    }};
+    (($kind:tt $($body:tt)*)) => {{
+        let mut _gene = $crate::types::Gene::new(gene!($kind));
+        gene!(@body _gene [] [] $($body)*);
+        $crate::types::Value::Gene(Box::new(_gene))
+    }};
+    ([$($items:tt)*]) => ($crate::types::Value::Array(gene!(@items [] $($items)*)));
+    ({$($props:tt)*}) => {{
+        #[allow(unused_mut)]
+        let mut _props = ::std::collections::HashMap::new();
+        gene!(@props _props $($props)*);
+        $crate::types::Value::Map(_props)
+    }};
+    (# $e:tt) => (gene!(@value $e));
+    (-) => ($crate::types::Value::Symbol("-".to_string()));
+    ($value:literal) => ($crate::types::Value::from($value));
+    ($symbol:ident) => ($crate::types::Value::Symbol(stringify!($symbol).to_string()));
+    ($symbol:tt) => ($crate::types::Value::Symbol(stringify!($symbol).to_string()));
+    ($($items:tt)+) => ($crate::types::Value::Stream(gene!(@items [] $($items)+)));
+}
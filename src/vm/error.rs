@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::super::types::{Gene, Value};
+use super::value::RuntimeValue;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    UndefinedVariable,
+    TypeError,
+    ArityMismatch,
+    NotCallable,
+    DivisionByZero,
+    /// Integer arithmetic that doesn't fit in 64 bits
+    Overflow,
+    /// Too many nested calls, see `VirtualMachine::set_max_depth`
+    StackOverflow,
+    /// A value thrown with `throw` that was not caught
+    Thrown,
+    /// No pattern of a `match` matches the value
+    NoMatch,
+    /// Malformed code, e.g. an instruction the compiler should never have generated
+    Internal,
+}
+
+/// A frame of the Gene call stack: the function (or block) name and the position
+/// of the instruction that was running
+#[derive(Clone, Debug, PartialEq)]
+pub struct StackFrame {
+    pub name: String,
+    pub pos: usize,
+}
+
+impl StackFrame {
+    pub fn new(name: String, pos: usize) -> Self {
+        StackFrame {
+            name,
+            pos,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Innermost frame first
+    pub stack: Vec<StackFrame>,
+    /// The value given to `throw`, as it is so that `catch` gets the same value
+    pub thrown: Option<RuntimeValue>,
+}
+
+impl RuntimeError {
+    pub fn new<S: Into<String>>(kind: ErrorKind, message: S) -> Self {
+        RuntimeError {
+            kind,
+            message: message.into(),
+            stack: Vec::new(),
+            thrown: None,
+        }
+    }
+
+    /// An error carrying a Gene value, like `(throw value)`
+    pub fn thrown<V: Into<RuntimeValue>>(value: V) -> Self {
+        let value = value.into();
+        let mut error = RuntimeError::new(ErrorKind::Thrown, format!("uncaught exception {}", value.describe()));
+        error.thrown = Some(value);
+        error
+    }
+
+    /// The thrown value converted to a Gene value
+    pub fn value(&self) -> Option<Value> {
+        self.thrown.as_ref().map(RuntimeValue::to_value)
+    }
+
+    /// The value `catch` binds: the thrown value, or `(Error ^kind "TypeError" ^message "...")`
+    /// for errors raised by the VM or native functions
+    pub fn to_runtime_value(&self) -> RuntimeValue {
+        match &self.thrown {
+            Some(value) => value.clone(),
+            None => RuntimeValue::from(self.to_value()),
+        }
+    }
+
+    /// Same as `to_runtime_value`, converted to a Gene value
+    pub fn to_value(&self) -> Value {
+        if let Some(value) = &self.thrown {
+            return value.to_value();
+        }
+        let mut props = HashMap::new();
+        props.insert("kind".to_string(), Value::String(format!("{:?}", self.kind)));
+        props.insert("message".to_string(), Value::String(self.message.clone()));
+        Value::Gene(Box::new(Gene {
+            kind: Value::Symbol("Error".to_string()),
+            props,
+            data: Vec::new(),
+        }))
+    }
+
+    pub fn undefined_variable(name: &str) -> Self {
+        RuntimeError::new(ErrorKind::UndefinedVariable, format!("undefined variable {}", name))
+    }
+
+    pub fn type_error<S: Into<String>>(message: S) -> Self {
+        RuntimeError::new(ErrorKind::TypeError, message)
+    }
+
+    pub fn overflow(op: &str, a: i64, b: i64) -> Self {
+        RuntimeError::new(ErrorKind::Overflow, format!("integer overflow in {} {} {}", a, op, b))
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&self.message)?;
+        for frame in self.stack.iter() {
+            write!(fmt, "\n    at {} ({})", frame.name, frame.pos)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
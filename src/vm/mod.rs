@@ -1,20 +1,39 @@
+pub mod error;
 pub mod types;
+pub mod value;
 
 use std::ptr;
-use std::any::Any;
 use std::cell::{Ref, RefCell, RefMut};
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::rc::Rc;
-use std::time::Instant;
 
+use self::error::{ErrorKind, RuntimeError, StackFrame};
 use self::types::*;
-use super::compiler::{Block, Instruction, Module};
+use self::value::{Iter, RuntimeValue, Step};
+use super::compiler::{Block, Instruction, Module, Shape};
 use super::types::Value;
 use super::utils::new_uuidv4;
 
+/// Default for `VirtualMachine::set_max_depth`
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+/// Default for `VirtualMachine::set_max_native_depth`. Each level takes about
+/// 7 KB of Rust stack in a debug build, 100 levels stay well within the 2 MB
+/// stack of a spawned thread.
+pub const DEFAULT_MAX_NATIVE_DEPTH: usize = 100;
+
 pub struct VirtualMachine {
     registers_store: RegistersStore,
-    pos: usize,
+    /// Number of function calls in progress, tail calls don't count
+    depth: usize,
+    max_depth: usize,
+    /// Number of `call`s in progress, each one runs the VM on top of the Rust stack
+    native_depth: usize,
+    max_native_depth: usize,
+    /// Top level definitions are kept here and shared by all modules loaded into the VM
+    root_context: Rc<RefCell<Context>>,
     // app: Application,
     code_manager: CodeManager,
 }
@@ -23,322 +42,647 @@ impl VirtualMachine {
     pub fn new() -> Self {
         VirtualMachine {
             registers_store: RegistersStore::new(),
-            pos: 0,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            native_depth: 0,
+            max_native_depth: DEFAULT_MAX_NATIVE_DEPTH,
+            root_context: Rc::new(RefCell::new(Context::root())),
             // app: Application::new(),
             code_manager: CodeManager::new(),
         }
     }
 
-    pub fn load_module(&mut self, module: &Module) -> Rc<RefCell<dyn Any>> {
-        let block = module.get_default_block();
+    pub fn load_module(&mut self, module: &Module) -> Result<Value, RuntimeError> {
+        // Map the ids used by the module to ids in the root namespace
+        let ids: Vec<usize> = {
+            let namespace = self.root_context.borrow().namespace.clone();
+            let mut namespace = namespace.borrow_mut();
+            module.globals.iter().map(|name| namespace.id(name)).collect()
+        };
 
         module.blocks.values().for_each(|block| {
             let id = block.id.clone();
-            self.code_manager.set_block(id, block.clone());
+            let block = if ids.is_empty() { block.clone() } else { Rc::new(resolve_globals(block, &ids)) };
+            self.code_manager.set_block(id, block);
         });
 
-        self.process(block.clone())
+        let block = self.code_manager.blocks[&module.get_default_block().id].clone();
+        self.process(block)
+    }
+
+    pub fn process(&mut self, block: Rc<Block>) -> Result<Value, RuntimeError> {
+        let registers = self.registers_store.get(self.root_context.clone());
+        registers.default = RuntimeValue::Void;
+        let registers_id = registers.id;
+        self.execute(block, registers_id)
+    }
+
+    /// Look up a top level variable or function
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.root_context.borrow().get_member(name).map(|v| v.to_value())
     }
 
-    pub fn process(&mut self, mut block: Rc<Block>) -> Rc<RefCell<dyn Any>> {
-        let start_time = Instant::now();
+    /// Look up a top level member as it is stored, e.g. to `call` a function
+    pub fn lookup(&self, name: &str) -> Option<RuntimeValue> {
+        self.root_context.borrow().get_member(name)
+    }
+
+    /// Make a Rust function callable from Gene code as `name`
+    pub fn register_native<F>(&mut self, name: &str, body: F)
+    where
+        F: Fn(&mut VirtualMachine, Arguments) -> Result<Value, RuntimeError> + 'static,
+    {
+        let function = NativeFunction::new(name.to_string(), body);
+        let mut context = self.root_context.borrow_mut();
+        context.def_member(name.to_string(), RuntimeValue::NativeFunction(Rc::new(function)), VarType::NAMESPACE);
+    }
+
+    /// Define or overwrite a top level variable, like `(var name value)` does
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        let mut context = self.root_context.borrow_mut();
+        context.def_member(name.to_string(), RuntimeValue::from(value), VarType::NAMESPACE);
+    }
+
+    /// Limit the number of nested function calls, including native functions and
+    /// functions they call. Deeper calls fail with `ErrorKind::StackOverflow`.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Limit the number of nested `call`s, e.g. of a native function that calls
+    /// a Gene function that calls the native function again. Unlike other calls
+    /// they use the Rust stack, so the limit is much lower than `max_depth`.
+    /// Deeper calls fail with `ErrorKind::StackOverflow`.
+    pub fn set_max_native_depth(&mut self, max_native_depth: usize) {
+        self.max_native_depth = max_native_depth;
+    }
+
+    pub fn max_native_depth(&self) -> usize {
+        self.max_native_depth
+    }
+
+    /// Call a Gene or native function, e.g. one passed to a native function as an argument.
+    /// This can be done while the VM is running, the call runs to completion before
+    /// the outer code continues.
+    pub fn call(&mut self, function: &RuntimeValue, args: Arguments) -> Result<Value, RuntimeError> {
+        let name = match function {
+            RuntimeValue::NativeFunction(native) => &native.name,
+            RuntimeValue::Function(target) => &target.name,
+            _ => return Err(not_callable(function)),
+        };
+        if self.native_depth >= self.max_native_depth {
+            return Err(RuntimeError::new(
+                ErrorKind::StackOverflow,
+                format!("stack overflow in {}: more than {} nested calls from native functions", name, self.max_native_depth),
+            ));
+        }
+        self.push_frame(name)?;
+        self.native_depth += 1;
+        let result = match function {
+            RuntimeValue::NativeFunction(native) => (native.body)(self, args),
+            RuntimeValue::Function(target) => {
+                self.enter(target, &args).and_then(|(registers_id, block)| self.execute(block, registers_id))
+            }
+            _ => unreachable!(),
+        };
+        self.native_depth -= 1;
+        self.depth -= 1;
+        result
+    }
 
-        let mut registers_id;
-        {
-            let root_context = Context::root();
-            let registers = self.registers_store.get(Rc::new(RefCell::new(root_context)));
-            registers_id = registers.id;
+    fn push_frame(&mut self, name: &str) -> Result<(), RuntimeError> {
+        if self.depth >= self.max_depth {
+            return Err(RuntimeError::new(
+                ErrorKind::StackOverflow,
+                format!("stack overflow in {}: more than {} nested calls", name, self.max_depth),
+            ));
         }
+        self.depth += 1;
+        Ok(())
+    }
 
-        self.pos = 0;
-        let mut break_from_loop = false;
-        let mut immature_break;
+    /// Run `block` with the registers `registers_id` until it returns.
+    /// The registers are freed afterwards.
+    fn execute(&mut self, mut block: Rc<Block>, mut registers_id: usize) -> Result<Value, RuntimeError> {
+        let mut pos = 0;
 
         // Use two level loop to separate instructions that change registers and those that don't
-        // TODO: clean up and document logic
-        while self.pos < block.instructions.len() {
-            immature_break = false;
-
-            {
-                let mut registers = self.registers_store.find(registers_id);
-
-                while self.pos < block.instructions.len() {
-                    let instr = &block.instructions[self.pos];
-
-                    // Handle break from loop
-                    if break_from_loop {
-                        self.pos += 1;
-                        match instr {
-                            Instruction::LoopEnd => {
-                                break_from_loop = false;
-                            }
-                            _ => {
-                                continue;
-                            }
-                        }
+        while pos < block.instructions.len() {
+            let result = {
+                let registers = self.registers_store.find(registers_id);
+                run(registers, &block, &mut pos)
+            };
+
+            let result = match result {
+                Ok(true) => match &block.instructions[pos] {
+                    Instruction::Call(target_reg, args_reg, _options) => {
+                        let (target_reg, args_reg) = (*target_reg, *args_reg);
+                        self.call_instr(&mut block, &mut pos, &mut registers_id, target_reg, args_reg, false)
                     }
-
-                    match instr {
-                        Instruction::Default(v) => {
-                            self.pos += 1;
-                            registers.default = Rc::new(RefCell::new(v.clone()));
-                        }
-                        Instruction::Save(reg, v) => {
-                            self.pos += 1;
-                            registers.insert(*reg, Rc::new(RefCell::new(v.clone())));
-                        }
-                        Instruction::CopyFromDefault(to) => {
-                            self.pos += 1;
-                            registers.insert(to.clone(), registers.default.clone());
-                        }
-                        Instruction::CopyToDefault(to) => {
-                            self.pos += 1;
-                            registers.default = registers.get(*to);
-                        }
-                        Instruction::DefMember(name) => {
-                            self.pos += 1;
-                            let value = registers.default.clone();
-                            let mut context = registers.context.borrow_mut();
-                            context.def_member(name.clone(), value, VarType::SCOPE);
-                        }
-                        Instruction::GetMember(name) => {
-                            self.pos += 1;
-                            let value = registers.get_member(name).unwrap();
-                            registers.default = value;
-                        }
-                        Instruction::SetMember(name) => {
-                            self.pos += 1;
-                            registers.set_member(name.clone(), registers.default.clone());
-                        }
-                        Instruction::Jump(pos) => {
-                            self.pos = *pos as usize;
-                        }
-                        Instruction::JumpIfFalse(pos) => {
-                            let value_ = registers.default.borrow();
-                            let value = value_.downcast_ref::<Value>().unwrap();
-                            match value {
-                                Value::Boolean(b) => {
-                                    if *b {
-                                        self.pos += 1;
-                                    } else {
-                                        self.pos = *pos as usize;
-                                    }
-                                }
-                                _ => unimplemented!()
-                            }
-                        }
-                        Instruction::Break => {
-                            self.pos += 1;
-                            break_from_loop = true;
-                        }
-                        Instruction::LoopStart => {
-                            self.pos += 1;
-                        }
-                        Instruction::LoopEnd => {
-                            self.pos += 1;
-                        }
-                        Instruction::BinaryOp(op, first) => {
-                            self.pos += 1;
-                            let first = registers.get(*first);
-                            let second = registers.default.clone();
-                            let result = binary_op(op, first, second);
-                            registers.default = result;
-                        }
-                        Instruction::Init => {
-                            self.pos += 1;
-                        }
-                        Instruction::Function(name, args, body_id) => {
-                            self.pos += 1;
-                            let function_temp;
-                            {
-                                let mut context = registers.context.borrow_mut();
-                                let function = Function::new(name.clone(), (*args).clone(), body_id.clone(), true, context.namespace.clone(), context.scope.clone());
-                                function_temp = Rc::new(RefCell::new(function));
-                                context.def_member(name.clone(), function_temp.clone(), VarType::NAMESPACE);
-                            }
-                            registers.default = function_temp.clone();
-                        }
-                        Instruction::Call(_target_reg, _args_reg, _options) => {
-                            immature_break = true;
-                            break;
-                        }
-                        Instruction::CallEnd => {
-                            immature_break = true;
-                            break;
-                        }
-                        Instruction::CreateArguments(reg) => {
-                            self.pos += 1;
-                            let data = Vec::<Rc<RefCell<Value>>>::new();
-                            registers.insert(reg.clone(), Rc::new(RefCell::new(data)));
-                        }
-                        Instruction::SetItem(target_reg, index) => {
-                            self.pos += 1;
-
-                            let value;
-                            {
-                                let value_ = registers.default.borrow();
-                                value = value_.downcast_ref::<Value>().unwrap().clone();
-                            }
-                            let target_temp = registers.get(*target_reg);
-                            let mut target_ = target_temp.borrow_mut();
-                            if let Some(args) = target_.downcast_mut::<Vec<Rc<RefCell<Value>>>>() {
-                                while *index >= args.len() {
-                                    args.push(Rc::new(RefCell::new(Value::Void)));
-                                }
-                                args[*index] = Rc::new(RefCell::new(value));
-                            } else if let Some(args) = target_.downcast_mut::<Value>() {
-                                match args {
-                                    Value::Array(arr) => {
-                                        while *index >= arr.len() {
-                                            arr.push(Value::Void);
-                                        }
-                                        arr[*index] = value.clone();
-                                    }
-                                    _ => unimplemented!()
-                                }
-                            } else {
-                                unimplemented!();
-                            }
-                        }
-                        Instruction::SetProp(target_reg, key) => {
-                            self.pos += 1;
-
-                            let value;
-                            {
-                                let value_ = registers.default.borrow();
-                                value = value_.downcast_ref::<Value>().unwrap().clone();
-                            }
-                            let target_temp = registers.get(*target_reg);
-                            let mut target_ = target_temp.borrow_mut();
-                            if let Some(v) = target_.downcast_mut::<Value>() {
-                                match v {
-                                    Value::Map(map) => {
-                                        map.insert(key.clone(), value);
-                                    }
-                                    _ => unimplemented!()
-                                }
-                            } else {
-                                unimplemented!();
-                            }
-                        }
-                        _ => unimplemented!()
+                    Instruction::TailCall(target_reg, args_reg) => {
+                        let (target_reg, args_reg) = (*target_reg, *args_reg);
+                        self.call_instr(&mut block, &mut pos, &mut registers_id, target_reg, args_reg, true)
                     }
-                }
+                    Instruction::CallEnd => {
+                        self.call_end(&mut block, &mut pos, &mut registers_id);
+                        Ok(())
+                    }
+                    Instruction::Next(reg, _) => {
+                        let function = match self.registers_store.find(registers_id).get(*reg) {
+                            RuntimeValue::Iterator(iter) => match &*iter.borrow() {
+                                Iter::Function(function, _) => function.clone(),
+                                _ => unreachable!(),
+                            },
+                            _ => unreachable!(),
+                        };
+                        let args = Arguments::new(HashMap::new(), Vec::new());
+                        self.invoke(&mut block, &mut pos, &mut registers_id, function, args, false)
+                    }
+                    _ => unreachable!(),
+                },
+                Ok(false) => break,
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = result {
+                self.unwind(e, &mut block, &mut pos, &mut registers_id)?;
             }
+        }
 
-            if immature_break {
-                let instr = &block.instructions[self.pos];
-                match instr {
-                    Instruction::Call(target_reg, args_reg, _options) => {
-                        self.pos += 1;
+        let registers = self.registers_store.find(registers_id);
+        let result = registers.default.to_value();
+        self.registers_store.free(registers_id);
+
+        Ok(result)
+    }
+
+    /// Call the function in `target_reg` with the arguments in `args_reg`.
+    /// The arguments are moved out of their register, it is not read after the call.
+    fn call_instr(&mut self, block: &mut Rc<Block>, pos: &mut usize, registers_id: &mut usize, target_reg: u16, args_reg: Option<u16>, tail: bool) -> Result<(), RuntimeError> {
+        let (target, args) = {
+            let registers = self.registers_store.find(*registers_id);
+            let args = match args_reg.map(|reg| registers.take(reg)) {
+                Some(RuntimeValue::Arguments(args)) => {
+                    Rc::try_unwrap(args).map(RefCell::into_inner).unwrap_or_else(|args| args.borrow().clone())
+                }
+                _ => Arguments::new(HashMap::new(), Vec::new()),
+            };
+            (registers.get(target_reg), args)
+        };
+        self.invoke(block, pos, registers_id, target, args, tail)
+    }
 
-                        let borrowed_;
-                        let borrowed;
-                        let target;
-                        let new_context;
+    /// Enter `target`, `pos` is at the instruction making the call.
+    /// Native functions run to completion right away.
+    /// A tail call frees the current frame first, the callee returns straight to
+    /// our caller, so it doesn't show up in stack traces either.
+    fn invoke(&mut self, block: &mut Rc<Block>, pos: &mut usize, registers_id: &mut usize, target: RuntimeValue, args: Arguments, tail: bool) -> Result<(), RuntimeError> {
+        let target = match target {
+            RuntimeValue::NativeFunction(native) => {
+                self.push_frame(&native.name)?;
+                let value = (native.body)(self, args);
+                self.depth -= 1;
+                let value = value?;
+                self.registers_store.find(*registers_id).default = RuntimeValue::from(value);
+                *pos = resume_pos(block, *pos);
+                return Ok(());
+            }
+            RuntimeValue::Function(target) => target,
+            _ => return Err(not_callable(&target)),
+        };
+        if !tail {
+            self.push_frame(&target.name)?;
+        }
+        let (new_registers_id, new_block) = match self.enter(&target, &args) {
+            Ok(entered) => entered,
+            Err(e) => {
+                if !tail {
+                    self.depth -= 1;
+                }
+                return Err(e);
+            }
+        };
 
-                        {
-                            let registers = self.registers_store.find(registers_id);
-                            borrowed_ = registers.get(*target_reg);
-                            borrowed = borrowed_.borrow();
-                            target = borrowed.downcast_ref::<Function>().unwrap();
+        let (caller, caller_registers) = if tail {
+            let registers = self.registers_store.find(*registers_id);
+            let caller = (registers.caller.take(), registers.caller_registers);
+            self.registers_store.free(*registers_id);
+            caller
+        } else {
+            (Some(Address::new(block.id.clone(), *pos)), *registers_id)
+        };
 
-                            let mut new_scope = Scope::new(target.parent_scope.clone());
+        let new_registers = self.registers_store.find(new_registers_id);
+        new_registers.caller = caller;
+        new_registers.caller_registers = caller_registers;
 
-                            if let Some(reg) = args_reg {
-                                let args_temp = registers.get(*reg);
-                                let args_ = args_temp.borrow();
-                                let args = args_.downcast_ref::<Vec<Rc<RefCell<Value>>>>().unwrap();
+        *registers_id = new_registers_id;
+        *block = new_block;
+        *pos = 0;
+        Ok(())
+    }
 
-                                for matcher in target.args.data_matchers.iter() {
-                                    let arg_value = args[matcher.index].clone();
-                                    new_scope.def_member(matcher.name.clone(), arg_value);
-                                }
-                            }
+    /// Bind the arguments and set up the registers for running the body of `target`
+    fn enter(&mut self, target: &Function, args: &Arguments) -> Result<(usize, Rc<Block>), RuntimeError> {
+        let block = self.code_manager.blocks[&target.body].clone();
+        let mut new_scope = Scope::with_locals(target.parent_scope.clone(), block.locals.clone());
+        target.args.bind(&target.name, args, &mut new_scope)?;
+
+        let new_context = Context::new(target.parent_namespace.clone(), Rc::new(RefCell::new(new_scope)), None);
+        let registers = self.registers_store.get(Rc::new(RefCell::new(new_context)));
+        registers.caller = None;
+        registers.default = RuntimeValue::Void;
+        Ok((registers.id, block))
+    }
 
-                            let new_namespace = Namespace::new(target.parent_namespace.clone());
-                            new_context = Context::new(Rc::new(RefCell::new(new_namespace)), Rc::new(RefCell::new(new_scope)), None);
-                        }
+    /// Return to the caller. The frame this execution started with has no caller,
+    /// a return there ends the execution, also when it's not at the end of the block.
+    fn call_end(&mut self, block: &mut Rc<Block>, pos: &mut usize, registers_id: &mut usize) {
+        let registers = self.registers_store.find(*registers_id);
+        if let Some(ret_addr) = registers.caller.take() {
+            self.depth -= 1;
+            *block = self.code_manager.blocks[&ret_addr.block_id].clone();
+            *pos = resume_pos(block, ret_addr.pos);
+
+            let value = registers.default.clone();
+            let caller_reg_id = registers.caller_registers;
+            let caller_registers = self.registers_store.find(caller_reg_id);
+            // Save returned value in caller's default register
+            caller_registers.default = value;
+
+            self.registers_store.free(*registers_id);
+            *registers_id = caller_reg_id;
+        } else {
+            *pos = block.instructions.len();
+        }
+    }
 
-                        let new_registers = self.registers_store.get(Rc::new(RefCell::new(new_context)));
+    /// Look for a handler of `error`, starting at the failed instruction and going
+    /// out through the callers. Frames without one are freed and added to the stack
+    /// trace. The error is returned when the frame this execution started with has
+    /// no handler either.
+    fn unwind(&mut self, mut error: RuntimeError, block: &mut Rc<Block>, pos: &mut usize, registers_id: &mut usize) -> Result<(), RuntimeError> {
+        loop {
+            if let Some(handler) = block.handler_at(*pos) {
+                *pos = handler.target;
+                self.registers_store.find(*registers_id).default = RuntimeValue::Error(Rc::new(error));
+                return Ok(());
+            }
 
-                        let ret_addr = Address::new(block.id.clone(), self.pos);
-                        new_registers.caller = Some(ret_addr);
-                        new_registers.caller_registers = registers_id;
+            error.stack.push(StackFrame::new(block.name.clone(), *pos));
+            let registers = self.registers_store.find(*registers_id);
+            let caller = registers.caller.take();
+            let caller_registers = registers.caller_registers;
+            self.registers_store.free(*registers_id);
+            match caller {
+                Some(ret_addr) => {
+                    self.depth -= 1;
+                    *block = self.code_manager.blocks[&ret_addr.block_id].clone();
+                    *pos = ret_addr.pos;
+                    *registers_id = caller_registers;
+                }
+                None => return Err(error),
+            }
+        }
+    }
+}
 
-                        registers_id = new_registers.id;
-                        block = self.code_manager.blocks[&target.body].clone();
-                        self.pos = 0;
+/// Run instructions until the end of the block or a Call / CallEnd, which change the registers.
+/// Returns whether it stopped at a Call / CallEnd. On error `pos` is left at the failed instruction.
+#[inline]
+fn run(registers: &mut Registers, block: &Block, pos: &mut usize) -> Result<bool, RuntimeError> {
+    while *pos < block.instructions.len() {
+        let instr = &block.instructions[*pos];
+
+        let mut next = *pos + 1;
+        match instr {
+            Instruction::Default(v) => {
+                registers.default = RuntimeValue::from(v);
+            }
+            Instruction::Save(reg, v) => {
+                registers.insert(*reg, RuntimeValue::from(v));
+            }
+            Instruction::CopyFromDefault(to) => {
+                registers.insert(*to, registers.default.clone());
+            }
+            Instruction::CopyToDefault(to) => {
+                registers.default = registers.get(*to);
+            }
+            Instruction::DefMember(name) => {
+                let value = registers.default.clone();
+                let mut context = registers.context.borrow_mut();
+                context.def_member(name.clone(), value, VarType::SCOPE);
+            }
+            Instruction::GetMember(name) => {
+                let value = registers.get_member(name).ok_or_else(|| RuntimeError::undefined_variable(name))?;
+                registers.default = value;
+            }
+            Instruction::SetMember(name) => {
+                registers.set_member(name.clone(), registers.default.clone())?;
+            }
+            Instruction::Jump(pos) => {
+                next = *pos as usize;
+            }
+            Instruction::JumpIfFalse(pos) => {
+                if !registers.default.is_truthy() {
+                    next = *pos as usize;
+                }
+            }
+            Instruction::JumpIfTrue(pos) => {
+                if registers.default.is_truthy() {
+                    next = *pos as usize;
+                }
+            }
+            Instruction::Not => {
+                registers.default = RuntimeValue::Boolean(!registers.default.is_truthy());
+            }
+            Instruction::LoopStart | Instruction::LoopEnd | Instruction::Init => (),
+            Instruction::BinaryOp(op, first) => {
+                registers.default = binary_op(op, &registers.get(*first), &registers.default)?;
+            }
+            Instruction::DefLocal(slot) => {
+                let context = registers.context.borrow();
+                context.scope.borrow_mut().def_local(*slot, registers.default.clone());
+            }
+            Instruction::GetLocal(slot) => {
+                let value = registers.context.borrow().scope.borrow().get_local(*slot)?;
+                registers.default = value;
+            }
+            Instruction::SetLocal(slot) => {
+                let context = registers.context.borrow();
+                context.scope.borrow_mut().set_local(*slot, registers.default.clone())?;
+            }
+            Instruction::GetUpvalue(depth, slot) => {
+                let scope = registers.context.borrow().scope.borrow().ancestor(*depth);
+                let value = scope.borrow().get_local(*slot)?;
+                registers.default = value;
+            }
+            Instruction::SetUpvalue(depth, slot) => {
+                let scope = registers.context.borrow().scope.borrow().ancestor(*depth);
+                scope.borrow_mut().set_local(*slot, registers.default.clone())?;
+            }
+            Instruction::DefGlobal(id) => {
+                let context = registers.context.borrow();
+                context.namespace.borrow_mut().def(*id, registers.default.clone());
+            }
+            Instruction::GetGlobal(id) => {
+                let value = {
+                    let context = registers.context.borrow();
+                    let namespace = context.namespace.borrow();
+                    namespace.get(*id).ok_or_else(|| RuntimeError::undefined_variable(namespace.name(*id)))?
+                };
+                registers.default = value;
+            }
+            Instruction::SetGlobal(id) => {
+                let context = registers.context.borrow();
+                context.namespace.borrow_mut().set(*id, registers.default.clone())?;
+            }
+            Instruction::Throw => {
+                return Err(match &registers.default {
+                    RuntimeValue::Error(error) => (**error).clone(),
+                    value => RuntimeError::thrown(value.clone()),
+                });
+            }
+            Instruction::Catch => {
+                if let RuntimeValue::Error(error) = &registers.default {
+                    registers.default = error.to_runtime_value();
+                }
+            }
+            Instruction::Function(name, args, body_id) => {
+                let function = {
+                    let context = registers.context.borrow();
+                    Function::new(name.clone(), args.clone(), body_id.clone(), context.namespace.clone(), context.scope.clone())
+                };
+                registers.default = RuntimeValue::Function(Rc::new(function));
+            }
+            Instruction::Call(..) | Instruction::TailCall(..) | Instruction::CallEnd => {
+                return Ok(true);
+            }
+            Instruction::Range(start_reg, end_reg) => {
+                let range = match (registers.get(*start_reg), registers.get(*end_reg), &registers.default) {
+                    (_, _, RuntimeValue::Integer(0)) => return Err(RuntimeError::type_error("range step can not be 0")),
+                    (RuntimeValue::Integer(start), RuntimeValue::Integer(end), RuntimeValue::Integer(step)) => {
+                        RuntimeValue::Range(start, end, *step)
                     }
-                    Instruction::CallEnd => {
-                        let old_registers_id = registers_id;
-
-                        {
-                            let registers = self.registers_store.find(registers_id);
-                            let caller = registers.caller.as_ref();
-                            if let Some(ret_addr) = caller {
-                                block = self.code_manager.blocks[&ret_addr.block_id].clone();
-                                self.pos = ret_addr.pos;
-
-                                let value = registers.default.clone();
-                                let caller_reg_id = registers.caller_registers;
-                                let caller_registers = self.registers_store.find(caller_reg_id);
-                                // Save returned value in caller's default register
-                                caller_registers.default = value;
-
-                                registers_id = caller_reg_id;
-                            } else {
-                                self.pos += 1;
-                            }
-                        }
-
-                        self.registers_store.free(old_registers_id);
+                    (start, end, step) => {
+                        return Err(RuntimeError::type_error(format!(
+                            "range expects integers, got {} {} {}",
+                            start.describe(),
+                            end.describe(),
+                            step.describe(),
+                        )));
                     }
-                    _ => unimplemented!()
+                };
+                registers.default = range;
+            }
+            Instruction::Iter(reg) | Instruction::PropsIter(reg) => {
+                let iter = Iter::new(&registers.default, matches!(instr, Instruction::PropsIter(_)))?;
+                registers.insert(*reg, RuntimeValue::Iterator(Rc::new(RefCell::new(iter))));
+            }
+            Instruction::Next(reg, end) => {
+                let iter = match registers.get(*reg) {
+                    RuntimeValue::Iterator(iter) => iter,
+                    v => return Err(RuntimeError::new(ErrorKind::Internal, format!("{} is not an iterator", v.describe()))),
+                };
+                let step = iter.borrow_mut().next(&registers.default);
+                match step {
+                    Step::Item(item) => registers.default = item,
+                    Step::Done => {
+                        registers.default = RuntimeValue::Void;
+                        next = *end as usize;
+                    }
+                    Step::Call => return Ok(true),
                 }
-            } else {
-                break;
             }
+            Instruction::CreateArguments(reg) => {
+                let args = Arguments::new(HashMap::new(), Vec::new());
+                registers.insert(*reg, RuntimeValue::Arguments(Rc::new(RefCell::new(args))));
+            }
+            Instruction::GetItem(target_reg, index) => {
+                registers.default = get_item(&registers.get(*target_reg), *index as i64)?;
+            }
+            Instruction::SetItem(target_reg, index) => {
+                set_item(&registers.get(*target_reg), *index as i64, registers.default.clone())?;
+            }
+            Instruction::GetProp(target_reg, key) => {
+                registers.default = get_prop(&registers.get(*target_reg), key)?;
+            }
+            Instruction::SetProp(target_reg, key) => {
+                set_prop(&registers.get(*target_reg), key, registers.default.clone())?;
+            }
+            Instruction::Check(reg, shape) => {
+                registers.default = RuntimeValue::Boolean(check(&registers.get(*reg), shape));
+            }
+            Instruction::GetKind(target_reg) => match registers.get(*target_reg) {
+                RuntimeValue::Gene(gene) => registers.default = gene.borrow().kind.clone(),
+                v => return Err(RuntimeError::type_error(format!("can not get kind of {}", v.describe()))),
+            },
+            Instruction::Rest(target_reg, start) => {
+                let rest = match registers.get(*target_reg) {
+                    RuntimeValue::Array(items) => items.borrow().get(*start..).unwrap_or_default().to_vec(),
+                    RuntimeValue::Gene(gene) => gene.borrow().data.get(*start..).unwrap_or_default().to_vec(),
+                    v => return Err(RuntimeError::type_error(format!("can not get items of {}", v.describe()))),
+                };
+                registers.default = RuntimeValue::array(rest);
+            }
+            Instruction::NoMatch(reg) => {
+                let message = format!("no pattern matches {}", registers.get(*reg).describe());
+                return Err(RuntimeError::new(ErrorKind::NoMatch, message));
+            }
+            Instruction::SetKind(target_reg) => match registers.get(*target_reg) {
+                RuntimeValue::Gene(gene) => gene.borrow_mut().kind = registers.default.clone(),
+                v => return Err(RuntimeError::type_error(format!("can not set kind of {}", v.describe()))),
+            },
+            Instruction::GetDynamic(target_reg, key_reg) => {
+                let target = registers.get(*target_reg);
+                registers.default = match registers.get(*key_reg) {
+                    RuntimeValue::Integer(index) => get_item(&target, index)?,
+                    RuntimeValue::String(key) | RuntimeValue::Symbol(key) => get_prop(&target, &key)?,
+                    key => return Err(invalid_key(&key)),
+                };
+            }
+            Instruction::SetDynamic(target_reg, key_reg) => {
+                let (target, value) = (registers.get(*target_reg), registers.default.clone());
+                match registers.get(*key_reg) {
+                    RuntimeValue::Integer(index) => set_item(&target, index, value)?,
+                    RuntimeValue::String(key) | RuntimeValue::Symbol(key) => set_prop(&target, &key, value)?,
+                    key => return Err(invalid_key(&key)),
+                }
+            }
+            _ => return Err(RuntimeError::new(ErrorKind::Internal, format!("unsupported instruction {}", instr))),
+        }
+        *pos = next;
+    }
+    Ok(false)
+}
+
+/// Where to continue after a call made at `pos` returns. `Next` runs again
+/// to take the result of a function iterator.
+#[inline]
+fn resume_pos(block: &Block, pos: usize) -> usize {
+    match block.instructions[pos] {
+        Instruction::Next(..) => pos,
+        _ => pos + 1,
+    }
+}
+
+/// Replace the module ids of DefGlobal / GetGlobal / SetGlobal with namespace ids
+fn resolve_globals(block: &Block, ids: &[usize]) -> Block {
+    let mut block = block.clone();
+    for instr in block.instructions.iter_mut() {
+        match instr {
+            Instruction::DefGlobal(id) | Instruction::GetGlobal(id) | Instruction::SetGlobal(id) => *id = ids[*id],
+            _ => (),
         }
+    }
+    block
+}
 
-        let registers = self.registers_store.find(registers_id);
-        let result = registers.default.clone();
-        // dbg!(result.borrow().downcast_ref::<Value>().unwrap());
+fn check(value: &RuntimeValue, shape: &Shape) -> bool {
+    let has_len = |len: usize, expected: usize, more: bool| len == expected || (more && len > expected);
+    match (value, shape) {
+        (RuntimeValue::Array(items), Shape::Array(len, more)) => has_len(items.borrow().len(), *len, *more),
+        (RuntimeValue::Map(_), Shape::Map) => true,
+        (RuntimeValue::Gene(gene), Shape::Gene(len, more)) => has_len(gene.borrow().data.len(), *len, *more),
+        (RuntimeValue::Map(map), Shape::HasProp(key)) => map.borrow().contains_key(key),
+        (RuntimeValue::Gene(gene), Shape::HasProp(key)) => gene.borrow().props.contains_key(key),
+        _ => false,
+    }
+}
 
-        println!("Execution time: {:.6} seconds", start_time.elapsed().as_nanos() as f64 / 1_000_000_000.);
+/// Missing items are void
+fn get_item(target: &RuntimeValue, index: i64) -> Result<RuntimeValue, RuntimeError> {
+    let item = match target {
+        RuntimeValue::Array(items) => item_at(&items.borrow(), index),
+        RuntimeValue::Gene(gene) => item_at(&gene.borrow().data, index),
+        _ => return Err(RuntimeError::type_error(format!("can not get item of {}", target.describe()))),
+    };
+    Ok(item.unwrap_or(RuntimeValue::Void))
+}
 
-        result
+fn item_at(items: &[RuntimeValue], index: i64) -> Option<RuntimeValue> {
+    let index = if index < 0 { index + items.len() as i64 } else { index };
+    usize::try_from(index).ok().and_then(|index| items.get(index)).cloned()
+}
+
+/// Setting past the end fills the gap with void
+fn set_item(target: &RuntimeValue, index: i64, item: RuntimeValue) -> Result<(), RuntimeError> {
+    match target {
+        RuntimeValue::Arguments(args) => set_item_at(&mut args.borrow_mut().data, index, item),
+        RuntimeValue::Array(items) => set_item_at(&mut items.borrow_mut(), index, item),
+        RuntimeValue::Gene(gene) => set_item_at(&mut gene.borrow_mut().data, index, item),
+        _ => Err(RuntimeError::type_error(format!("can not set item of {}", target.describe()))),
     }
 }
 
+fn set_item_at(items: &mut Vec<RuntimeValue>, index: i64, item: RuntimeValue) -> Result<(), RuntimeError> {
+    let len = items.len();
+    let index = if index < 0 { index + len as i64 } else { index };
+    let index = usize::try_from(index)
+        .map_err(|_| RuntimeError::type_error(format!("index {} is out of range for {} items", index - len as i64, len)))?;
+    while index >= items.len() {
+        items.push(RuntimeValue::Void);
+    }
+    items[index] = item;
+    Ok(())
+}
+
+/// Missing props are void
+fn get_prop(target: &RuntimeValue, key: &str) -> Result<RuntimeValue, RuntimeError> {
+    let value = match target {
+        RuntimeValue::Map(map) => map.borrow().get(key).cloned(),
+        RuntimeValue::Gene(gene) => gene.borrow().props.get(key).cloned(),
+        _ => return Err(RuntimeError::type_error(format!("can not get prop of {}", target.describe()))),
+    };
+    Ok(value.unwrap_or(RuntimeValue::Void))
+}
+
+fn set_prop(target: &RuntimeValue, key: &str, value: RuntimeValue) -> Result<(), RuntimeError> {
+    let key = key.to_string();
+    match target {
+        RuntimeValue::Arguments(args) => args.borrow_mut().props.insert(key, value),
+        RuntimeValue::Map(map) => map.borrow_mut().insert(key, value),
+        RuntimeValue::Gene(gene) => gene.borrow_mut().props.insert(key, value),
+        _ => return Err(RuntimeError::type_error(format!("can not set prop of {}", target.describe()))),
+    };
+    Ok(())
+}
+
+fn invalid_key(key: &RuntimeValue) -> RuntimeError {
+    RuntimeError::type_error(format!("can not use {} as a key, expected an integer, string or symbol", key.describe()))
+}
+
+fn not_callable(v: &RuntimeValue) -> RuntimeError {
+    RuntimeError::new(ErrorKind::NotCallable, format!("{} is not callable", v.describe()))
+}
+
 #[derive(Debug)]
 pub struct Registers {
     pub id: usize,
     pub caller: Option<Address>,
     pub caller_registers: usize,
-    pub default: Rc<RefCell<dyn Any>>,
+    pub default: RuntimeValue,
     pub context: Rc<RefCell<Context>>,
-    pub cache: [Rc<RefCell<dyn Any>>; 16],
-    pub store: HashMap<u16, Rc<RefCell<dyn Any>>>,
-    // pub members_cache: HashMap<String, Rc<RefCell<dyn Any>>>,
+    pub cache: [RuntimeValue; 16],
+    pub store: HashMap<u16, RuntimeValue>,
+    // pub members_cache: HashMap<String, RuntimeValue>,
 }
 
 impl Registers {
     pub fn new(id: usize, context: Rc<RefCell<Context>>) -> Self {
-        let dummy = Rc::new(RefCell::new(0));
+        const VOID: RuntimeValue = RuntimeValue::Void;
 
         Registers {
             id,
             caller: None,
             caller_registers: 0,
-            default: dummy.clone(),
+            default: VOID,
             context,
-            cache: [
-                dummy.clone(), dummy.clone(), dummy.clone(), dummy.clone(),
-                dummy.clone(), dummy.clone(), dummy.clone(), dummy.clone(),
-                dummy.clone(), dummy.clone(), dummy.clone(), dummy.clone(),
-                dummy.clone(), dummy.clone(), dummy.clone(), dummy.clone(),
-            ],
+            cache: [VOID; 16],
             store: HashMap::new(),
             // members_cache: HashMap::new(),
         }
@@ -349,7 +693,7 @@ impl Registers {
     }
 
     #[inline]
-    pub fn insert(&mut self, key: u16, val: Rc<RefCell<dyn Any>>) {
+    pub fn insert(&mut self, key: u16, val: RuntimeValue) {
         if key < 16 {
             self.cache[key as usize] = val;
         } else {
@@ -358,7 +702,7 @@ impl Registers {
     }
 
     #[inline]
-    pub fn get(&self, key: u16) -> Rc<RefCell<dyn Any>> {
+    pub fn get(&self, key: u16) -> RuntimeValue {
         if key < 16 {
             self.cache[key as usize].clone()
         } else {
@@ -366,16 +710,26 @@ impl Registers {
         }
      }
 
+    /// Move the value out of a register, leaving void behind
     #[inline]
-    fn get_member(&self, name: &str) -> Option<Rc<RefCell<dyn Any>>> {
+    pub fn take(&mut self, key: u16) -> RuntimeValue {
+        if key < 16 {
+            std::mem::replace(&mut self.cache[key as usize], RuntimeValue::Void)
+        } else {
+            self.store.remove(&key).unwrap_or(RuntimeValue::Void)
+        }
+    }
+
+    #[inline]
+    fn get_member(&self, name: &str) -> Option<RuntimeValue> {
         let context = self.context.borrow();
         context.get_member(name)
     }
 
     #[inline]
-    fn set_member(&mut self, name: String, value: Rc<RefCell<dyn Any>>) {
+    fn set_member(&mut self, name: String, value: RuntimeValue) -> Result<(), RuntimeError> {
         let mut context = self.context.borrow_mut();
-        context.set_member(name.clone(), value.clone());
+        context.set_member(name, value)
     }
 }
 
@@ -410,7 +764,7 @@ impl RegistersStore {
     pub fn get(&mut self, context: Rc<RefCell<Context>>) -> &mut Registers {
         if !self.freed.is_empty() {
             let id = self.freed.pop().unwrap();
-            let mut registers =
+            let registers =
                 if id < 32 {
                     &mut self.cache[id]
                 } else {
@@ -420,7 +774,7 @@ impl RegistersStore {
             registers.context = context;
             registers
         } else if self.next < 32 {
-            let mut registers = &mut self.cache[self.next];
+            let registers = &mut self.cache[self.next];
             self.next += 1;
             registers.context = context;
             registers
@@ -449,78 +803,93 @@ impl RegistersStore {
     }
 }
 
+/// `==` and `!=` work on all values, see `RuntimeValue::equals`.
+/// Integers and floats can be mixed, the result is a float then.
+/// Integer overflow and division by zero are errors.
+/// Strings are concatenated with `+` and compared lexicographically.
 #[inline]
-fn binary_op<'a>(
-    op: &'a str,
-    first: Rc<RefCell<dyn Any>>,
-    second: Rc<RefCell<dyn Any>>,
-) -> Rc<RefCell<dyn Any>> {
-    let borrowed1 = first.borrow();
-    let borrowed2 = second.borrow();
-    let value1 = borrowed1.downcast_ref::<Value>().unwrap();
-    let value2 = borrowed2.downcast_ref::<Value>().unwrap();
-    match op {
-        "+" => {
-            match (value1, value2) {
-                (Value::Integer(a), Value::Integer(b)) => Rc::new(RefCell::new(Value::Integer(a + b))),
-                _ => unimplemented!()
-            }
-        }
-        "-" => {
-            match (value1, value2) {
-                (Value::Integer(a), Value::Integer(b)) => Rc::new(RefCell::new(Value::Integer(a - b))),
-                _ => unimplemented!()
-            }
+fn binary_op(op: &str, first: &RuntimeValue, second: &RuntimeValue) -> Result<RuntimeValue, RuntimeError> {
+    match (first, second) {
+        (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => integer_op(op, *a, *b),
+        _ if op == "==" => Ok(RuntimeValue::Boolean(first.equals(second))),
+        _ if op == "!=" => Ok(RuntimeValue::Boolean(!first.equals(second))),
+        (RuntimeValue::Integer(a), RuntimeValue::Float(b)) => float_op(op, *a as f64, *b),
+        (RuntimeValue::Float(a), RuntimeValue::Integer(b)) => float_op(op, *a, *b as f64),
+        (RuntimeValue::Float(a), RuntimeValue::Float(b)) => float_op(op, *a, *b),
+        (RuntimeValue::String(a), RuntimeValue::String(b)) if op == "+" => {
+            Ok(RuntimeValue::String(Rc::from(format!("{}{}", a, b).as_str())))
         }
-        "*" => {
-            match (value1, value2) {
-                (Value::Integer(a), Value::Integer(b)) => Rc::new(RefCell::new(Value::Integer(a * b))),
-                _ => unimplemented!()
-            }
+        (RuntimeValue::String(a), RuntimeValue::String(b)) if matches!(op, "<" | "<=" | ">" | ">=") => {
+            compare(op, a.cmp(b))
         }
-        "/" => {
-            match (value1, value2) {
-                (Value::Integer(a), Value::Integer(b)) => Rc::new(RefCell::new(Value::Integer(a / b))),
-                _ => unimplemented!()
-            }
-        }
-        "<" => {
-            match (value1, value2) {
-                (Value::Integer(a), Value::Integer(b)) => Rc::new(RefCell::new(Value::Boolean(a < b))),
-                _ => unimplemented!()
-            }
-        }
-        "<=" => {
-            match (value1, value2) {
-                (Value::Integer(a), Value::Integer(b)) => Rc::new(RefCell::new(Value::Boolean(a <= b))),
-                _ => unimplemented!()
-            }
-        }
-        ">" => {
-            match (value1, value2) {
-                (Value::Integer(a), Value::Integer(b)) => Rc::new(RefCell::new(Value::Boolean(a > b))),
-                _ => unimplemented!()
-            }
-        }
-        ">=" => {
-            match (value1, value2) {
-                (Value::Integer(a), Value::Integer(b)) => Rc::new(RefCell::new(Value::Boolean(a >= b))),
-                _ => unimplemented!()
-            }
-        }
-        "==" => {
-            match (value1, value2) {
-                (Value::Integer(a), Value::Integer(b)) => Rc::new(RefCell::new(Value::Boolean(a == b))),
-                _ => unimplemented!()
-            }
-        }
-        _ => unimplemented!()
+        _ => Err(RuntimeError::type_error(format!(
+            "unsupported operand types for {}: {} and {}",
+            op,
+            first.describe(),
+            second.describe(),
+        ))),
     }
 }
 
+#[inline]
+fn integer_op(op: &str, a: i64, b: i64) -> Result<RuntimeValue, RuntimeError> {
+    let result = match op {
+        "+" => a.checked_add(b),
+        "-" => a.checked_sub(b),
+        "*" => a.checked_mul(b),
+        "/" | "%" if b == 0 => return Err(division_by_zero()),
+        // Truncates towards zero, the remainder has the sign of `a`
+        "/" => a.checked_div(b),
+        "%" => a.checked_rem(b),
+        // A negative exponent gives a fraction
+        "**" if b < 0 => return Ok(RuntimeValue::Float((a as f64).powf(b as f64))),
+        "**" => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+        _ => return compare(op, a.cmp(&b)),
+    };
+    result.map(RuntimeValue::Integer).ok_or_else(|| RuntimeError::overflow(op, a, b))
+}
+
+#[inline]
+fn float_op(op: &str, a: f64, b: f64) -> Result<RuntimeValue, RuntimeError> {
+    let result = match op {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "/" | "%" if b == 0.0 => return Err(division_by_zero()),
+        "/" => a / b,
+        "%" => a % b,
+        "**" => a.powf(b),
+        // NaN is not equal to or ordered with anything
+        _ => match a.partial_cmp(&b) {
+            Some(ordering) => return compare(op, ordering),
+            None => return Ok(RuntimeValue::Boolean(op == "!=")),
+        },
+    };
+    Ok(RuntimeValue::Float(result))
+}
+
+#[inline]
+fn compare(op: &str, ordering: Ordering) -> Result<RuntimeValue, RuntimeError> {
+    let result = match op {
+        "<" => ordering == Ordering::Less,
+        "<=" => ordering != Ordering::Greater,
+        ">" => ordering == Ordering::Greater,
+        ">=" => ordering != Ordering::Less,
+        "==" => ordering == Ordering::Equal,
+        "!=" => ordering != Ordering::Equal,
+        _ => return Err(RuntimeError::new(ErrorKind::Internal, format!("unknown operator {}", op))),
+    };
+    Ok(RuntimeValue::Boolean(result))
+}
+
+fn division_by_zero() -> RuntimeError {
+    RuntimeError::new(ErrorKind::DivisionByZero, "division by zero")
+}
+
 #[derive(Debug)]
 pub struct Address {
     pub block_id: String,
+    /// The instruction making the call
     pub pos: usize,
 }
 
@@ -1,10 +1,14 @@
-use std::any::Any;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::rc::Rc;
 
 use super::super::types::Value;
 use super::super::compiler::Block;
+use super::error::{ErrorKind, RuntimeError};
+use super::value::RuntimeValue;
+use super::VirtualMachine;
 
 #[derive(Debug)]
 pub struct Application {}
@@ -19,7 +23,7 @@ impl Application {
 pub struct Context {
     pub namespace: Rc<RefCell<Namespace>>,
     pub scope: Rc<RefCell<Scope>>,
-    pub _self: Option<Rc<RefCell<dyn Any>>>,
+    pub _self: Option<RuntimeValue>,
 }
 
 pub enum VarType {
@@ -28,7 +32,7 @@ pub enum VarType {
 }
 
 impl Context {
-    pub fn new(namespace: Rc<RefCell<Namespace>>, scope: Rc<RefCell<Scope>>, _self: Option<Rc<RefCell<dyn Any>>>) -> Self {
+    pub fn new(namespace: Rc<RefCell<Namespace>>, scope: Rc<RefCell<Scope>>, _self: Option<RuntimeValue>) -> Self {
         Self {
             namespace,
             scope,
@@ -45,7 +49,7 @@ impl Context {
     }
 
     #[inline]
-    pub fn def_member(&mut self, name: String, value: Rc<RefCell<dyn Any>>, var_type: VarType) {
+    pub fn def_member(&mut self, name: String, value: RuntimeValue, var_type: VarType) {
         match var_type {
             VarType::SCOPE => {
                 self.scope.borrow_mut().def_member(name, value);
@@ -57,7 +61,7 @@ impl Context {
     }
 
     #[inline]
-    pub fn get_member(&self, name: &str) -> Option<Rc<RefCell<dyn Any>>> {
+    pub fn get_member(&self, name: &str) -> Option<RuntimeValue> {
         let result = self.scope.borrow().get_member(name);
         if result.is_none() {
             self.namespace.borrow().get_member(name)
@@ -67,69 +71,115 @@ impl Context {
     }
 
     #[inline]
-    pub fn set_member(&mut self, name: String, value: Rc<RefCell<dyn Any>>) {
+    pub fn set_member(&mut self, name: String, value: RuntimeValue) -> Result<(), RuntimeError> {
         if self.scope.borrow().has_member(name.clone()) {
             self.scope.borrow_mut().set_member(name, value);
         } else if self.namespace.borrow().has_member(name.clone()) {
             self.namespace.borrow_mut().set_member(name, value);
         } else {
-            panic!("Undefined variable: {}", name);
+            return Err(RuntimeError::undefined_variable(&name));
         }
+        Ok(())
     }
 
 }
 
+/// Top level variables and functions. Each name gets an id the first time it is
+/// used, compiled code refers to members by id.
 #[derive(Clone, Debug)]
 pub struct Namespace {
     parent: Option<Rc<RefCell<Namespace>>>,
-    members: HashMap<String, Rc<RefCell<dyn Any>>>,
+    ids: HashMap<String, usize>,
+    names: Vec<String>,
+    /// None until the member is defined
+    values: Vec<Option<RuntimeValue>>,
 }
 
 impl Namespace {
     pub fn new(parent: Rc<RefCell<Namespace>>) -> Self {
         Self {
             parent: Some(parent),
-            members: HashMap::new(),
+            ids: HashMap::new(),
+            names: Vec::new(),
+            values: Vec::new(),
         }
     }
 
     pub fn root() -> Self {
         Self {
             parent: None,
-            members: HashMap::new(),
+            ids: HashMap::new(),
+            names: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// The id of `name`, the member doesn't have to be defined yet
+    pub fn id(&mut self, name: &str) -> usize {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
         }
+        let id = self.names.len();
+        self.ids.insert(name.to_string(), id);
+        self.names.push(name.to_string());
+        self.values.push(None);
+        id
+    }
+
+    pub fn name(&self, id: usize) -> &str {
+        &self.names[id]
     }
 
     #[inline]
-    pub fn def_member(&mut self, name: String, value: Rc<RefCell<dyn Any>>) {
-        self.members.insert(name, value);
+    pub fn get(&self, id: usize) -> Option<RuntimeValue> {
+        self.values[id].clone()
     }
 
     #[inline]
-    pub fn get_member(&self, name: &str) -> Option<Rc<RefCell<dyn Any>>> {
-        if self.members.contains_key(name) {
-            self.members.get(name).cloned()
-        } else if self.parent.is_some() {
-            self.parent.clone().unwrap().borrow().get_member(name)
-        } else {
-            None
+    pub fn def(&mut self, id: usize, value: RuntimeValue) {
+        self.values[id] = Some(value);
+    }
+
+    #[inline]
+    pub fn set(&mut self, id: usize, value: RuntimeValue) -> Result<(), RuntimeError> {
+        match &mut self.values[id] {
+            Some(v) => {
+                *v = value;
+                Ok(())
+            }
+            None => Err(RuntimeError::undefined_variable(&self.names[id])),
         }
     }
 
     #[inline]
-    pub fn set_member(&mut self, name: String, value: Rc<RefCell<dyn Any>>) {
-        if self.members.contains_key(&name) {
-            self.members.insert(name.clone(), value);
-        } else {
-            let parent = self.parent.clone().unwrap();
-            let mut borrowed = parent.borrow_mut();
-            borrowed.set_member(name, value);
+    pub fn def_member(&mut self, name: String, value: RuntimeValue) {
+        let id = self.id(&name);
+        self.def(id, value);
+    }
+
+    #[inline]
+    pub fn get_member(&self, name: &str) -> Option<RuntimeValue> {
+        match self.ids.get(name).and_then(|id| self.get(*id)) {
+            Some(value) => Some(value),
+            None => self.parent.as_ref()?.borrow().get_member(name),
+        }
+    }
+
+    #[inline]
+    pub fn set_member(&mut self, name: String, value: RuntimeValue) {
+        match self.ids.get(&name) {
+            Some(id) if self.values[*id].is_some() => self.values[*id] = Some(value),
+            _ => {
+                let parent = self.parent.clone().unwrap();
+                let mut borrowed = parent.borrow_mut();
+                borrowed.set_member(name, value);
+            }
         }
     }
 
     #[inline]
     pub fn has_member(&self, name: String) -> bool {
-        if self.members.contains_key(&name) {
+        if self.ids.get(&name).is_some_and(|id| self.values[*id].is_some()) {
             true
         } else if self.parent.is_some() {
             self.parent.clone().unwrap().borrow().has_member(name)
@@ -139,10 +189,17 @@ impl Namespace {
     }
 }
 
+/// Variables of a function call. Code compiled by `compiler2` keeps them in
+/// `slots`, the index of a variable is decided at compile time. `members` is
+/// used for names that were not resolved, e.g. at the top level.
 #[derive(Clone, Debug)]
 pub struct Scope {
     pub parent: Option<Rc<RefCell<Scope>>>,
-    pub members: HashMap<String, Rc<RefCell<dyn Any>>>,
+    pub members: HashMap<String, RuntimeValue>,
+    /// None until the variable is defined
+    pub slots: Vec<Option<RuntimeValue>>,
+    /// Names of the slots, for error messages
+    pub names: Rc<Vec<String>>,
 }
 
 impl Scope {
@@ -150,6 +207,18 @@ impl Scope {
         Scope {
             parent: Some(parent),
             members: HashMap::new(),
+            slots: Vec::new(),
+            names: Rc::new(Vec::new()),
+        }
+    }
+
+    /// A scope for a function body with the local variables `names`
+    pub fn with_locals(parent: Rc<RefCell<Scope>>, names: Rc<Vec<String>>) -> Self {
+        Scope {
+            parent: Some(parent),
+            members: HashMap::new(),
+            slots: vec![None; names.len()],
+            names,
         }
     }
 
@@ -157,32 +226,64 @@ impl Scope {
         Scope {
             parent: None,
             members: HashMap::new(),
+            slots: Vec::new(),
+            names: Rc::new(Vec::new()),
+        }
+    }
+
+    #[inline]
+    pub fn def_local(&mut self, slot: usize, value: RuntimeValue) {
+        self.slots[slot] = Some(value);
+    }
+
+    #[inline]
+    pub fn get_local(&self, slot: usize) -> Result<RuntimeValue, RuntimeError> {
+        match &self.slots[slot] {
+            Some(value) => Ok(value.clone()),
+            None => Err(RuntimeError::undefined_variable(&self.names[slot])),
+        }
+    }
+
+    #[inline]
+    pub fn set_local(&mut self, slot: usize, value: RuntimeValue) -> Result<(), RuntimeError> {
+        match &mut self.slots[slot] {
+            Some(v) => {
+                *v = value;
+                Ok(())
+            }
+            None => Err(RuntimeError::undefined_variable(&self.names[slot])),
+        }
+    }
+
+    /// The scope `depth` levels up, `depth` is at least 1
+    pub fn ancestor(&self, depth: usize) -> Rc<RefCell<Scope>> {
+        let mut scope = self.parent.clone().unwrap();
+        for _ in 1..depth {
+            let parent = scope.borrow().parent.clone().unwrap();
+            scope = parent;
         }
+        scope
     }
 
     #[inline]
-    pub fn def_member(&mut self, name: String, value: Rc<RefCell<dyn Any>>) {
+    pub fn def_member(&mut self, name: String, value: RuntimeValue) {
         self.members.insert(name, value);
     }
 
     #[inline]
-    pub fn get_member(&self, name: &str) -> Option<Rc<RefCell<dyn Any>>> {
+    pub fn get_member(&self, name: &str) -> Option<RuntimeValue> {
         let value = self.members.get(name);
         if value.is_none() && self.parent.is_some() {
             let parent_ = self.parent.clone().unwrap();
             let parent = parent_.borrow();
-            let v = parent.get_member(name);
-            match v {
-                Some(value) => Some(value.clone()),
-                None => None,
-            }
+            parent.get_member(name)
         } else {
             value.cloned()
         }
     }
 
     #[inline]
-    pub fn set_member(&mut self, name: String, value: Rc<RefCell<dyn Any>>) {
+    pub fn set_member(&mut self, name: String, value: RuntimeValue) {
         if self.members.contains_key(&name) {
             self.members.insert(name.clone(), value);
         } else {
@@ -202,72 +303,428 @@ impl Scope {
     }
 }
 
+/// Function signature, built from the argument list of `fn`:
+///
+/// * `_` takes no arguments, `a` a single one
+/// * `[a b]` takes two, each element of the array is a parameter:
+///   * `a` binds the argument to a name, `_` ignores it
+///   * `(a = 1)` is optional, missing arguments get the default value (defaults
+///     are literals, they are not evaluated)
+///   * `rest...` takes the remaining arguments as an array, it must come last
+///   * `[x y]` destructures an array argument with the same rules
+///   * `(kind ^p p x y)` destructures a gene argument: its props and data, the
+///     kind has to be `kind` (`_` takes any kind)
+///   * `1`, `"a"`, `true`, `null` or `` `red `` inside a destructured argument
+///     have to be equal to the value there
+///   * `{^verbose false}` declares prop parameters and their defaults, they are
+///     passed as `(f ^verbose true 1 2)`
 #[derive(Debug, Clone)]
+pub struct Matcher {
+    pub props: Vec<PropMatcher>,
+    pub data: DataMatcher,
+}
+
+/// Positional parameters of a function or of an array/gene pattern
+#[derive(Debug, Clone, Default)]
 pub struct DataMatcher {
+    pub params: Vec<Param>,
+    /// A `Pattern::Name`, or `Pattern::Ignore` for `_...`
+    pub rest: Option<Box<Pattern>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub pattern: Pattern,
+    pub default: Option<Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PropMatcher {
     pub name: String,
-    pub index: usize,
+    pub pattern: Pattern,
+    pub default: Option<Value>,
+}
+
+/// What function arguments and `match` clauses are tested against, see `Matcher`
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Ignore,
+    /// The slot is set when the compiler resolved the name to a local variable,
+    /// otherwise the argument is bound by name
+    Name(String, Option<usize>),
+    /// The value has to be equal to this one
+    Literal(Value),
+    Array(DataMatcher),
+    /// A map that has the props, it may have others
+    Map(Vec<PropMatcher>),
+    /// Gene(kind, props, data), a symbol kind is a `Pattern::Literal`
+    Gene(Box<Pattern>, Vec<PropMatcher>, DataMatcher),
+}
+
+impl Matcher {
+    pub fn new(props: Vec<PropMatcher>, data: DataMatcher) -> Self {
+        Matcher {
+            props,
+            data,
+        }
+    }
+
+    /// Bind the arguments of a call to `function` in `scope`
+    pub fn bind(&self, function: &str, args: &Arguments, scope: &mut Scope) -> Result<(), RuntimeError> {
+        for name in args.props.keys() {
+            if !self.props.iter().any(|prop| &prop.name == name) {
+                return Err(RuntimeError::new(
+                    ErrorKind::ArityMismatch,
+                    format!("{} got an unexpected prop ^{}", function, name),
+                ));
+            }
+        }
+        bind_props(function, &self.props, &args.props, scope)?;
+        if !self.data.accepts(args.data.len()) {
+            return Err(RuntimeError::new(
+                ErrorKind::ArityMismatch,
+                format!("{} expects {}, got {}", function, self.data.describe_arity(), args.data.len()),
+            ));
+        }
+        self.data.bind(function, &args.data, scope)
+    }
+
+    /// Bind arguments to local variable slots instead of names, `slot` returns
+    /// the slot of a name. Names are visited in the order they are bound.
+    pub fn resolve<F: FnMut(&str) -> usize>(&mut self, slot: &mut F) {
+        for prop in self.props.iter_mut() {
+            prop.pattern.resolve(slot);
+        }
+        self.data.resolve(slot);
+    }
+
+    /// Names bound by the arguments, in the order they are bound
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.clone().resolve(&mut |name| {
+            names.push(name.to_string());
+            0
+        });
+        names
+    }
 }
 
 impl DataMatcher {
-    pub fn new(name: String, index: usize) -> Self {
-        DataMatcher {
-            name,
-            index,
+    fn required(&self) -> usize {
+        self.params.iter().filter(|param| param.default.is_none()).count()
+    }
+
+    fn accepts(&self, count: usize) -> bool {
+        count >= self.required() && (self.rest.is_some() || count <= self.params.len())
+    }
+
+    fn describe_arity(&self) -> String {
+        let (min, max) = (self.required(), self.params.len());
+        if self.rest.is_some() {
+            format!("at least {} argument(s)", min)
+        } else if min == max {
+            format!("{} argument(s)", min)
+        } else {
+            format!("{} to {} argument(s)", min, max)
+        }
+    }
+
+    fn resolve<F: FnMut(&str) -> usize>(&mut self, slot: &mut F) {
+        for param in self.params.iter_mut() {
+            param.pattern.resolve(slot);
+        }
+        if let Some(rest) = &mut self.rest {
+            rest.resolve(slot);
         }
     }
+
+    fn bind(&self, function: &str, values: &[RuntimeValue], scope: &mut Scope) -> Result<(), RuntimeError> {
+        for (index, param) in self.params.iter().enumerate() {
+            let value = match (values.get(index), &param.default) {
+                (Some(value), _) => value.clone(),
+                (None, Some(default)) => RuntimeValue::from(default),
+                (None, None) => unreachable!("arity is checked before binding"),
+            };
+            param.pattern.bind(function, value, scope)?;
+        }
+        if let Some(rest) = &self.rest {
+            let rest_values = values.iter().skip(self.params.len()).cloned().collect();
+            rest.bind(function, RuntimeValue::array(rest_values), scope)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct Matcher {
-    pub data_matchers: Vec<DataMatcher>,
+impl Pattern {
+    /// Names bound by the pattern, in the order they are bound
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.clone().resolve(&mut |name| {
+            names.push(name.to_string());
+            0
+        });
+        names
+    }
+
+    fn resolve<F: FnMut(&str) -> usize>(&mut self, slot: &mut F) {
+        match self {
+            Pattern::Ignore | Pattern::Literal(_) => (),
+            Pattern::Name(name, resolved) => *resolved = Some(slot(name)),
+            Pattern::Array(data) => data.resolve(slot),
+            Pattern::Map(props) => {
+                for prop in props.iter_mut() {
+                    prop.pattern.resolve(slot);
+                }
+            }
+            Pattern::Gene(kind, props, data) => {
+                kind.resolve(slot);
+                for prop in props.iter_mut() {
+                    prop.pattern.resolve(slot);
+                }
+                data.resolve(slot);
+            }
+        }
+    }
+
+    fn bind(&self, function: &str, value: RuntimeValue, scope: &mut Scope) -> Result<(), RuntimeError> {
+        match (self, &value) {
+            (Pattern::Ignore, _) => Ok(()),
+            (Pattern::Name(_, Some(slot)), _) => {
+                scope.def_local(*slot, value);
+                Ok(())
+            }
+            (Pattern::Name(name, None), _) => {
+                scope.def_member(name.clone(), value);
+                Ok(())
+            }
+            (Pattern::Literal(literal), _) => {
+                if value.equals(&RuntimeValue::from(literal)) {
+                    Ok(())
+                } else {
+                    Err(RuntimeError::type_error(format!(
+                        "{} expects {}, got {}", function, literal.to_canonical(), value.describe(),
+                    )))
+                }
+            }
+            (Pattern::Array(matcher), RuntimeValue::Array(items)) => {
+                let items = items.borrow().clone();
+                matcher.bind_nested(function, &items, &value, scope)
+            }
+            (Pattern::Gene(kind, props, data), RuntimeValue::Gene(gene)) => {
+                let gene = gene.borrow().clone();
+                kind.bind(function, gene.kind, scope)?;
+                bind_props(function, props, &gene.props, scope)?;
+                data.bind_nested(function, &gene.data, &value, scope)
+            }
+            (Pattern::Map(props), RuntimeValue::Map(map)) => {
+                let map = map.borrow().clone();
+                bind_props(function, props, &map, scope)
+            }
+            (Pattern::Array(_), _) => Err(RuntimeError::type_error(format!(
+                "{} expects an array, got {}", function, value.describe(),
+            ))),
+            (Pattern::Gene(..), _) => Err(RuntimeError::type_error(format!(
+                "{} expects a gene, got {}", function, value.describe(),
+            ))),
+            (Pattern::Map(_), _) => Err(RuntimeError::type_error(format!(
+                "{} expects a map, got {}", function, value.describe(),
+            ))),
+        }
+    }
 }
 
-impl Matcher {
-    pub fn new(data_matchers: Vec<DataMatcher>) -> Self {
-        Matcher {
-            data_matchers,
+impl DataMatcher {
+    fn bind_nested(&self, function: &str, items: &[RuntimeValue], value: &RuntimeValue, scope: &mut Scope) -> Result<(), RuntimeError> {
+        if !self.accepts(items.len()) {
+            return Err(RuntimeError::new(
+                ErrorKind::ArityMismatch,
+                format!("{} expects {} in {}, got {}", function, self.describe_arity(), value.describe(), items.len()),
+            ));
         }
+        self.bind(function, items, scope)
     }
 }
 
-impl From<&Value> for Matcher {
-    fn from(v: &Value) -> Matcher {
+fn bind_props(
+    function: &str,
+    matchers: &[PropMatcher],
+    props: &HashMap<String, RuntimeValue>,
+    scope: &mut Scope,
+) -> Result<(), RuntimeError> {
+    for matcher in matchers.iter() {
+        let value = match (props.get(&matcher.name), &matcher.default) {
+            (Some(value), _) => value.clone(),
+            (None, Some(default)) => RuntimeValue::from(default),
+            (None, None) => {
+                return Err(RuntimeError::new(
+                    ErrorKind::ArityMismatch,
+                    format!("{} expects prop ^{}", function, matcher.name),
+                ));
+            }
+        };
+        matcher.pattern.bind(function, value, scope)?;
+    }
+    Ok(())
+}
+
+impl TryFrom<&Value> for Matcher {
+    type Error = String;
+
+    fn try_from(v: &Value) -> Result<Matcher, String> {
         match v {
-            Value::Symbol(name) => {
-                let mut matchers = Vec::<DataMatcher>::new();
-                if name != "_" {
-                    matchers.push(DataMatcher::new(name.to_string(), 0));
+            Value::Symbol(name) if name == "_" => Ok(Matcher::new(Vec::new(), DataMatcher::default())),
+            Value::Symbol(_) => Ok(Matcher::new(Vec::new(), DataMatcher::try_from(&[v.clone()][..])?)),
+            Value::Array(args) => {
+                let mut props = Vec::new();
+                let mut data = Vec::new();
+                for arg in args.iter() {
+                    match arg {
+                        Value::Map(map) => {
+                            let mut names: Vec<&String> = map.keys().collect();
+                            names.sort();
+                            for name in names {
+                                props.push(PropMatcher {
+                                    name: name.clone(),
+                                    pattern: Pattern::Name(name.clone(), None),
+                                    default: Some(map[name].clone()),
+                                });
+                            }
+                        }
+                        _ => data.push(arg.clone()),
+                    }
+                }
+                let data = DataMatcher::try_from(&data[..])?;
+                // Literals only make sense inside a destructured argument
+                if let Some(arg) = data.params.iter().find_map(|param| match &param.pattern {
+                    Pattern::Literal(literal) => Some(literal),
+                    _ => None,
+                }) {
+                    return Err(format!("invalid argument {}", arg.to_canonical()));
                 }
-                Matcher::new(matchers)
+                Ok(Matcher::new(props, data))
             }
-            Value::Array(args) => {
-                let mut matchers = Vec::<DataMatcher>::new();
-                for (index, name) in args.iter().enumerate() {
-                    matchers.push(DataMatcher::new(name.to_string(), index));
+            _ => Err(format!("invalid argument list {}", v.to_canonical())),
+        }
+    }
+}
+
+impl TryFrom<&[Value]> for DataMatcher {
+    type Error = String;
+
+    fn try_from(args: &[Value]) -> Result<DataMatcher, String> {
+        let mut matcher = DataMatcher::default();
+        let mut rest_name = None;
+        for arg in args.iter() {
+            if let Some(rest) = rest_name {
+                return Err(format!("{} must be the last argument", rest));
+            }
+            if let Value::Symbol(name) = arg {
+                if let Some(name) = name.strip_suffix("...") {
+                    let rest = match name {
+                        "" => return Err(format!("invalid argument {}", arg.to_canonical())),
+                        "_" => Pattern::Ignore,
+                        _ => Pattern::Name(name.to_string(), None),
+                    };
+                    matcher.rest = Some(Box::new(rest));
+                    rest_name = Some(name);
+                    continue;
                 }
-                Matcher::new(matchers)
             }
-            _ => unimplemented!()
+            let param = Param::try_from(arg)?;
+            if param.default.is_none() && matcher.params.iter().any(|p| p.default.is_some()) {
+                return Err(format!("{} must have a default value", arg.to_canonical()));
+            }
+            matcher.params.push(param);
         }
+        Ok(matcher)
+    }
+}
+
+impl TryFrom<&Value> for Param {
+    type Error = String;
+
+    fn try_from(v: &Value) -> Result<Param, String> {
+        if let Value::Gene(gene) = v {
+            if let [Value::Symbol(op), default] = gene.data.as_slice() {
+                if op == "=" && gene.props.is_empty() {
+                    return Ok(Param {
+                        pattern: Pattern::try_from(&gene.kind)?,
+                        default: Some(default.clone()),
+                    });
+                }
+            }
+        }
+        Ok(Param {
+            pattern: Pattern::try_from(v)?,
+            default: None,
+        })
+    }
+}
+
+impl TryFrom<&Value> for Pattern {
+    type Error = String;
+
+    fn try_from(v: &Value) -> Result<Pattern, String> {
+        match v {
+            Value::Symbol(name) if name == "_" => Ok(Pattern::Ignore),
+            Value::Symbol(name) if name.ends_with("...") => Err(format!("invalid argument {}", name)),
+            Value::Symbol(name) => Ok(Pattern::Name(name.clone(), None)),
+            Value::Null | Value::Boolean(_) | Value::Integer(_) | Value::Float(_) | Value::String(_) => {
+                Ok(Pattern::Literal(v.clone()))
+            }
+            Value::Array(items) => Ok(Pattern::Array(DataMatcher::try_from(&items[..])?)),
+            Value::Map(map) => Ok(Pattern::Map(prop_matchers(map)?)),
+            // `` `red ``
+            Value::Gene(gene) if gene.kind == Value::Symbol("#QUOTE".to_string()) && gene.data.len() == 1 => {
+                Ok(Pattern::Literal(gene.data[0].clone()))
+            }
+            Value::Gene(gene) => {
+                let kind = match &gene.kind {
+                    Value::Symbol(name) if name != "_" => Pattern::Literal(gene.kind.clone()),
+                    kind => Pattern::try_from(kind)?,
+                };
+                let props = prop_matchers(&gene.props)?;
+                Ok(Pattern::Gene(Box::new(kind), props, DataMatcher::try_from(&gene.data[..])?))
+            }
+            _ => Err(format!("invalid argument {}", v.to_canonical())),
+        }
+    }
+}
+
+/// Props of a gene or map pattern, sorted by name
+fn prop_matchers(props: &HashMap<String, Value>) -> Result<Vec<PropMatcher>, String> {
+    let mut names: Vec<&String> = props.keys().collect();
+    names.sort();
+    let mut matchers = Vec::new();
+    for name in names {
+        let param = Param::try_from(&props[name])?;
+        matchers.push(PropMatcher {
+            name: name.clone(),
+            pattern: param.pattern,
+            default: param.default,
+        });
     }
+    Ok(matchers)
 }
 
+/// A Gene function. It closes over the scope and namespace it was created in:
+/// each call gets a new scope on top of `parent_scope`, so the function sees the
+/// variables of the enclosing calls as they are when it runs, not as they were
+/// when it was created.
 #[derive(Debug)]
 pub struct Function {
     pub name: String,
     pub args: Matcher,
     pub body: String,
-    pub inherit_scope: bool,
     pub parent_namespace: Rc<RefCell<Namespace>>,
     pub parent_scope: Rc<RefCell<Scope>>,
 }
 
-impl<'a> Function {
+impl Function {
     pub fn new(
         name: String,
         args: Matcher,
         body: String,
-        inherit_scope: bool,
         parent_namespace: Rc<RefCell<Namespace>>,
         parent_scope: Rc<RefCell<Scope>>,
     ) -> Self {
@@ -275,39 +732,81 @@ impl<'a> Function {
             name,
             args,
             body,
-            inherit_scope,
             parent_namespace,
             parent_scope,
         }
     }
 }
 
-#[derive(Debug)]
+pub type NativeBody = dyn Fn(&mut VirtualMachine, Arguments) -> Result<Value, RuntimeError>;
+
+/// A Rust function that can be called from Gene code like any Gene function
+pub struct NativeFunction {
+    pub name: String,
+    pub body: Rc<NativeBody>,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: String, body: F) -> Self
+    where
+        F: Fn(&mut VirtualMachine, Arguments) -> Result<Value, RuntimeError> + 'static,
+    {
+        NativeFunction {
+            name,
+            body: Rc::new(body),
+        }
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "NativeFunction({})", self.name)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Arguments {
-    pub data: Vec<Rc<RefCell<dyn Any>>>,
+    pub props: HashMap<String, RuntimeValue>,
+    pub data: Vec<RuntimeValue>,
 }
 
 impl Arguments {
     pub fn new(
-        data: Vec<Rc<RefCell<dyn Any>>>,
+        props: HashMap<String, RuntimeValue>,
+        data: Vec<RuntimeValue>,
     ) -> Self {
         Arguments {
-            // props,
+            props,
             data,
         }
     }
+
+    /// Plain data arguments, e.g. for `VirtualMachine::call`
+    pub fn from_data(data: Vec<Value>) -> Self {
+        Arguments::new(HashMap::new(), data.into_iter().map(RuntimeValue::from).collect())
+    }
+
+    /// Data argument at `index` converted to a Value
+    pub fn get(&self, index: usize) -> Option<Value> {
+        self.data.get(index).map(RuntimeValue::to_value)
+    }
+
+    /// Prop argument `name` converted to a Value
+    pub fn prop(&self, name: &str) -> Option<Value> {
+        self.props.get(name).map(RuntimeValue::to_value)
+    }
 }
 
 #[derive(Debug)]
 pub struct Module {
-    name: String,
-    methods: HashMap<String, Function>,
+    pub name: String,
+    pub methods: HashMap<String, Function>,
 }
 
 #[derive(Debug)]
 pub struct Address {
-    block_id: String,
-    position: usize,
+    pub block_id: String,
+    pub position: usize,
 }
 
 impl Address {
@@ -324,8 +823,8 @@ impl Address {
 
 #[derive(Debug)]
 pub struct RegAddress {
-    registers_id: String,
-    register: String,
+    pub registers_id: String,
+    pub register: String,
 }
 
 impl RegAddress {
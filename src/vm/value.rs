@@ -0,0 +1,339 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use ordered_float::OrderedFloat;
+
+use super::super::types::{Gene, Value};
+use super::error::RuntimeError;
+use super::types::{Arguments, Function, NativeFunction};
+
+/// Values as the VM sees them. Numbers and booleans are stored inline, everything
+/// else is a reference counted pointer, so copying a value between registers and
+/// scopes never allocates. Collections are shared, not copied.
+#[derive(Clone, Debug)]
+pub enum RuntimeValue {
+    Void,
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(Rc<str>),
+    Symbol(Rc<str>),
+    Array(Rc<RefCell<Vec<RuntimeValue>>>),
+    Map(Rc<RefCell<HashMap<String, RuntimeValue>>>),
+    Gene(Rc<RefCell<RuntimeGene>>),
+    Function(Rc<Function>),
+    NativeFunction(Rc<NativeFunction>),
+    /// Arguments collected for a call
+    Arguments(Rc<RefCell<Arguments>>),
+    /// An error being handled by `catch` / `finally`
+    Error(Rc<RuntimeError>),
+    /// Range(start, end, step), the end is not included
+    Range(i64, i64, i64),
+    /// The state of a `for` loop
+    Iterator(Rc<RefCell<Iter>>),
+}
+
+#[derive(Clone, Debug)]
+pub struct RuntimeGene {
+    pub kind: RuntimeValue,
+    pub props: HashMap<String, RuntimeValue>,
+    pub data: Vec<RuntimeValue>,
+}
+
+impl RuntimeValue {
+    pub fn array(items: Vec<RuntimeValue>) -> Self {
+        RuntimeValue::Array(Rc::new(RefCell::new(items)))
+    }
+
+    pub fn map(map: HashMap<String, RuntimeValue>) -> Self {
+        RuntimeValue::Map(Rc::new(RefCell::new(map)))
+    }
+
+    /// Functions can't be represented as a Value, they come out as `(fn name)`.
+    /// A collection inside itself comes out as the symbol `...`.
+    pub fn to_value(&self) -> Value {
+        self.to_value_in(&mut Vec::new())
+    }
+
+    /// `parents` are the collections being converted around this value
+    fn to_value_in(&self, parents: &mut Vec<*const ()>) -> Value {
+        match self {
+            RuntimeValue::Void | RuntimeValue::Arguments(_) | RuntimeValue::Iterator(_) => Value::Void,
+            RuntimeValue::Null => Value::Null,
+            RuntimeValue::Boolean(b) => Value::Boolean(*b),
+            RuntimeValue::Integer(i) => Value::Integer(*i),
+            RuntimeValue::Float(f) => Value::Float(OrderedFloat(*f)),
+            RuntimeValue::String(s) => Value::String(s.to_string()),
+            RuntimeValue::Symbol(s) => Value::Symbol(s.to_string()),
+            RuntimeValue::Array(items) => nested(Rc::as_ptr(items) as *const (), parents, |parents| {
+                Value::Array(items.borrow().iter().map(|v| v.to_value_in(parents)).collect())
+            }),
+            RuntimeValue::Map(map) => nested(Rc::as_ptr(map) as *const (), parents, |parents| {
+                Value::Map(map.borrow().iter().map(|(k, v)| (k.clone(), v.to_value_in(parents))).collect())
+            }),
+            RuntimeValue::Gene(gene) => nested(Rc::as_ptr(gene) as *const (), parents, |parents| {
+                let gene = gene.borrow();
+                Value::Gene(Box::new(Gene {
+                    kind: gene.kind.to_value_in(parents),
+                    props: gene.props.iter().map(|(k, v)| (k.clone(), v.to_value_in(parents))).collect(),
+                    data: gene.data.iter().map(|v| v.to_value_in(parents)).collect(),
+                }))
+            }),
+            RuntimeValue::Function(f) => function_value(&f.name),
+            RuntimeValue::NativeFunction(f) => function_value(&f.name),
+            RuntimeValue::Error(e) => e.to_value(),
+            RuntimeValue::Range(start, end, step) => Value::Gene(Box::new(Gene {
+                kind: Value::Symbol("range".to_string()),
+                props: HashMap::new(),
+                data: vec![Value::Integer(*start), Value::Integer(*end), Value::Integer(*step)],
+            })),
+        }
+    }
+
+    /// Same rules as `Value::is_truthy`, functions are true
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            RuntimeValue::Void | RuntimeValue::Null => false,
+            RuntimeValue::Boolean(b) => *b,
+            RuntimeValue::Integer(i) => *i != 0,
+            RuntimeValue::Float(f) => *f != 0.,
+            RuntimeValue::String(s) => !s.is_empty(),
+            RuntimeValue::Array(items) => !items.borrow().is_empty(),
+            RuntimeValue::Map(map) => !map.borrow().is_empty(),
+            _ => true,
+        }
+    }
+
+    /// Structural equality. Integers and floats are compared by value, functions by identity.
+    /// Collections containing themselves are equal when no difference is found before
+    /// coming back to a pair being compared already.
+    pub fn equals(&self, other: &RuntimeValue) -> bool {
+        self.equals_in(other, &mut Vec::new())
+    }
+
+    /// `pairs` are the collections being compared around these values
+    fn equals_in(&self, other: &RuntimeValue, pairs: &mut Vec<(*const (), *const ())>) -> bool {
+        match (self, other) {
+            (RuntimeValue::Void, RuntimeValue::Void) | (RuntimeValue::Null, RuntimeValue::Null) => true,
+            (RuntimeValue::Boolean(a), RuntimeValue::Boolean(b)) => a == b,
+            (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => a == b,
+            (RuntimeValue::Integer(a), RuntimeValue::Float(b)) => *a as f64 == *b,
+            (RuntimeValue::Float(a), RuntimeValue::Integer(b)) => *a == *b as f64,
+            (RuntimeValue::Float(a), RuntimeValue::Float(b)) => a == b,
+            (RuntimeValue::String(a), RuntimeValue::String(b)) => a == b,
+            (RuntimeValue::Symbol(a), RuntimeValue::Symbol(b)) => a == b,
+            (RuntimeValue::Array(a), RuntimeValue::Array(b)) => {
+                Rc::ptr_eq(a, b) || compare(pair(a, b), pairs, |pairs| items_equal(&a.borrow(), &b.borrow(), pairs))
+            }
+            (RuntimeValue::Map(a), RuntimeValue::Map(b)) => {
+                Rc::ptr_eq(a, b) || compare(pair(a, b), pairs, |pairs| maps_equal(&a.borrow(), &b.borrow(), pairs))
+            }
+            (RuntimeValue::Gene(a), RuntimeValue::Gene(b)) => {
+                Rc::ptr_eq(a, b)
+                    || compare(pair(a, b), pairs, |pairs| {
+                        let (a, b) = (a.borrow(), b.borrow());
+                        a.kind.equals_in(&b.kind, pairs)
+                            && maps_equal(&a.props, &b.props, pairs)
+                            && items_equal(&a.data, &b.data, pairs)
+                    })
+            }
+            (RuntimeValue::Function(a), RuntimeValue::Function(b)) => Rc::ptr_eq(a, b),
+            (RuntimeValue::NativeFunction(a), RuntimeValue::NativeFunction(b)) => Rc::ptr_eq(a, b),
+            (RuntimeValue::Arguments(a), RuntimeValue::Arguments(b)) => Rc::ptr_eq(a, b),
+            (RuntimeValue::Error(a), RuntimeValue::Error(b)) => Rc::ptr_eq(a, b),
+            (RuntimeValue::Range(a, b, c), RuntimeValue::Range(d, e, f)) => (a, b, c) == (d, e, f),
+            (RuntimeValue::Iterator(a), RuntimeValue::Iterator(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// Describe the value in error messages
+    pub fn describe(&self) -> String {
+        match self {
+            RuntimeValue::Function(f) => format!("function {}", f.name),
+            RuntimeValue::NativeFunction(f) => format!("native function {}", f.name),
+            _ => self.to_value().to_canonical(),
+        }
+    }
+}
+
+/// What a `for` loop iterates over. Arrays and gene data are read as the
+/// loop goes, so items added in the loop are seen. Maps and props are
+/// iterated as `[key value]` pairs sorted by key.
+///
+/// Anything callable works as an iterator too: it is called without
+/// arguments for every item until it returns void.
+#[derive(Debug)]
+pub enum Iter {
+    Items(Rc<RefCell<Vec<RuntimeValue>>>, usize),
+    GeneData(Rc<RefCell<RuntimeGene>>, usize),
+    Pairs(std::vec::IntoIter<(String, RuntimeValue)>),
+    /// Range(next, end, step)
+    Range(i64, i64, i64),
+    /// Function(function, whether a call for the next item is in progress)
+    Function(RuntimeValue, bool),
+}
+
+/// See `Iter::next`
+pub enum Step {
+    Item(RuntimeValue),
+    Done,
+    /// Call the function of `Iter::Function`, its result is the next item
+    Call,
+}
+
+impl Iter {
+    /// Iterate over `value`, or over its props if `props` is set
+    pub fn new(value: &RuntimeValue, props: bool) -> Result<Self, RuntimeError> {
+        let iter = match value {
+            RuntimeValue::Map(map) => Iter::pairs(&map.borrow()),
+            RuntimeValue::Gene(gene) if props => Iter::pairs(&gene.borrow().props),
+            _ if props => return Err(RuntimeError::type_error(format!("can not iterate over props of {}", value.describe()))),
+            RuntimeValue::Array(items) => Iter::Items(items.clone(), 0),
+            RuntimeValue::Gene(gene) => Iter::GeneData(gene.clone(), 0),
+            RuntimeValue::Range(start, end, step) => Iter::Range(*start, *end, *step),
+            RuntimeValue::Function(_) | RuntimeValue::NativeFunction(_) => Iter::Function(value.clone(), false),
+            _ => return Err(RuntimeError::type_error(format!("can not iterate over {}", value.describe()))),
+        };
+        Ok(iter)
+    }
+
+    fn pairs(map: &HashMap<String, RuntimeValue>) -> Self {
+        let mut pairs: Vec<(String, RuntimeValue)> = map.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        pairs.sort_by(|a, b| a.0.cmp(&b.0));
+        Iter::Pairs(pairs.into_iter())
+    }
+
+    /// `result` is what the last `Step::Call` returned
+    pub fn next(&mut self, result: &RuntimeValue) -> Step {
+        match self {
+            Iter::Items(items, index) => match items.borrow().get(*index) {
+                Some(item) => {
+                    *index += 1;
+                    Step::Item(item.clone())
+                }
+                None => Step::Done,
+            },
+            Iter::GeneData(gene, index) => match gene.borrow().data.get(*index) {
+                Some(item) => {
+                    *index += 1;
+                    Step::Item(item.clone())
+                }
+                None => Step::Done,
+            },
+            Iter::Pairs(pairs) => match pairs.next() {
+                Some((key, value)) => Step::Item(RuntimeValue::array(vec![RuntimeValue::String(Rc::from(key.as_str())), value])),
+                None => Step::Done,
+            },
+            Iter::Range(next, end, step) => {
+                if (*step > 0 && *next < *end) || (*step < 0 && *next > *end) {
+                    let item = *next;
+                    // Stop instead of overflowing
+                    *next = next.checked_add(*step).unwrap_or(*end);
+                    Step::Item(RuntimeValue::Integer(item))
+                } else {
+                    Step::Done
+                }
+            }
+            Iter::Function(_, waiting) => {
+                if !*waiting {
+                    *waiting = true;
+                    return Step::Call;
+                }
+                *waiting = false;
+                match result {
+                    RuntimeValue::Void => Step::Done,
+                    item => Step::Item(item.clone()),
+                }
+            }
+        }
+    }
+}
+
+impl PartialEq for RuntimeValue {
+    fn eq(&self, other: &RuntimeValue) -> bool {
+        self.equals(other)
+    }
+}
+
+/// Convert a collection with `convert`, unless it is one of its own `parents`
+fn nested<F>(ptr: *const (), parents: &mut Vec<*const ()>, convert: F) -> Value
+where
+    F: FnOnce(&mut Vec<*const ()>) -> Value,
+{
+    if parents.contains(&ptr) {
+        return Value::Symbol("...".to_string());
+    }
+    parents.push(ptr);
+    let value = convert(parents);
+    parents.pop();
+    value
+}
+
+fn pair<T, U>(a: &Rc<T>, b: &Rc<U>) -> (*const (), *const ()) {
+    (Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ())
+}
+
+/// Compare two collections with `compare`, a pair met again inside itself is taken as equal
+fn compare<F>(pair: (*const (), *const ()), pairs: &mut Vec<(*const (), *const ())>, compare: F) -> bool
+where
+    F: FnOnce(&mut Vec<(*const (), *const ())>) -> bool,
+{
+    if pairs.contains(&pair) {
+        return true;
+    }
+    pairs.push(pair);
+    let equal = compare(pairs);
+    pairs.pop();
+    equal
+}
+
+fn items_equal(a: &[RuntimeValue], b: &[RuntimeValue], pairs: &mut Vec<(*const (), *const ())>) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.equals_in(b, pairs))
+}
+
+fn maps_equal(
+    a: &HashMap<String, RuntimeValue>,
+    b: &HashMap<String, RuntimeValue>,
+    pairs: &mut Vec<(*const (), *const ())>,
+) -> bool {
+    a.len() == b.len() && a.iter().all(|(key, value)| b.get(key).is_some_and(|other| value.equals_in(other, pairs)))
+}
+
+fn function_value(name: &str) -> Value {
+    Value::Gene(Box::new(Gene {
+        kind: Value::Symbol("fn".to_string()),
+        props: HashMap::new(),
+        data: vec![Value::Symbol(name.to_string())],
+    }))
+}
+
+impl From<&Value> for RuntimeValue {
+    /// Streams become arrays
+    fn from(v: &Value) -> Self {
+        match v {
+            Value::Void => RuntimeValue::Void,
+            Value::Null => RuntimeValue::Null,
+            Value::Boolean(b) => RuntimeValue::Boolean(*b),
+            Value::Integer(i) => RuntimeValue::Integer(*i),
+            Value::Float(f) => RuntimeValue::Float(f.into_inner()),
+            Value::String(s) => RuntimeValue::String(Rc::from(s.as_str())),
+            Value::Symbol(s) => RuntimeValue::Symbol(Rc::from(s.as_str())),
+            Value::Array(items) | Value::Stream(items) => RuntimeValue::array(items.iter().map(RuntimeValue::from).collect()),
+            Value::Map(map) => RuntimeValue::map(map.iter().map(|(k, v)| (k.clone(), RuntimeValue::from(v))).collect()),
+            Value::Gene(gene) => RuntimeValue::Gene(Rc::new(RefCell::new(RuntimeGene {
+                kind: RuntimeValue::from(&gene.kind),
+                props: gene.props.iter().map(|(k, v)| (k.clone(), RuntimeValue::from(v))).collect(),
+                data: gene.data.iter().map(RuntimeValue::from).collect(),
+            }))),
+        }
+    }
+}
+
+impl From<Value> for RuntimeValue {
+    fn from(v: Value) -> Self {
+        RuntimeValue::from(&v)
+    }
+}
@@ -0,0 +1,110 @@
+#[macro_use]
+extern crate gene;
+
+mod common;
+
+use gene::runtime::{EvalError, Runtime};
+use gene::types::Value;
+
+use common::{eval, eval_error};
+
+#[test]
+fn test_items() {
+    assert_eq!(eval("(var a [1 2 3]) (a .0)"), Value::Integer(1));
+    assert_eq!(eval("(var a [1 2 3]) (a .-1)"), Value::Integer(3));
+    assert_eq!(eval("(var a [1 2 3]) [(a .3) (a .-4)]"), Value::Array(vec![Value::Void, Value::Void]));
+    assert_eq!(eval("(var a [[1 2] [3 4]]) (a .1 .0)"), Value::Integer(3));
+
+    assert_eq!(eval("(var a [1 2 3]) ((a .1) = 20) a"), gene!([1 20 3]));
+    assert_eq!(eval("(var a [1]) ((a .2) = 3) a"), Value::Array(vec![Value::Integer(1), Value::Void, Value::Integer(3)]));
+    assert_eq!(eval("(var a [1 2 3]) ((a .-1) = 30) a"), gene!([1 2 30]));
+    // The value of an assignment is the value assigned
+    assert_eq!(eval("(var a []) ((a .0) = 5)"), Value::Integer(5));
+}
+
+#[test]
+fn test_props() {
+    assert_eq!(eval("(var m {^a 1 ^b {^c 2}}) [(m .a) (m .b .c)]"), gene!([1 2]));
+    assert_eq!(eval("(var m {}) (m .x)"), Value::Void);
+    assert_eq!(eval("(var m {^a 1}) ((m .b) = 2) m"), gene!({^a 1 ^b 2}));
+    assert_eq!(eval("(var m {^a {^b 1}}) ((m .a .b) = 3) m"), gene!({^a {^b 3}}));
+    assert_eq!(eval("(var m {^items [1 2]}) ((m .items .1) = 3) (m .items)"), gene!([1 3]));
+
+    // Collections are shared, not copied
+    assert_eq!(eval("(var m {^a 1}) (var n m) ((n .a) = 2) (m .a)"), Value::Integer(2));
+}
+
+#[test]
+fn test_genes() {
+    let mut runtime = Runtime::new();
+    runtime.set_global("g", gene!((item ^name "x" 10 20)));
+    assert_eq!(runtime.eval("[(g .name) (g .0) (g .-1)]").unwrap(), gene!(["x" 10 20]));
+    runtime.eval("((g .name) = \"y\") ((g .1) = 30)").unwrap();
+    assert_eq!(runtime.get_global("g"), Some(gene!((item ^name "y" 10 30))));
+}
+
+#[test]
+fn test_computed_keys() {
+    let source = "
+        (var m {^a 1 ^b 2})
+        (var sum 0)
+        (for k in [\"a\" \"b\"] (sum = (sum + (m @ k))))
+        sum
+    ";
+    assert_eq!(eval(source), Value::Integer(3));
+    assert_eq!(eval("(var a [1 2 3]) (var i 1) [(a @ i) (a @ (i - 2))]"), gene!([2 3]));
+    assert_eq!(eval("(var m {^a [1 2]}) (m @ \"a\" .1)"), Value::Integer(2));
+
+    let source = "
+        (var counts {})
+        (for w in [\"x\" \"y\" \"x\"]
+            ((counts @ w) = (((counts @ w) || 0) + 1))
+        )
+        counts
+    ";
+    assert_eq!(eval(source), gene!({^x 2 ^y 1}));
+    assert_eq!(eval("(var a [0 0]) (var i 1) ((a @ i) = 5) a"), gene!([0 5]));
+
+    // Works on values computed at runtime
+    assert_eq!(eval("(fn f _ {^a [1 2]}) ((f) .a .0)"), Value::Integer(1));
+}
+
+#[test]
+fn test_access_errors() {
+    assert_eq!(eval_error("(var a 1) (a .0)").message, "can not get item of 1");
+    assert_eq!(eval_error("(var a [1]) (a .x)").message, "can not get prop of [1]");
+    assert_eq!(eval_error("(var m {}) (m .0)").message, "can not get item of {}");
+    assert_eq!(eval_error("(var m {}) ((m .0) = 1)").message, "can not set item of {}");
+    assert_eq!(eval_error("(var a 1) ((a .x) = 1)").message, "can not set prop of 1");
+    assert_eq!(eval_error("(var a [1]) ((a .-2) = 1)").message, "index -2 is out of range for 1 items");
+    assert_eq!(
+        eval_error("(var a [1]) (a @ 1.5)").message,
+        "can not use 1.5 as a key, expected an integer, string or symbol",
+    );
+
+    for source in ["(var a []) (a .0 ^x 1)", "(var a []) (a .0 1)", "(var a []) (a @)"].iter() {
+        match Runtime::new().eval(source) {
+            Err(EvalError::Compile(errors)) => assert_eq!(errors[0].message, "expected (target .name .index @ key ...)"),
+            result => panic!("unexpected result {:?} for {}", result, source),
+        }
+    }
+}
+
+#[test]
+fn test_cyclic_collections() {
+    // A collection inside itself is shown as ...
+    assert_eq!(eval("(var a [1]) ((a .0) = a) a"), gene!([...]));
+    assert_eq!(eval("(var m {^x 1}) ((m .self) = m) m"), gene!({^x 1 ^self ...}));
+    assert_eq!(eval("(var a [1]) (var b [a a]) ((a .0) = b) b"), gene!([[...] [...]]));
+
+    // Shared but not cyclic values are shown in full
+    assert_eq!(eval("(var a [1]) [a a]"), gene!([[1] [1]]));
+
+    // Cyclic values are equal if no difference is found
+    assert_eq!(eval("(var a [1]) ((a .0) = a) (var b [1]) ((b .0) = b) (a == b)"), Value::Boolean(true));
+    assert_eq!(eval("(var a [1 2]) ((a .0) = a) (var b [1 3]) ((b .0) = b) (a == b)"), Value::Boolean(false));
+    assert_eq!(eval("(var a [1]) ((a .0) = a) (a == [a])"), Value::Boolean(true));
+
+    assert_eq!(eval_error("(var m {}) ((m .self) = m) (throw m)").message, "uncaught exception {^self ...}");
+    assert_eq!(eval_error("(var a []) ((a .0) = a) (a + 1)").message, "unsupported operand types for +: [...] and 1");
+}
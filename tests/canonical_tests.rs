@@ -0,0 +1,108 @@
+#[macro_use]
+extern crate gene;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use ordered_float::OrderedFloat;
+
+use gene::parser::Parser;
+use gene::types::Gene;
+use gene::types::Value;
+
+#[test]
+fn test_canonical_scalars() {
+    assert_eq!(Value::Null.to_canonical(), "null");
+    assert_eq!(Value::Boolean(true).to_canonical(), "true");
+    assert_eq!(Value::Integer(-10).to_canonical(), "-10");
+    assert_eq!(Value::Float(OrderedFloat(1.0)).to_canonical(), "1.0");
+    assert_eq!(Value::Float(OrderedFloat(-0.0)).to_canonical(), "0.0");
+    assert_eq!(Value::Float(OrderedFloat(1e300)).to_canonical(), "1.0e300");
+    assert_eq!(Value::Float(OrderedFloat(f64::NAN)).to_canonical(), "NaN");
+    assert_eq!(Value::String("a\"b\\c".to_string()).to_canonical(), "\"a\\\"b\\\\c\"");
+    assert_eq!(Value::Symbol("a b".to_string()).to_canonical(), "a\\ b");
+    assert_eq!(Value::Symbol("true".to_string()).to_canonical(), "\\true");
+    assert_eq!(Value::Symbol("1a".to_string()).to_canonical(), "\\1a");
+}
+
+#[test]
+fn test_canonical_collections() {
+    let map = Value::Map(map! {
+        "b" => Value::Integer(2),
+        "a" => Value::Integer(1),
+        "c" => Value::Array(vec![Value::Integer(3), Value::Null]),
+    });
+    assert_eq!(map.to_canonical(), "{^a 1 ^b 2 ^c [3 null]}");
+
+    let mut gene = Gene::new(Value::Symbol("div".to_string()));
+    gene.props.insert("z".to_string(), Value::Boolean(false));
+    gene.props.insert("id".to_string(), Value::String("x".to_string()));
+    gene.data.push(Value::Integer(1));
+    let gene = Value::Gene(Box::new(gene));
+    assert_eq!(gene.to_canonical(), "(div ^id \"x\" ^z false 1)");
+}
+
+#[test]
+fn test_canonical_round_trip() {
+    let values = vec![
+        "(div ^class \"a b\" ^^hidden [1 2.5 {^x null}] \"t\\\"ext\")",
+        "[\\true \\1a a\\ b \\#c]",
+        "{^k1 (f 1) ^k2 -1.5}",
+    ];
+    for s in values {
+        let parsed = Parser::new(s).parse().unwrap();
+        let canonical = parsed.to_canonical();
+        let reparsed = Parser::new(&canonical).parse().unwrap();
+        assert_eq!(parsed, reparsed);
+        assert_eq!(canonical, reparsed.to_canonical());
+    }
+}
+
+#[test]
+fn test_content_hash() {
+    let a = Parser::new("{^a 1 ^b [1 2] ^c (x ^p 1 ^q 2)}").parse().unwrap();
+    let b = Parser::new("{^c (x ^q 2 ^p 1) ^b [1 2] ^a 1}").parse().unwrap();
+    let c = Parser::new("{^a 1 ^b [1 2] ^c (x ^p 1 ^q 3)}").parse().unwrap();
+    assert_eq!(a.content_hash(), b.content_hash());
+    assert_ne!(a.content_hash(), c.content_hash());
+    // FNV-1a of "1"
+    assert_eq!(Value::Integer(1).content_hash(), 0xaf63_ac4c_8601_9afc);
+}
+
+#[test]
+fn test_canonical_streams() {
+    let stream = Value::Stream(vec![Value::Integer(1)]);
+    assert_eq!(stream.to_canonical(), "(#stream 1)");
+    assert_eq!(Value::Stream(vec![]).to_canonical(), "(#stream)");
+    assert_eq!(Parser::new("a [b]").parse().unwrap().to_canonical(), "(#stream a [b])");
+    // A stream is not hashed like its item, or like a gene that looks like it
+    assert_ne!(stream.content_hash(), Value::Integer(1).content_hash());
+    let gene = Value::Gene(Box::new(Gene {
+        kind: Value::Symbol("#stream".to_string()),
+        props: HashMap::new(),
+        data: vec![Value::Integer(1)],
+    }));
+    assert_eq!(gene.to_canonical(), "(\\#stream 1)");
+    assert_ne!(stream.content_hash(), gene.content_hash());
+}
+
+#[test]
+fn test_values_as_keys() {
+    let a = Parser::new("(x ^p 1 ^q 2)").parse().unwrap();
+    let b = Parser::new("(x ^q 2 ^p 1)").parse().unwrap();
+
+    let mut set = HashSet::new();
+    set.insert(a.clone());
+    set.insert(b.clone());
+    assert_eq!(set.len(), 1);
+
+    let mut map = HashMap::new();
+    map.insert(a, 1);
+    assert_eq!(map.get(&b), Some(&1));
+
+    let mut tree = BTreeMap::new();
+    tree.insert(Value::Integer(2), "2");
+    tree.insert(Value::String("a".to_string()), "a");
+    tree.insert(Value::Null, "null");
+    tree.insert(Value::Integer(1), "1");
+    assert_eq!(tree.values().cloned().collect::<Vec<_>>(), vec!["null", "1", "2", "a"]);
+}
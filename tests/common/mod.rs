@@ -0,0 +1,30 @@
+//! Helpers shared by the tests that run Gene code, each test crate uses some of them
+#![allow(dead_code)]
+
+use gene::runtime::{EvalError, Runtime};
+use gene::types::Value;
+use gene::vm::error::RuntimeError;
+
+pub fn eval(source: &str) -> Value {
+    Runtime::new().eval(source).unwrap()
+}
+
+/// The runtime error `source` fails with
+pub fn eval_error(source: &str) -> RuntimeError {
+    match Runtime::new().eval(source) {
+        Err(EvalError::Runtime(e)) => e,
+        result => panic!("unexpected result {:?} for {}", result, source),
+    }
+}
+
+/// The messages of the compile errors in `source`
+pub fn compile_errors(source: &str) -> Vec<String> {
+    match Runtime::new().eval(source) {
+        Err(EvalError::Compile(errors)) => errors.into_iter().map(|e| e.message).collect(),
+        result => panic!("unexpected result {:?} for {}", result, source),
+    }
+}
+
+pub fn string(s: &str) -> Value {
+    Value::String(s.to_string())
+}
@@ -5,10 +5,13 @@ use std::collections::HashMap;
 
 use ordered_float::OrderedFloat;
 
-use gene::compiler2::Compiler;
+use gene::compiler::Instruction;
+use gene::compiler2::{Compiler, Span};
 use gene::parser::Parser;
+use gene::runtime::{EvalError, Runtime};
 use gene::types::Value;
 use gene::vm::VirtualMachine;
+use gene::vm::error::{ErrorKind, StackFrame};
 
 #[test]
 fn test_basic_stmts() {
@@ -16,101 +19,83 @@ fn test_basic_stmts() {
         let mut parser = Parser::new("null");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Null);
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Null);
     }
     {
         let mut parser = Parser::new("1");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("1.1");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Float(OrderedFloat(1.1)));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Float(OrderedFloat(1.1)));
     }
     {
         let mut parser = Parser::new("true");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Boolean(true));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Boolean(true));
     }
     {
         let mut parser = Parser::new("\"ab\"");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::String("ab".to_string()));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::String("ab".to_string()));
     }
     {
         let mut parser = Parser::new("[]");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Array(Vec::new()));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Array(Vec::new()));
     }
     {
         let mut parser = Parser::new("[1]");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Array(vec![Value::Integer(1)]));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Array(vec![Value::Integer(1)]));
     }
     {
         let mut parser = Parser::new("{}");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Map(HashMap::new()));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Map(HashMap::new()));
     }
     {
         let mut parser = Parser::new("{^key 1}");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
+        let result = VirtualMachine::new().load_module(&module).unwrap();
         assert_eq!(
-            *result,
+            result,
             Value::Map(map! {
                 "key" => Value::Integer(1),
             })
@@ -129,13 +114,11 @@ fn test_variables() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("
@@ -145,13 +128,11 @@ fn test_variables() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Array(vec![
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Array(vec![
             Value::Integer(1),
             Value::Integer(2),
             Value::Integer(3),
@@ -165,13 +146,11 @@ fn test_variables() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Array(vec![
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Array(vec![
             Value::Integer(1),
             Value::Integer(2),
             Value::Integer(3),
@@ -185,14 +164,12 @@ fn test_variables() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
+        let result = VirtualMachine::new().load_module(&module).unwrap();
         assert_eq!(
-            *result,
+            result,
             Value::Map(map! {
                 "ka" => Value::Integer(1),
                 "kb" => Value::Integer(2),
@@ -207,14 +184,12 @@ fn test_variables() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
+        let result = VirtualMachine::new().load_module(&module).unwrap();
         assert_eq!(
-            *result,
+            result,
             Value::Map(map! {
                 "ka" => Value::Integer(1),
                 "kb" => Value::Integer(3),
@@ -231,13 +206,11 @@ fn test_binary_operations() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(3));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(3));
     }
     {
         let mut parser = Parser::new("
@@ -245,13 +218,11 @@ fn test_binary_operations() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Boolean(true));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Boolean(true));
     }
 }
 
@@ -265,13 +236,11 @@ fn test_assignments() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(2));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(2));
     }
 }
 
@@ -283,13 +252,11 @@ fn test_ifs() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("
@@ -297,13 +264,11 @@ fn test_ifs() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(2));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(2));
     }
     {
         let mut parser = Parser::new("
@@ -311,13 +276,11 @@ fn test_ifs() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(2));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(2));
     }
     {
         let mut parser = Parser::new("
@@ -325,13 +288,11 @@ fn test_ifs() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(4));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(4));
     }
 }
 
@@ -347,13 +308,11 @@ fn test_loops() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(2));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(2));
     }
     {
         let mut parser = Parser::new("
@@ -366,13 +325,11 @@ fn test_loops() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
 }
 
@@ -385,13 +342,11 @@ fn test_functions() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("
@@ -400,13 +355,11 @@ fn test_functions() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("
@@ -415,13 +368,11 @@ fn test_functions() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(3));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(3));
     }
     {
         let mut parser = Parser::new("
@@ -429,13 +380,11 @@ fn test_functions() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("
@@ -445,13 +394,11 @@ fn test_functions() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("
@@ -461,13 +408,11 @@ fn test_functions() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(3));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(3));
     }
 }
 
@@ -486,12 +431,187 @@ fn test_fibonacci() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(8));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(8));
     }
 }
+
+#[test]
+fn test_compile_errors() {
+    {
+        let mut parser = Parser::new("(1 = 2)");
+        let mut compiler = Compiler::new();
+        let errors = compiler.compile(parser.parse().unwrap()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "expected (name = value)");
+        assert_eq!(errors[0].form, gene!((#(Value::Integer(1)) = 2)));
+        assert_eq!(errors[0].span, None);
+    }
+    {
+        // All invalid forms are reported
+        let mut parser = Parser::new("
+            (var 1 2)
+            (fn f)
+            (fn g [1] 2)
+            (if)
+            (while)
+            (1 +)
+            (1 &&)
+            (! 1 2)
+        ");
+        let mut compiler = Compiler::new();
+        let errors = compiler.compile(parser.parse().unwrap()).unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.message.clone()).collect();
+        assert_eq!(messages, vec![
+            "expected (var name value)",
+            "expected (fn name args body...)",
+            "invalid argument 1",
+            "expected (if condition body... else body...)",
+            "expected (while condition body...)",
+            "+ expects two operands",
+            "&& expects two operands",
+            "expected (! value)",
+        ]);
+    }
+    {
+        // Errors inside function bodies
+        let mut parser = Parser::new("(fn f _ (a = 1) (var b))");
+        let mut compiler = Compiler::new();
+        let errors = compiler.compile(parser.parse().unwrap()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "expected (var name value): (var b)");
+    }
+}
+
+#[test]
+fn test_compile_error_spans() {
+    let source = "(var a 1)\n(break)\n(fn f _ (break) (1 +))\n(break)";
+    let (value, spans) = Parser::new(source).parse_with_spans().unwrap();
+    let mut compiler = Compiler::new();
+    let errors = compiler.compile_with_spans(value, &spans).unwrap_err();
+    let spans: Vec<Option<Span>> = errors.iter().map(|e| e.span).collect();
+    assert_eq!(spans, vec![
+        Some(Span { start: 10, end: 17 }),
+        Some(Span { start: 26, end: 33 }),
+        Some(Span { start: 34, end: 39 }),
+        Some(Span { start: 41, end: 48 }),
+    ]);
+    assert_eq!(errors[2].to_string(), "+ expects two operands: (1 +) at 34..39");
+
+    // Runtime::eval keeps the spans
+    match Runtime::new().eval("(var x 1)\n  (var)") {
+        Err(EvalError::Compile(errors)) => assert_eq!(errors[0].span, Some(Span { start: 12, end: 17 })),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn test_runtime_errors() {
+    {
+        let mut parser = Parser::new("a");
+        let mut compiler = Compiler::new();
+        compiler.compile(parser.parse().unwrap()).unwrap();
+        let error = VirtualMachine::new().load_module(&compiler.module).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UndefinedVariable);
+        assert_eq!(error.message, "undefined variable a");
+        assert_eq!(error.stack, vec![StackFrame::new("__default__".to_string(), 1)]);
+    }
+    {
+        let mut parser = Parser::new("(a = 1)");
+        let mut compiler = Compiler::new();
+        compiler.compile(parser.parse().unwrap()).unwrap();
+        let error = VirtualMachine::new().load_module(&compiler.module).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UndefinedVariable);
+    }
+    {
+        let mut parser = Parser::new("(1 + \"a\")");
+        let mut compiler = Compiler::new();
+        compiler.compile(parser.parse().unwrap()).unwrap();
+        let error = VirtualMachine::new().load_module(&compiler.module).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TypeError);
+        assert_eq!(error.message, "unsupported operand types for +: 1 and \"a\"");
+    }
+    {
+        let mut parser = Parser::new("(var a 1) (a 2)");
+        let mut compiler = Compiler::new();
+        compiler.compile(parser.parse().unwrap()).unwrap();
+        let error = VirtualMachine::new().load_module(&compiler.module).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::NotCallable);
+    }
+    {
+        let mut parser = Parser::new("(fn f [a b] (a + b)) (f 1)");
+        let mut compiler = Compiler::new();
+        compiler.compile(parser.parse().unwrap()).unwrap();
+        let error = VirtualMachine::new().load_module(&compiler.module).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::ArityMismatch);
+    }
+    {
+        // The stack follows Gene function calls
+        let mut parser = Parser::new("
+            (fn f a (a + b))
+            (fn g a ((f a) + 1))
+            (g 1)
+        ");
+        let mut compiler = Compiler::new();
+        compiler.compile(parser.parse().unwrap()).unwrap();
+        let mut vm = VirtualMachine::new();
+        let error = vm.load_module(&compiler.module).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UndefinedVariable);
+        let names: Vec<&str> = error.stack.iter().map(|frame| frame.name.as_str()).collect();
+        assert_eq!(names, vec!["f", "g", "__default__"]);
+        assert!(error.to_string().starts_with("undefined variable b\n    at f ("));
+
+        // The VM is still usable
+        let mut parser = Parser::new("1");
+        let mut compiler = Compiler::new();
+        compiler.compile(parser.parse().unwrap()).unwrap();
+        assert_eq!(vm.load_module(&compiler.module).unwrap(), Value::Integer(1));
+    }
+}
+
+#[test]
+fn test_variable_resolution() {
+    let mut parser = Parser::new("
+        (var x 1)
+        (fn outer a
+          (var b 2)
+          (fn inner c (a + (b + (c + x))))
+        )
+        ((outer 3) 4)
+    ");
+    let mut compiler = Compiler::new();
+    compiler.compile(parser.parse().unwrap()).unwrap();
+    let module = compiler.module;
+    assert_eq!(module.globals, vec!["x", "outer"]);
+
+    let outer = module.blocks.values().find(|block| block.name == "outer").unwrap();
+    assert_eq!(*outer.locals, vec!["a", "b", "inner"]);
+    let inner = module.blocks.values().find(|block| block.name == "inner").unwrap();
+    assert_eq!(*inner.locals, vec!["c"]);
+    let instructions: Vec<String> = inner.instructions.iter().map(|instr| instr.to_string()).collect();
+    assert_eq!(instructions, vec![
+        "(GetUpvalue 1 0)", "(CopyFromDefault 0)",
+        "(GetUpvalue 1 1)", "(CopyFromDefault 1)",
+        "(GetLocal 0)", "(CopyFromDefault 2)",
+        "(GetGlobal 0)",
+        "(2 + Default)", "(1 + Default)", "(0 + Default)",
+        "(CallEnd)",
+    ]);
+
+    // Only function bodies have tail calls
+    let default_block = module.get_default_block();
+    assert!(matches!(default_block.instructions[default_block.len() - 2], Instruction::Call(..)));
+
+    let result = VirtualMachine::new().load_module(&module).unwrap();
+    assert_eq!(result, Value::Integer(10));
+
+    let mut parser = Parser::new("(fn f n (if (n > 0) (f (n - 1)) else (g n)))");
+    let mut compiler = Compiler::new();
+    compiler.compile(parser.parse().unwrap()).unwrap();
+    let f = compiler.module.blocks.values().find(|block| block.name == "f").unwrap();
+    let tail_calls = f.instructions.iter().filter(|instr| matches!(instr, Instruction::TailCall(..))).count();
+    assert_eq!(tail_calls, 2);
+}
@@ -0,0 +1,356 @@
+#[macro_use]
+extern crate gene;
+
+mod common;
+
+use gene::runtime::Runtime;
+use gene::types::Value;
+use gene::vm::types::Arguments;
+
+use common::{eval, eval_error, compile_errors};
+
+#[test]
+fn test_return() {
+    assert_eq!(eval("(fn f _ (return 1) 2) (f)"), Value::Integer(1));
+    assert_eq!(eval("(fn f _ (return) 2) (f)"), Value::Void);
+    assert_eq!(eval("(fn f a (if (a > 0) (return \"positive\")) \"other\") [(f 1) (f 0)]"), gene!(["positive" "other"]));
+
+    // From nested loops
+    let source = "
+        (fn find n
+            (var i 0)
+            (while true
+                (var j 0)
+                (while (j < i)
+                    (if ((i * j) == n) (return [i j]))
+                    (j = (j + 1))
+                )
+                (i = (i + 1))
+            )
+        )
+        (find 12)
+    ";
+    assert_eq!(eval(source), gene!([4 3]));
+
+    // Only the innermost function returns
+    let source = "
+        (fn f _
+            (var g (fnx _ (return 1) 2))
+            ((g) + 10)
+        )
+        (f)
+    ";
+    assert_eq!(eval(source), Value::Integer(11));
+}
+
+#[test]
+fn test_return_without_caller() {
+    // Functions called from Rust start a new execution, return ends it
+    let mut runtime = Runtime::new();
+    runtime.eval("(fn f _ (return 1) 2) (fn g _ (while true (return 5)) 6)").unwrap();
+    assert_eq!(runtime.call("f", vec![]).unwrap(), Value::Integer(1));
+    assert_eq!(runtime.call("g", vec![]).unwrap(), Value::Integer(5));
+
+    // Same for functions called back from native functions
+    runtime.register_native("apply", |vm, args| {
+        let function = args.data[0].clone();
+        vm.call(&function, Arguments::from_data(vec![]))
+    });
+    assert_eq!(runtime.eval("[(apply f) (apply g)]").unwrap(), gene!([1 5]));
+    let source = "
+        (fn h _
+            (var x (apply (fnx _ (for i in (range 0 10) (if (i == 3) (return i))) 100)))
+            (x + 10)
+        )
+        (h)
+    ";
+    assert_eq!(runtime.eval(source).unwrap(), Value::Integer(13));
+}
+
+#[test]
+fn test_break_and_continue() {
+    let source = "
+        (var i 0)
+        (var sum 0)
+        (while (i < 10)
+            (i = (i + 1))
+            (if ((i % 2) == 0) (continue))
+            (if (i > 7) (break))
+            (sum = (sum + i))
+        )
+        [i sum]
+    ";
+    assert_eq!(eval(source), gene!([9 16]));
+
+    // break and continue leave the innermost loop
+    let source = "
+        (var i 0)
+        (var count 0)
+        (while (i < 3)
+            (i = (i + 1))
+            (var j 0)
+            (while true
+                (j = (j + 1))
+                (if (j > 2) (break))
+                (count = (count + 1))
+            )
+        )
+        count
+    ";
+    assert_eq!(eval(source), Value::Integer(6));
+}
+
+#[test]
+fn test_labeled_loops() {
+    let source = "
+        (var count 0)
+        (var i 0)
+        (while ^label outer (i < 5)
+            (i = (i + 1))
+            (var j 0)
+            (while (j < 5)
+                (j = (j + 1))
+                (if (j == i) (continue outer))
+                (if ((i + j) > 6) (break outer))
+                (count = (count + 1))
+            )
+        )
+        [i count]
+    ";
+    // Counted: (2 1) (3 1) (3 2) (4 1) (4 2), then (4 3) breaks out
+    assert_eq!(eval(source), gene!([4 5]));
+
+    // Labels are looked up from the innermost loop
+    let source = "
+        (var n 0)
+        (while ^label l true
+            (while ^label l true
+                (n = (n + 1))
+                (break l)
+            )
+            (n = (n + 10))
+            (break l)
+        )
+        n
+    ";
+    assert_eq!(eval(source), Value::Integer(11));
+}
+
+#[test]
+fn test_jumps_through_finally() {
+    let source = "
+        (var log [])
+        (fn f _
+            (try
+                (try
+                    (return 1)
+                finally
+                    (log = [log \"inner\"])
+                )
+            finally
+                (log = [log \"outer\"])
+            )
+            2
+        )
+        [(f) log]
+    ";
+    assert_eq!(eval(source), gene!([1 [[[] "inner"] "outer"]]));
+
+    let source = "
+        (var n 0)
+        (var i 0)
+        (while (i < 3)
+            (i = (i + 1))
+            (try
+                (if (i == 1) (continue))
+                (if (i == 2) (break))
+            finally
+                (n = (n + 1))
+            )
+        )
+        [i n]
+    ";
+    assert_eq!(eval(source), gene!([2 2]));
+
+    // Errors in the copy of the finally code made for a jump are not caught by its own try
+    let source = "
+        (fn f _
+            (try
+                (try
+                    (return 1)
+                catch e
+                    2
+                finally
+                    (throw 3)
+                )
+            catch e
+                (e + 10)
+            )
+        )
+        (f)
+    ";
+    assert_eq!(eval(source), Value::Integer(13));
+
+    // Loops inside the finally code don't see the jumps around them
+    let source = "
+        (var n 0)
+        (fn f _
+            (try
+                (return 1)
+            finally
+                (while true
+                    (n = (n + 1))
+                    (break)
+                )
+            )
+        )
+        [(f) n]
+    ";
+    assert_eq!(eval(source), gene!([1 1]));
+}
+
+#[test]
+fn test_invalid_jumps() {
+    assert_eq!(compile_errors("(break)"), vec!["break outside of a loop"]);
+    assert_eq!(compile_errors("(continue)"), vec!["continue outside of a loop"]);
+    assert_eq!(compile_errors("(while true (break outer))"), vec!["no loop labeled outer"]);
+    assert_eq!(compile_errors("(while true (break 1))"), vec!["expected (break label)"]);
+    assert_eq!(compile_errors("(while ^label 1 true)"), vec!["expected (while ^label name condition body...)"]);
+    assert_eq!(compile_errors("(return 1)"), vec!["return outside of a function"]);
+    assert_eq!(compile_errors("(fn f _ (return 1 2))"), vec!["expected (return value)"]);
+    // A function body doesn't see the loops around it
+    assert_eq!(compile_errors("(while true (fn f _ (break)))"), vec!["break outside of a loop"]);
+}
+
+#[test]
+fn test_for_loops() {
+    assert_eq!(eval("(var sum 0) (for x in [1 2 3] (sum = (sum + x))) sum"), Value::Integer(6));
+    assert_eq!(eval("(var n 0) (for _ in [] (n = 1)) n"), Value::Integer(0));
+
+    // Maps are iterated as [key value] pairs sorted by key
+    let source = "
+        (var keys \"\")
+        (var sum 0)
+        (for [k v] in {^b 2 ^a 1 ^c 3}
+            (keys = (keys + k))
+            (sum = (sum + v))
+        )
+        [keys sum]
+    ";
+    assert_eq!(eval(source), gene!(["abc" 6]));
+
+    // Nested patterns
+    let source = "
+        (var sum 0)
+        (for [a [b _]] in [[1 [2 3]] [4 [5 6]]] (sum = (sum + (a * b))))
+        sum
+    ";
+    assert_eq!(eval(source), Value::Integer(22));
+
+    // Loop variables are local to the function
+    let source = "
+        (fn total items
+            (var sum 0)
+            (for x in items (sum = (sum + x)))
+            sum
+        )
+        (total [1 2 3 4])
+    ";
+    assert_eq!(eval(source), Value::Integer(10));
+    assert_eq!(eval_error("(fn f _ (for x in [1] x)) (f) x").message, "undefined variable x");
+
+    // break, continue and labels
+    let source = "
+        (var found [])
+        (for ^label rows row in [[1 2] [3 4] [5 6]]
+            (for x in row
+                (if (x == 2) (continue rows))
+                (if (x == 5) (break rows))
+                (found = [found x])
+            )
+        )
+        found
+    ";
+    assert_eq!(eval(source), gene!([[[[] 1] 3] 4]));
+}
+
+#[test]
+fn test_for_genes() {
+    let mut runtime = Runtime::new();
+    runtime.set_global("g", gene!((item ^a 1 ^b 2 10 20)));
+    let source = "
+        (var data 0)
+        (for x in g (data = (data + x)))
+        (var props 0)
+        (for ^^props [k v] in g (props = (props + v)))
+        [data props]
+    ";
+    assert_eq!(runtime.eval(source).unwrap(), gene!([30 3]));
+}
+
+#[test]
+fn test_ranges() {
+    assert_eq!(eval("(var sum 0) (for i in (range 0 5) (sum = (sum + i))) sum"), Value::Integer(10));
+    assert_eq!(eval("(var s \"\") (for i in (range 10 0 -3) (s = (s + \"x\"))) s"), Value::String("xxxx".to_string()));
+    assert_eq!(eval("(var n 0) (for i in (range 5 5) (n = 1)) n"), Value::Integer(0));
+    assert_eq!(eval("(var r (range 1 3)) (var n 0) (for i in r (n = (n + i))) (for i in r (n = (n + i))) n"), Value::Integer(6));
+    assert_eq!(eval("(range 0 3)"), gene!((range 0 3 1)));
+
+    assert_eq!(eval_error("(range 0 1 0)").message, "range step can not be 0");
+    assert_eq!(eval_error("(range 0 \"a\")").message, "range expects integers, got 0 \"a\" 1");
+}
+
+#[test]
+fn test_iterator_functions() {
+    // Anything callable is an iterator, it is called until it returns void
+    let source = "
+        (fn counter n
+            (var i 0)
+            (fnx _
+                (if (i == n) (return))
+                (i = (i + 1))
+                i
+            )
+        )
+        (var sum 0)
+        (for x in (counter 4) (sum = (sum + x)))
+        sum
+    ";
+    assert_eq!(eval(source), Value::Integer(10));
+
+    let mut runtime = Runtime::new();
+    runtime.eval("(var items [])").unwrap();
+    runtime.register_native("pop", |vm, _args| {
+        let items = match vm.get_global("items") {
+            Some(Value::Array(items)) => items,
+            _ => unreachable!(),
+        };
+        match items.split_last() {
+            Some((last, rest)) => {
+                vm.set_global("items", Value::Array(rest.to_vec()));
+                Ok(last.clone())
+            }
+            None => Ok(Value::Void),
+        }
+    });
+    runtime.set_global("items", gene!([1 2 3]));
+    assert_eq!(runtime.eval("(var out []) (for x in pop (out = [out x])) out").unwrap(), gene!([[[[] 3] 2] 1]));
+
+    // Errors raised by the iterator can be caught around the loop
+    let source = "
+        (fn failing _ (throw \"stop\"))
+        (try (for x in failing x) catch e e)
+    ";
+    assert_eq!(eval(source), Value::String("stop".to_string()));
+}
+
+#[test]
+fn test_invalid_for_loops() {
+    assert_eq!(eval_error("(for x in 1 x)").message, "can not iterate over 1");
+    assert_eq!(eval_error("(for ^^props x in [1] x)").message, "can not iterate over props of [1]");
+    assert_eq!(eval_error("(for [a b] in [1] a)").message, "can not get item of 1");
+    for source in ["(for x [1])", "(for 1 in [1])", "(for [x 1] in [1])"].iter() {
+        assert_eq!(compile_errors(source), vec!["expected (for pattern in collection body...)"]);
+    }
+    assert_eq!(compile_errors("(range 1)"), vec!["expected (range start end step)"]);
+}
@@ -0,0 +1,160 @@
+#[macro_use]
+extern crate gene;
+
+use std::collections::HashMap;
+
+use gene::convert::{ConvertError, FromGene, ToGene};
+use gene::parser::Parser;
+use gene::types::Value;
+use gene_derive::{FromGene, ToGene};
+
+#[derive(Debug, PartialEq, ToGene, FromGene)]
+#[gene(kind = "person")]
+struct Person {
+    #[gene(data)]
+    name: String,
+    #[gene(rename = "years")]
+    age: i64,
+    email: Option<String>,
+    tags: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, ToGene, FromGene)]
+struct Point(i32, i32);
+
+#[derive(Debug, PartialEq, ToGene, FromGene)]
+struct Marker;
+
+#[derive(Debug, PartialEq, ToGene, FromGene)]
+enum Shape {
+    Circle { center: Point, radius: f64 },
+    #[gene(kind = "rect")]
+    Rect(Point, Point),
+    Empty,
+}
+
+#[derive(Debug, PartialEq, ToGene, FromGene)]
+struct Wrapper<T> {
+    value: T,
+}
+
+// Field names used by the generated code
+#[derive(Debug, PartialEq, ToGene, FromGene)]
+struct Node {
+    gene: String,
+    value: i64,
+}
+
+#[derive(Debug, PartialEq, ToGene, FromGene)]
+enum Tree {
+    Leaf { gene: i64 },
+    Branch(Vec<Tree>),
+}
+
+fn parse(s: &str) -> Value {
+    Parser::new(s).parse().unwrap()
+}
+
+#[test]
+fn test_struct_to_gene() {
+    let person = Person {
+        name: "Ann".to_string(),
+        age: 30,
+        email: None,
+        tags: vec!["a".to_string()],
+    };
+    assert_eq!(person.to_gene().unwrap(), parse("(person ^years 30 ^email null ^tags [\"a\"] \"Ann\")"));
+    assert_eq!(Point(1, 2).to_gene().unwrap(), parse("(Point 1 2)"));
+    assert_eq!(Marker.to_gene().unwrap(), parse("(Marker)"));
+    assert_eq!(Wrapper { value: true }.to_gene().unwrap(), parse("(Wrapper ^value true)"));
+}
+
+#[test]
+fn test_struct_from_gene() {
+    let person = Person::from_gene(&parse("(person ^years 30 ^tags [] \"Ann\")")).unwrap();
+    assert_eq!(person, Person {
+        name: "Ann".to_string(),
+        age: 30,
+        email: None,
+        tags: vec![],
+    });
+    assert_eq!(Point::from_gene(&parse("(Point 1 2)")).unwrap(), Point(1, 2));
+    assert_eq!(Marker::from_gene(&parse("(Marker)")).unwrap(), Marker);
+
+    let value = Wrapper { value: HashMap::<String, i64>::new() }.to_gene().unwrap();
+    assert_eq!(Wrapper::<HashMap<String, i64>>::from_gene(&value).unwrap().value, HashMap::new());
+}
+
+#[test]
+fn test_enum_round_trip() {
+    let shapes = vec![
+        Shape::Circle { center: Point(0, 0), radius: 1.5 },
+        Shape::Rect(Point(0, 0), Point(2, 3)),
+        Shape::Empty,
+    ];
+    for shape in shapes {
+        assert_eq!(Shape::from_gene(&shape.to_gene().unwrap()).unwrap(), shape);
+    }
+    assert_eq!(Shape::Rect(Point(0, 0), Point(2, 3)).to_gene().unwrap(), parse("(rect (Point 0 0) (Point 2 3))"));
+    assert_eq!(Shape::from_gene(&parse("Empty")).unwrap(), Shape::Empty);
+}
+
+#[test]
+fn test_fields_named_like_generated_locals() {
+    let node = Node {
+        gene: "x".to_string(),
+        value: 1,
+    };
+    assert_eq!(node.to_gene().unwrap(), parse("(Node ^gene \"x\" ^value 1)"));
+    assert_eq!(Node::from_gene(&node.to_gene().unwrap()).unwrap(), node);
+
+    let tree = Tree::Branch(vec![Tree::Leaf { gene: 1 }]);
+    assert_eq!(tree.to_gene().unwrap(), parse("(Branch [(Leaf ^gene 1)])"));
+    assert_eq!(Tree::from_gene(&tree.to_gene().unwrap()).unwrap(), tree);
+}
+
+#[test]
+fn test_conversion_errors() {
+    let err = Person::from_gene(&parse("(person ^years \"x\" ^tags [] \"Ann\")")).unwrap_err();
+    assert_eq!(err.to_string(), "years: expected integer, found \"x\"");
+
+    let err = Person::from_gene(&parse("(person ^tags [1] \"Ann\" ^years 1)")).unwrap_err();
+    assert_eq!(err.to_string(), "tags.0: expected string, found 1");
+
+    let err = Person::from_gene(&parse("(person ^tags [])")).unwrap_err();
+    assert_eq!(err, ConvertError {
+        message: "missing value".to_string(),
+        path: vec!["0".to_string()],
+    });
+
+    let err = Point::from_gene(&parse("(Pt 1 2)")).unwrap_err();
+    assert_eq!(err.to_string(), "expected (Point ...), found (Pt 1 2)");
+
+    let err = Shape::from_gene(&parse("(Square)")).unwrap_err();
+    assert_eq!(err.to_string(), "expected one of (Circle ...), (rect ...), (Empty ...), found (Square)");
+
+    let err = u8::from_gene(&Value::Integer(300)).unwrap_err();
+    assert_eq!(err.to_string(), "300 is out of range for u8");
+
+    // Integers Gene can't hold
+    let err = u64::MAX.to_gene().unwrap_err();
+    assert_eq!(err.to_string(), "18446744073709551615 is out of range for i64");
+    let err = Wrapper { value: vec![1, usize::MAX] }.to_gene().unwrap_err();
+    assert_eq!(err.to_string(), "value.1: 18446744073709551615 is out of range for i64");
+    let err = u64::from_gene(&Value::Integer(-1)).unwrap_err();
+    assert_eq!(err.to_string(), "-1 is out of range for u64");
+}
+
+#[test]
+fn test_primitive_conversions() {
+    let value = Value::Map(map! {
+        "a" => Value::Integer(1),
+    });
+    let map = HashMap::<String, i64>::from_gene(&value).unwrap();
+    assert_eq!(map["a"], 1);
+    assert_eq!(map.to_gene().unwrap(), value);
+    assert_eq!(Option::<i64>::from_gene(&Value::Null).unwrap(), None);
+    assert_eq!(f64::from_gene(&Value::Integer(2)).unwrap(), 2.0);
+    assert_eq!(u64::from_gene(&Value::Integer(3)).unwrap(), 3);
+    assert_eq!((i64::MAX as u64).to_gene().unwrap(), Value::Integer(i64::MAX));
+}
@@ -0,0 +1,168 @@
+#[macro_use]
+extern crate gene;
+
+mod common;
+
+use gene::runtime::{EvalError, Runtime};
+use gene::types::Value;
+use gene::vm::error::{ErrorKind, RuntimeError};
+use gene::vm::types::Arguments;
+use gene::vm::value::RuntimeValue;
+
+use common::{eval, eval_error, string};
+
+#[test]
+fn test_throw_and_catch() {
+    assert_eq!(eval("(try (throw \"boom\") catch e e)"), string("boom"));
+    assert_eq!(eval("(try (throw [1 2]) 3 catch e e)"), gene!([1 2]));
+    assert_eq!(eval("(try 1 catch e 2)"), Value::Integer(1));
+    assert_eq!(eval("(try catch e 2)"), Value::Void);
+    assert_eq!(eval("(try (throw 1) catch _ 2)"), Value::Integer(2));
+
+    // Through function calls
+    let source = "
+        (fn f n (throw (n * 2)))
+        (fn g n ((f n) + 1))
+        (try (g 5) catch e (e + 1))
+    ";
+    assert_eq!(eval(source), Value::Integer(11));
+
+    // Nested, errors in catch go to the outer try
+    let source = "
+        (try
+          (try (throw 1) catch e (throw (e + 1)))
+        catch e
+          (e + 10)
+        )
+    ";
+    assert_eq!(eval(source), Value::Integer(12));
+
+    // The variable is local to the function
+    let mut runtime = Runtime::new();
+    runtime.eval("(fn f _ (try (throw 1) catch e e))").unwrap();
+    assert_eq!(runtime.eval("(f)").unwrap(), Value::Integer(1));
+    assert!(runtime.eval("e").is_err());
+}
+
+#[test]
+fn test_thrown_values_are_kept() {
+    // Functions can be thrown, caught, rethrown and called
+    assert_eq!(eval("(fn h _ 42) (try (throw h) catch e (e))"), Value::Integer(42));
+    assert_eq!(eval("(fn h _ 42) (try (try (throw h) catch e (throw e)) catch e (e))"), Value::Integer(42));
+    // Collections keep their identity
+    assert_eq!(eval("(var a [1]) (try (throw a) catch e ((e .0) = 2)) a"), gene!([2]));
+    assert_eq!(eval("(var m {}) (try (throw m) catch e ((e .x) = 1)) m"), gene!({^x 1}));
+
+    let error = eval_error("(fn h _ 42) (throw h)");
+    assert_eq!(error.message, "uncaught exception function h");
+}
+
+#[test]
+fn test_catch_runtime_errors() {
+    let error = gene!((Error ^kind "DivisionByZero" ^message "division by zero"));
+    assert_eq!(eval("(try (1 / 0) catch e e)"), error);
+    let error = gene!((Error ^kind "UndefinedVariable" ^message "undefined variable x"));
+    assert_eq!(eval("(fn f _ x) (try (f) catch e e)"), error);
+    let error = gene!((Error ^kind "TypeError" ^message "unsupported operand types for +: 1 and true"));
+    assert_eq!(eval("(try (1 + true) catch e e)"), error);
+
+    // Stack overflows too, and the VM keeps working after them
+    let mut runtime = Runtime::new();
+    runtime.vm.set_max_depth(100);
+    runtime.eval("(fn down n (1 + (down n)))").unwrap();
+    runtime.eval("(fn sum n (if (n == 0) 0 else (n + (sum (n - 1)))))").unwrap();
+    for _ in 0..3 {
+        let result = runtime.eval("(try (down 0) catch e \"caught\")").unwrap();
+        assert_eq!(result, string("caught"));
+        assert_eq!(runtime.eval("(sum 98)").unwrap(), Value::Integer(4851));
+    }
+}
+
+#[test]
+fn test_catch_native_errors() {
+    let mut runtime = Runtime::new();
+    runtime.register_native("fail", |_vm, args| match args.get(0) {
+        Some(Value::String(message)) => Err(RuntimeError::type_error(message)),
+        Some(value) => Err(RuntimeError::thrown(value)),
+        None => Ok(Value::Null),
+    });
+    runtime.register_native("apply", |vm, args| {
+        let function = args.data[0].clone();
+        vm.call(&function, Arguments::new(Default::default(), args.data[1..].to_vec()))
+    });
+
+    let error = gene!((Error ^kind "TypeError" ^message "bad"));
+    assert_eq!(runtime.eval("(try (fail \"bad\") catch e e)").unwrap(), error);
+    assert_eq!(runtime.eval("(try (fail 1) catch e e)").unwrap(), Value::Integer(1));
+
+    // Errors thrown by Gene code called from a native function
+    runtime.eval("(fn thrower n (throw (n + 1)))").unwrap();
+    assert_eq!(runtime.eval("(try (apply thrower 1) catch e e)").unwrap(), Value::Integer(2));
+    runtime.eval("(fn safe n (try (thrower n) catch e \"safe\"))").unwrap();
+    assert_eq!(runtime.eval("(apply safe 1)").unwrap(), string("safe"));
+}
+
+#[test]
+fn test_finally() {
+    let mut runtime = Runtime::new();
+    runtime.eval("(var log [])").unwrap();
+    runtime.register_native("note", |vm, args| {
+        if let Some(RuntimeValue::Array(log)) = vm.lookup("log") {
+            log.borrow_mut().push(args.data[0].clone());
+        }
+        Ok(Value::Void)
+    });
+
+    // The result of the body or catch is kept
+    assert_eq!(runtime.eval("(try 1 finally (note \"a\") 2)").unwrap(), Value::Integer(1));
+    assert_eq!(runtime.eval("(try (throw 1) catch e (e + 1) finally (note \"b\"))").unwrap(), Value::Integer(2));
+    assert_eq!(runtime.eval("(try (note \"c\") catch e (note \"not run\") finally (note \"d\") 3)").unwrap(), Value::Void);
+
+    // Errors are raised again after the finally code, also from catch
+    match runtime.eval("(try (1 / 0) finally (note \"e\"))") {
+        Err(EvalError::Runtime(e)) => assert_eq!(e.kind, ErrorKind::DivisionByZero),
+        result => panic!("unexpected result {:?}", result),
+    }
+    match runtime.eval("(fn f _ (try (throw 1) catch e (throw (e + 1)) finally (note \"f\"))) (f)") {
+        Err(EvalError::Runtime(e)) => {
+            assert_eq!(e.value(), Some(Value::Integer(2)));
+            let names: Vec<&str> = e.stack.iter().map(|frame| frame.name.as_str()).collect();
+            assert_eq!(names, vec!["f", "__default__"]);
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+    assert_eq!(
+        runtime.eval("(try (try (throw 1) finally (note \"g\")) catch e e)").unwrap(),
+        Value::Integer(1),
+    );
+    assert_eq!(runtime.get_global("log"), Some(gene!(["a" "b" "c" "d" "e" "f" "g"])));
+}
+
+#[test]
+fn test_uncaught_exceptions() {
+    let error = eval_error("(fn f _ (throw \"boom\")) (fn g _ ((f) + 1)) (g)");
+    assert_eq!(error.kind, ErrorKind::Thrown);
+    assert_eq!(error.message, "uncaught exception \"boom\"");
+    assert_eq!(error.value(), Some(string("boom")));
+    let names: Vec<&str> = error.stack.iter().map(|frame| frame.name.as_str()).collect();
+    assert_eq!(names, vec!["f", "g", "__default__"]);
+
+    // Calls in a try are not tail calls, their errors are caught
+    assert_eq!(eval("(fn g _ (throw 1)) (fn f _ (try (g) catch e \"caught\")) (f)"), string("caught"));
+}
+
+#[test]
+fn test_invalid_try() {
+    for source in ["(try 1)", "(try 1 catch)", "(try 1 catch [e])", "(try finally 1 catch e 2)"].iter() {
+        match Runtime::new().eval(source) {
+            Err(EvalError::Compile(errors)) => {
+                assert_eq!(errors[0].message, "expected (try body... catch name body... finally body...)");
+            }
+            result => panic!("unexpected result {:?} for {}", result, source),
+        }
+    }
+    match Runtime::new().eval("(throw)") {
+        Err(EvalError::Compile(errors)) => assert_eq!(errors[0].message, "expected (throw value)"),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
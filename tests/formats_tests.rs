@@ -0,0 +1,110 @@
+#[macro_use]
+extern crate gene;
+
+use gene::formats::{self, toml, yaml, Format};
+use gene::parser::Parser;
+use gene::types::Value;
+
+fn parse(s: &str) -> Value {
+    Parser::new(s).parse().unwrap()
+}
+
+#[test]
+fn test_yaml_import() {
+    let value = yaml::from_str("
+name: web
+replicas: 2
+ratio: 0.5
+enabled: true
+owner: ~
+ports: [80, 443]
+").unwrap();
+    assert_eq!(value, gene!({^name "web" ^replicas 2 ^ratio 0.5 ^enabled true ^owner null ^ports [80 443]}));
+}
+
+#[test]
+fn test_yaml_genes_and_symbols() {
+    let value = parse("(service ^name \"web\" (port 80) [x])");
+    let text = yaml::to_string(&value).unwrap();
+    assert_eq!(text.trim_start_matches("---\n"), "\
+\"#gene\": service
+name: web
+\"#data\":
+  - \"#gene\": port
+    \"#data\":
+      - 80
+  - - \"#symbol\": x
+");
+    assert_eq!(yaml::from_str(&text).unwrap(), value);
+}
+
+#[test]
+fn test_toml_round_trip() {
+    let value = toml::from_str("
+title = \"app\"
+[server]
+port = 8080
+hosts = [\"a\", \"b\"]
+").unwrap();
+    assert_eq!(value, gene!({^title "app" ^server {^port 8080 ^hosts ["a" "b"]}}));
+    assert_eq!(toml::from_str(&toml::to_string(&value).unwrap()).unwrap(), value);
+
+    let value = parse("(deploy ^env \"prod\" (port 80))");
+    assert_eq!(toml::from_str(&toml::to_string(&value).unwrap()).unwrap(), value);
+}
+
+#[test]
+fn test_reserved_keys() {
+    // Maps and props with the keys used for the encoding are kept as data
+    for key in ["#gene", "#data", "#symbol", "##x"].iter() {
+        for source in [format!("{{^{} 1}}", key), format!("(a ^{} 1 2)", key)].iter() {
+            let value = parse(source);
+            assert_eq!(yaml::from_str(&yaml::to_string(&value).unwrap()).unwrap(), value, "{}", source);
+            assert_eq!(toml::from_str(&toml::to_string(&value).unwrap()).unwrap(), value, "{}", source);
+        }
+    }
+    let text = yaml::to_string(&parse("{^#gene 1 ^a 2}")).unwrap();
+    assert_eq!(text.trim_start_matches("---\n"), "\"##gene\": 1\na: 2\n");
+}
+
+#[test]
+fn test_yaml_errors() {
+    assert_eq!(
+        yaml::from_str("a: [18446744073709551615]").unwrap_err().to_string(),
+        "a.0: 18446744073709551615 is out of range for i64"
+    );
+    assert_eq!(yaml::from_str("a: 9223372036854775807").unwrap(), parse("{^a 9223372036854775807}"));
+}
+
+#[test]
+fn test_toml_errors() {
+    assert_eq!(
+        toml::to_string(&gene!([1 2])).unwrap_err().to_string(),
+        "expected map or gene at the top of a TOML document, found [1 2]"
+    );
+    assert_eq!(
+        toml::to_string(&gene!({^a {^b null}})).unwrap_err().to_string(),
+        "a.b: TOML can not represent null"
+    );
+}
+
+#[test]
+fn test_convert() {
+    assert_eq!(formats::convert("a [1]", Format::Gene, Format::Gene).unwrap(), "a\n[1]");
+    let output = formats::convert("a: 1\nb: [x]\n", Format::Yaml, Format::Gene).unwrap();
+    assert_eq!(output, "{^a 1 ^b [\"x\"]}");
+
+    let output = formats::convert("(config ^debug false)", Format::Gene, Format::Toml).unwrap();
+    assert_eq!(output, "\"#gene\" = \"config\"\ndebug = false\n");
+
+    // Key order is kept between YAML and TOML
+    let output = formats::convert("z = 1\na = 2\n", Format::Toml, Format::Yaml).unwrap();
+    assert_eq!(output.trim_start_matches("---\n"), "z: 1\na: 2\n");
+    let output = formats::convert("z: 1\na: 2\n", Format::Yaml, Format::Toml).unwrap();
+    assert_eq!(output, "z = 1\na = 2\n");
+
+    assert_eq!(
+        Format::from_name("json").unwrap_err().to_string(),
+        "unknown format json, expected gene, yaml or toml"
+    );
+}
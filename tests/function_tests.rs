@@ -0,0 +1,290 @@
+#[macro_use]
+extern crate gene;
+
+mod common;
+
+use gene::runtime::{EvalError, Runtime};
+use gene::types::Value;
+use gene::vm::error::ErrorKind;
+use gene::vm::types::Arguments;
+use gene::vm::value::RuntimeValue;
+use gene::vm::{DEFAULT_MAX_DEPTH, DEFAULT_MAX_NATIVE_DEPTH};
+
+use common::{eval, eval_error, compile_errors};
+
+#[test]
+fn test_default_arguments() {
+    let source = "(fn f [a (b = 10) (c = [1])] [a b c])";
+    assert_eq!(eval(&format!("{} (f 1)", source)), gene!([1 10 [1]]));
+    assert_eq!(eval(&format!("{} (f 1 2)", source)), gene!([1 2 [1]]));
+    assert_eq!(eval(&format!("{} (f 1 2 3)", source)), gene!([1 2 3]));
+
+    let error = eval_error(&format!("{} (f)", source));
+    assert_eq!(error.kind, ErrorKind::ArityMismatch);
+    assert_eq!(error.message, "f expects 1 to 3 argument(s), got 0");
+    let error = eval_error(&format!("{} (f 1 2 3 4)", source));
+    assert_eq!(error.message, "f expects 1 to 3 argument(s), got 4");
+}
+
+#[test]
+fn test_rest_arguments() {
+    assert_eq!(eval("(fn f [a rest...] [a rest]) (f 1 2 3)"), gene!([1 [2 3]]));
+    assert_eq!(eval("(fn f [a rest...] [a rest]) (f 1)"), gene!([1 []]));
+    assert_eq!(eval("(fn f args... args) (f 1 2)"), gene!([1 2]));
+    assert_eq!(eval("(fn f [(a = 0) rest...] [a rest]) (f)"), gene!([0 []]));
+
+    let error = eval_error("(fn f [a b rest...] a) (f 1)");
+    assert_eq!(error.message, "f expects at least 2 argument(s), got 1");
+}
+
+#[test]
+fn test_prop_arguments() {
+    let source = "(fn f [{^verbose false ^level 1} a] [verbose level a])";
+    assert_eq!(eval(&format!("{} (f 0)", source)), gene!([false 1 0]));
+    assert_eq!(eval(&format!("{} (f ^^verbose 0)", source)), gene!([true 1 0]));
+    assert_eq!(eval(&format!("{} (f ^verbose true ^level (1 + 1) 0)", source)), gene!([true 2 0]));
+
+    let error = eval_error(&format!("{} (f ^levle 2 0)", source));
+    assert_eq!(error.kind, ErrorKind::ArityMismatch);
+    assert_eq!(error.message, "f got an unexpected prop ^levle");
+}
+
+#[test]
+fn test_destructuring() {
+    assert_eq!(eval("(fn f [[a b] c] [b a c]) (f [1 2] 3)"), gene!([2 1 3]));
+    assert_eq!(eval("(fn f [[a [b c]]] (a + (b + c))) (f [1 [2 3]])"), Value::Integer(6));
+    assert_eq!(eval("(fn f [[head tail...]] [head tail]) (f [1 2 3])"), gene!([1 [2 3]]));
+    assert_eq!(eval("(fn f [[_ (b = 5)]] b) (f [1])"), Value::Integer(5));
+
+    // Genes can't be built in Gene code yet, pass them from Rust
+    let mut runtime = Runtime::new();
+    runtime.eval("(fn f [(point ^x x ^y (y = 0) a)] [x y a]) (fn g [(_ ^x x)] x)").unwrap();
+    assert_eq!(runtime.call("f", vec![gene!((point ^x 1 2))]).unwrap(), gene!([1 0 2]));
+    // The kind is compared like in `match`, `_` takes any kind
+    match runtime.call("f", vec![gene!((line ^x 1 2))]) {
+        Err(EvalError::Runtime(e)) => {
+            assert_eq!(e.kind, ErrorKind::TypeError);
+            assert_eq!(e.message, "f expects point, got line");
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+    match runtime.call("g", vec![gene!((point 1))]) {
+        Err(EvalError::Runtime(e)) => assert_eq!(e.message, "g expects prop ^x"),
+        result => panic!("unexpected result {:?}", result),
+    }
+    assert_eq!(eval("(fn f [[0 x]] x) (f [0 1])"), Value::Integer(1));
+    assert_eq!(eval_error("(fn f [[{^type \"point\" ^x x}]] x) (f [{^type \"line\" ^x 1}])").message, "f expects \"point\", got \"line\"");
+
+    let error = eval_error("(fn f [[a b]] a) (f 1)");
+    assert_eq!(error.kind, ErrorKind::TypeError);
+    assert_eq!(error.message, "f expects an array, got 1");
+    let error = eval_error("(fn f [[a b]] a) (f [1])");
+    assert_eq!(error.kind, ErrorKind::ArityMismatch);
+    assert_eq!(error.message, "f expects 2 argument(s) in [1], got 1");
+}
+
+#[test]
+fn test_invalid_signatures() {
+    assert_eq!(compile_errors("(fn f [rest... a] a)"), vec!["rest must be the last argument"]);
+    assert_eq!(compile_errors("(fn f [(a = 1) b] a)"), vec!["b must have a default value"]);
+    assert_eq!(compile_errors("(fn f [1] 1)"), vec!["invalid argument 1"]);
+    assert_eq!(compile_errors("(fn f [...] 1)"), vec!["invalid argument ..."]);
+}
+
+#[test]
+fn test_closures() {
+    // Each call gets its own environment
+    let source = "
+        (fn make_counter _
+          (var n 0)
+          (fn inc _ (n = (n + 1)))
+        )
+        (var c (make_counter))
+        (var d (make_counter))
+        (c) (c) (d)
+        [(c) (d)]
+    ";
+    assert_eq!(eval(source), gene!([3 2]));
+
+    // Closures see variables defined after them and later changes
+    assert_eq!(eval("(fn f _ (fn get _ later) (var later 5) (get)) (f)"), Value::Integer(5));
+    assert_eq!(eval("(fn f _ (var x 1) (fn get _ x) (x = 2) (get)) (f)"), Value::Integer(2));
+    assert_eq!(eval("(fn adder a (fn add b (a + b))) (var add1 (adder 1)) (adder 10) (add1 2)"), Value::Integer(3));
+
+    // Nested functions are local to the call, like variables
+    assert_eq!(eval("(var g 1) (fn f _ (fn g _ 2) (g)) [(f) g]"), gene!([2 1]));
+    assert_eq!(eval_error("(fn f _ (fn g _ 2) 1) (f) (g)").kind, ErrorKind::UndefinedVariable);
+    assert_eq!(
+        eval("(fn outer n (fn down n (if (n > 0) (down (n - 1)) else \"done\")) (down n)) (outer 3)"),
+        Value::String("done".to_string()),
+    );
+}
+
+#[test]
+fn test_anonymous_functions() {
+    assert_eq!(eval("((fnx a (a * 2)) 4)"), Value::Integer(8));
+    assert_eq!(eval("(var add (fnx [a b] (a + b))) (add 1 2)"), Value::Integer(3));
+    assert_eq!(eval("(fn adder a (fnx b (a + b))) ((adder 1) 2)"), Value::Integer(3));
+    // They are not bound to a name
+    assert_eq!(eval_error("(fnx _ 1) fnx").kind, ErrorKind::UndefinedVariable);
+    assert_eq!(compile_errors("(fnx)"), vec!["expected (fnx args body...)"]);
+}
+
+#[test]
+fn test_closures_in_collections() {
+    let mut runtime = Runtime::new();
+    runtime.register_native("call_all", |vm, args| {
+        let functions = match &args.data[0] {
+            RuntimeValue::Array(functions) => functions.borrow().clone(),
+            _ => panic!("expected an array"),
+        };
+        let mut results = Vec::new();
+        for function in functions.iter() {
+            results.push(vm.call(function, Arguments::from_data(vec![Value::Integer(1)]))?);
+        }
+        Ok(Value::Array(results))
+    });
+    let source = "
+        (fn adder a (fnx b (a + b)))
+        (var total 0)
+        (call_all [(adder 10) (adder 20) (fnx b (total = (total + b)))])
+    ";
+    assert_eq!(runtime.eval(source).unwrap(), gene!([11 21 1]));
+    assert_eq!(runtime.get_global("total"), Some(Value::Integer(1)));
+}
+
+#[test]
+fn test_variable_scopes() {
+    // Locals shadow globals and don't leak out of the call
+    assert_eq!(eval("(var x 1) (fn f _ (var x 2) x) [(f) x]"), gene!([2 1]));
+    assert_eq!(eval("(var x 1) (fn f x (x = (x + 1)) x) [(f 5) x]"), gene!([6 1]));
+    assert_eq!(eval("(fn f _ (var y 1) y) (f) (var y 3) y"), Value::Integer(3));
+
+    // Closures can change the variables of the enclosing calls
+    assert_eq!(eval("(fn f _ (var n 0) (fn g _ (fn h _ (n = (n + 10))) (h)) (g) (g) n) (f)"), Value::Integer(20));
+
+    // A local is only defined once its `var` has run
+    let error = eval_error("(var x 1) (fn f _ (var y x) (var x 2) y) (f)");
+    assert_eq!(error.kind, ErrorKind::UndefinedVariable);
+    assert_eq!(error.message, "undefined variable x");
+    let error = eval_error("(fn f _ (fn g _ later) (g)) (f)");
+    assert_eq!(error.message, "undefined variable later");
+    assert_eq!(eval_error("(fn f _ (z = 1)) (f)").message, "undefined variable z");
+
+    // Globals can be defined after the functions that use them, even by a later eval
+    let mut runtime = Runtime::new();
+    runtime.eval("(fn get _ late)").unwrap();
+    match runtime.eval("(get)") {
+        Err(EvalError::Runtime(e)) => assert_eq!(e.message, "undefined variable late"),
+        result => panic!("unexpected result {:?}", result),
+    }
+    runtime.eval("(var late 1)").unwrap();
+    assert_eq!(runtime.eval("(get)").unwrap(), Value::Integer(1));
+    runtime.set_global("late", Value::Integer(2));
+    assert_eq!(runtime.eval("(get)").unwrap(), Value::Integer(2));
+}
+
+#[test]
+fn test_tail_calls() {
+    // Runs in constant space, the frames of the tail calls are reused
+    let source = "
+        (fn count [n acc]
+          (if (n == 0)
+            acc
+          else
+            (count (n - 1) (acc + 1))
+          )
+        )
+        (count 100000 0)
+    ";
+    assert_eq!(eval(source), Value::Integer(100000));
+
+    // Mutual recursion, and tail calls through && / ||
+    let source = "
+        (fn even n (if (n == 0) true else (odd (n - 1))))
+        (fn odd n ((n != 0) && (even (n - 1))))
+        [(even 100001) (odd 100001)]
+    ";
+    assert_eq!(eval(source), gene!([false true]));
+
+    // Tail calls from functions called by Rust return to Rust
+    let mut runtime = Runtime::new();
+    runtime.eval("(fn loop n (if (n > 0) (loop (n - 1)) else \"done\"))").unwrap();
+    assert_eq!(runtime.call("loop", vec![Value::Integer(50000)]).unwrap(), Value::String("done".to_string()));
+
+    // The caller of a tail call is not in the stack trace
+    let error = eval_error("(fn f a (a + b)) (fn g a (f a)) (fn h a ((g a) + 1)) (h 1)");
+    let names: Vec<&str> = error.stack.iter().map(|frame| frame.name.as_str()).collect();
+    assert_eq!(names, vec!["f", "h", "__default__"]);
+}
+
+#[test]
+fn test_stack_overflow() {
+    let mut runtime = Runtime::new();
+    runtime.eval("(fn down n (1 + (down (n + 1))))").unwrap();
+    match runtime.eval("(down 0)") {
+        Err(EvalError::Runtime(e)) => {
+            assert_eq!(e.kind, ErrorKind::StackOverflow);
+            assert_eq!(e.message, format!("stack overflow in down: more than {} nested calls", DEFAULT_MAX_DEPTH));
+            assert_eq!(e.stack.len(), DEFAULT_MAX_DEPTH + 1);
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // The VM can be used again and the limit is configurable
+    runtime.eval("(fn sum n (if (n == 0) 0 else (n + (sum (n - 1)))))").unwrap();
+    assert_eq!(runtime.eval("(sum 100)").unwrap(), Value::Integer(5050));
+    runtime.vm.set_max_depth(50);
+    assert_eq!(runtime.eval("(sum 49)").unwrap(), Value::Integer(1225));
+    match runtime.eval("(sum 50)") {
+        Err(EvalError::Runtime(e)) => assert_eq!(e.kind, ErrorKind::StackOverflow),
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // Tail calls don't count
+    assert_eq!(eval("(fn loop n (if (n > 0) (loop (n - 1)) else n)) (loop 20000)"), Value::Integer(0));
+}
+
+#[test]
+fn test_stack_overflow_through_native_functions() {
+    let mut runtime = Runtime::new();
+    runtime.vm.set_max_depth(100);
+    runtime.register_native("apply", |vm, args| {
+        let function = args.data[0].clone();
+        let rest = args.data[1..].iter().map(RuntimeValue::to_value).collect();
+        vm.call(&function, Arguments::from_data(rest))
+    });
+    runtime.eval("(fn f n (if (n == 0) 0 else (1 + (apply f (n - 1)))))").unwrap();
+    assert_eq!(runtime.eval("(f 40)").unwrap(), Value::Integer(40));
+    match runtime.eval("(f 1000)") {
+        Err(EvalError::Runtime(e)) => {
+            assert_eq!(e.kind, ErrorKind::StackOverflow);
+            assert!(e.message.starts_with("stack overflow in "));
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+    assert_eq!(runtime.eval("(f 40)").unwrap(), Value::Integer(40));
+}
+
+#[test]
+fn test_native_depth() {
+    // With the default limits, deep recursion through a native function is
+    // stopped before it overflows the Rust stack of the test thread
+    let mut runtime = Runtime::new();
+    runtime.register_native("apply", |vm, args| {
+        let function = args.data[0].clone();
+        let rest = args.data[1..].iter().map(RuntimeValue::to_value).collect();
+        vm.call(&function, Arguments::from_data(rest))
+    });
+    runtime.eval("(fn f n (if (n == 0) 0 else (1 + (apply f (n - 1)))))").unwrap();
+    let source = "(try (f 5000) catch e [(e .kind) (e .message)])";
+    let message = format!("stack overflow in f: more than {} nested calls from native functions", DEFAULT_MAX_NATIVE_DEPTH);
+    assert_eq!(runtime.eval(source).unwrap(), Value::Array(vec![Value::String("StackOverflow".to_string()), Value::String(message)]));
+    assert_eq!(runtime.eval("(f 90)").unwrap(), Value::Integer(90));
+
+    runtime.vm.set_max_native_depth(10);
+    match runtime.eval("(f 20)") {
+        Err(EvalError::Runtime(e)) => assert_eq!(e.kind, ErrorKind::StackOverflow),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
@@ -0,0 +1,96 @@
+#[macro_use]
+extern crate gene;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use ordered_float::OrderedFloat;
+
+use gene::parser::Parser;
+use gene::types::Gene;
+use gene::types::Value;
+
+fn parse(s: &str) -> Value {
+    Parser::new(s).parse().unwrap()
+}
+
+#[test]
+fn test_gene_macro_literals() {
+    assert_eq!(gene!(null), Value::Null);
+    assert_eq!(gene!(()), Value::Void);
+    assert_eq!(gene!(true), Value::Boolean(true));
+    assert_eq!(gene!(1), Value::Integer(1));
+    assert_eq!(gene!(1.5), Value::Float(OrderedFloat(1.5)));
+    assert_eq!(gene!("ab"), Value::String("ab".to_string()));
+    assert_eq!(gene!(ab), Value::Symbol("ab".to_string()));
+    assert_eq!(gene!(<=), Value::Symbol("<=".to_string()));
+    assert_eq!(gene!(#(-1)), Value::Integer(-1));
+    assert_eq!(gene!(-1), Value::Integer(-1));
+    assert_eq!(gene!(-1.5), Value::Float(OrderedFloat(-1.5)));
+}
+
+#[test]
+fn test_gene_macro_collections() {
+    assert_eq!(gene!([]), Value::Array(vec![]));
+    assert_eq!(gene!([1 "a" [b]]), parse("[1 \"a\" [b]]"));
+    assert_eq!(gene!({}), Value::Map(HashMap::new()));
+    assert_eq!(gene!({^a 1 ^^b ^!c ^"d-e" [2]}), parse("{^a 1 ^^b ^!c ^d-e [2]}"));
+    assert_eq!(
+        gene!((div ^class "x" (span 1) ^^hidden 2)),
+        parse("(div ^class \"x\" ^^hidden (span 1) 2)")
+    );
+    assert_eq!(gene!((n - x)), parse("(n - x)"));
+    assert_eq!(gene!((- 1 2)), parse("(- 1 2)"));
+    assert_eq!(gene!(a 1), Value::Stream(vec![gene!(a), gene!(1)]));
+}
+
+#[test]
+fn test_gene_macro_negative_numbers() {
+    assert_eq!(gene!([1 -1 -2.5]), parse("[1 -1 -2.5]"));
+    assert_eq!(gene!({^a -1 ^b [-2]}), parse("{^a -1 ^b [-2]}"));
+    assert_eq!(gene!((n ^a -1 -2 - x)), parse("(n ^a -1 -2 - x)"));
+    assert_eq!(gene!((n - 1)), parse("(n -1)"));
+    assert_eq!(gene!(1 -1), Value::Stream(vec![Value::Integer(1), Value::Integer(-1)]));
+}
+
+#[test]
+fn test_gene_macro_interpolation() {
+    let name = "Ann";
+    let age: i64 = 30;
+    let tags = vec!["a", "b"];
+    let kind = Value::Symbol("person".to_string());
+    assert_eq!(
+        gene!((#kind ^name #name ^age #(age + 1) #tags)),
+        parse("(person ^name \"Ann\" ^age 31 [\"a\" \"b\"])")
+    );
+}
+
+#[test]
+fn test_from_conversions() {
+    assert_eq!(Value::from(1), Value::Integer(1));
+    assert_eq!(Value::from(1i64), Value::Integer(1));
+    assert_eq!(Value::from(false), Value::Boolean(false));
+    assert_eq!(Value::from("a"), Value::String("a".to_string()));
+    assert_eq!(Value::from(vec![1, 2]), gene!([1 2]));
+    assert_eq!(Value::from(None::<i64>), Value::Null);
+
+    let mut map = HashMap::new();
+    map.insert("a".to_string(), 1.5);
+    assert_eq!(Value::from(map), gene!({^a 1.5}));
+
+    assert_eq!(Value::from(Gene::new(gene!(a))), gene!((a)));
+}
+
+#[test]
+fn test_try_from_conversions() {
+    assert_eq!(i64::try_from(gene!(1)), Ok(1));
+    assert_eq!(bool::try_from(gene!(true)), Ok(true));
+    assert_eq!(String::try_from(gene!("a")), Ok("a".to_string()));
+    assert_eq!(Vec::<i64>::try_from(gene!([1 2])), Ok(vec![1, 2]));
+    assert_eq!(HashMap::<String, f64>::try_from(gene!({^a 1.5})).unwrap()["a"], 1.5);
+    assert_eq!(Gene::try_from(gene!((a 1))).unwrap().data, vec![Value::Integer(1)]);
+    assert_eq!(
+        i64::try_from(gene!("a")).unwrap_err().to_string(),
+        "expected integer, found \"a\""
+    );
+}
@@ -0,0 +1,169 @@
+#[macro_use]
+extern crate gene;
+
+mod common;
+
+use gene::compiler::{Instruction, Shape};
+use gene::compiler2::Compiler;
+use gene::parser::Parser;
+use gene::runtime::{EvalError, Runtime};
+use gene::types::Value;
+use gene::vm::error::ErrorKind;
+
+use common::{compile_errors, eval, eval_error, string};
+
+#[test]
+fn test_literals_and_bindings() {
+    let source = "
+        (fn describe x
+            (match x
+                (0 \"zero\")
+                (\"a\" \"letter a\")
+                (true \"yes\")
+                (null \"nothing\")
+                (`red \"color\")
+                (n (n * 10))
+            )
+        )
+        [(describe 0) (describe \"a\") (describe true) (describe null) (describe `red) (describe 5)]
+    ";
+    assert_eq!(eval(source), gene!(["zero" "letter a" "yes" "nothing" "color" 50]));
+    assert_eq!(eval("(match 1 (_ \"any\"))"), string("any"));
+    // Clauses are tried in order
+    assert_eq!(eval("(match 1 (x \"first\") (1 \"second\"))"), string("first"));
+}
+
+#[test]
+fn test_arrays_and_maps() {
+    let source = "
+        (fn f x
+            (match x
+                ([] \"empty\")
+                ([a] a)
+                ([a b] (a + b))
+                ([a _ rest...] [a rest])
+                ({^type \"point\" ^x x ^y y} (x * y))
+                ({^type _} \"typed\")
+                ({} \"map\")
+            )
+        )
+        [(f []) (f [1]) (f [1 2]) (f [1 2 3 4]) (f {^type \"point\" ^x 2 ^y 3}) (f {^type 1}) (f {^a 1})]
+    ";
+    assert_eq!(eval(source), gene!(["empty" 1 3 [1 [3 4]] 6 "typed" "map"]));
+    assert_eq!(eval("(match [[1 2] [3]] ([[a b] [c]] (a + (b + c))))"), Value::Integer(6));
+}
+
+#[test]
+fn test_genes() {
+    let source = "
+        (fn render node
+            (match node
+                ((text s) s)
+                ((link ^href url label) (\"<a href='\" + (url + (\"'>\" + (label + \"</a>\")))))
+                ((list items...) (items .1))
+                ((_ ^id id _...) id)
+                ((_) \"empty\")
+            )
+        )
+        [
+            (render `(text \"hi\"))
+            (render `(link ^href \"/\" \"home\"))
+            (render `(list 1 2 3))
+            (render `(other ^id 7 1))
+            (render `(other))
+        ]
+    ";
+    assert_eq!(eval(source), gene!(["hi" "<a href='/'>home</a>" 2 7 "empty"]));
+
+    // Kind and data length both have to match
+    assert_eq!(eval("(match `(text 1 2) ((text s) 1) ((text a b) 2))"), Value::Integer(2));
+    assert_eq!(eval("(match [1] ((text s) 1) (_ 2))"), Value::Integer(2));
+    // Nested genes and ^^flags
+    assert_eq!(eval("(match `(a ^^on (b 1)) ((a ^^on (b x)) x))"), Value::Integer(1));
+    assert_eq!(eval("(match `(a ^!on) ((a ^^on) 1) ((a) 2))"), Value::Integer(2));
+}
+
+#[test]
+fn test_guards() {
+    let source = "
+        (fn sign n
+            (match n
+                (x if (x < 0) \"negative\")
+                (0 \"zero\")
+                (_ \"positive\")
+            )
+        )
+        [(sign -5) (sign 0) (sign 5)]
+    ";
+    assert_eq!(eval(source), gene!(["negative" "zero" "positive"]));
+    assert_eq!(eval("(match `(p 1 2) ((p a b) if (a > b) a) ((p a b) b))"), Value::Integer(2));
+}
+
+#[test]
+fn test_match_in_functions() {
+    // Pattern variables are local to the function
+    let source = "
+        (fn sum [items acc]
+            (match items
+                ([] acc)
+                ([x rest...] (sum rest (acc + x)))
+            )
+        )
+        (sum [1 2 3 4] 0)
+    ";
+    let mut runtime = Runtime::new();
+    assert_eq!(runtime.eval(source).unwrap(), Value::Integer(10));
+    assert_eq!(runtime.get_global("x"), None);
+}
+
+#[test]
+fn test_no_match() {
+    let error = eval_error("(match [1 2] ([a] a))");
+    assert_eq!(error.kind, ErrorKind::NoMatch);
+    assert_eq!(error.message, "no pattern matches [1 2]");
+    assert_eq!(eval("(try (match 1 (2 2)) catch e (e .kind))"), string("NoMatch"));
+}
+
+#[test]
+fn test_invalid_match() {
+    for source in ["(match 1)", "(match 1 2)", "(match 1 (x if))", "(match 1 (x ^a 1 2))"].iter() {
+        assert_eq!(compile_errors(source), vec!["expected (match value (pattern body...)...)"]);
+    }
+    match Runtime::new().eval("(match 1 ([a... b] 1))") {
+        Err(EvalError::Compile(errors)) => assert_eq!(errors[0].to_string(), "a must be the last argument: [a... b] at 10..18"),
+        result => panic!("unexpected result {:?}", result),
+    }
+    assert_eq!(compile_errors("(match 1 ([(a = 1)] a))"), vec!["match patterns can't have default values"]);
+}
+
+#[test]
+fn test_shared_tests() {
+    // Clauses share the tests they have in common, each one runs once
+    let source = "
+        (match p
+            ((point ^x 0 y) y)
+            ((point ^x 1 y) (y * 10))
+            ((point ^x x _) x)
+            ([a] a)
+        )
+    ";
+    let mut compiler = Compiler::new();
+    compiler.compile(Parser::new(source).parse().unwrap()).unwrap();
+    let block = compiler.module.get_default_block();
+    let count = |f: fn(&Instruction) -> bool| block.instructions.iter().filter(|instr| f(instr)).count();
+    assert_eq!(count(|instr| matches!(instr, Instruction::Check(_, Shape::Gene(..)))), 1);
+    assert_eq!(count(|instr| matches!(instr, Instruction::GetKind(_))), 1);
+    assert_eq!(count(|instr| matches!(instr, Instruction::Check(_, Shape::HasProp(_)))), 1);
+    assert_eq!(count(|instr| matches!(instr, Instruction::Check(_, Shape::Array(..)))), 1);
+
+    let mut runtime = Runtime::new();
+    runtime.eval(&format!("(fn f p {})", source)).unwrap();
+    assert_eq!(runtime.call("f", vec![gene!((point ^x 0 1))]).unwrap(), Value::Integer(1));
+    assert_eq!(runtime.call("f", vec![gene!((point ^x 1 2))]).unwrap(), Value::Integer(20));
+    assert_eq!(runtime.call("f", vec![gene!((point ^x 5 1))]).unwrap(), Value::Integer(5));
+    assert_eq!(runtime.call("f", vec![gene!([7])]).unwrap(), Value::Integer(7));
+    match runtime.call("f", vec![gene!((line ^x 0 1))]) {
+        Err(EvalError::Runtime(e)) => assert_eq!(e.kind, ErrorKind::NoMatch),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
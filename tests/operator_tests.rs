@@ -0,0 +1,155 @@
+extern crate gene;
+
+mod common;
+
+use ordered_float::OrderedFloat;
+
+use gene::runtime::Runtime;
+use gene::types::Value;
+use gene::vm::error::ErrorKind;
+
+use common::{eval, eval_error};
+
+fn float(f: f64) -> Value {
+    Value::Float(OrderedFloat(f))
+}
+
+#[test]
+fn test_integer_arithmetic() {
+    assert_eq!(eval("(1 + 2)"), Value::Integer(3));
+    assert_eq!(eval("(1 - 2)"), Value::Integer(-1));
+    assert_eq!(eval("(3 * 4)"), Value::Integer(12));
+    assert_eq!(eval("(7 / 2)"), Value::Integer(3));
+    assert_eq!(eval("(-7 / 2)"), Value::Integer(-3));
+    assert_eq!(eval("(7 % 3)"), Value::Integer(1));
+    assert_eq!(eval("(-7 % 3)"), Value::Integer(-1));
+    assert_eq!(eval("(2 ** 10)"), Value::Integer(1024));
+    assert_eq!(eval("(2 ** -1)"), float(0.5));
+}
+
+#[test]
+fn test_float_arithmetic() {
+    assert_eq!(eval("(1.5 + 2.5)"), float(4.0));
+    assert_eq!(eval("(1 + 0.5)"), float(1.5));
+    assert_eq!(eval("(0.5 * 4)"), float(2.0));
+    assert_eq!(eval("(7 / 2.0)"), float(3.5));
+    assert_eq!(eval("(7.5 % 2)"), float(1.5));
+    assert_eq!(eval("(4 ** 0.5)"), float(2.0));
+}
+
+#[test]
+fn test_numeric_comparisons() {
+    assert_eq!(eval("(1 < 2)"), Value::Boolean(true));
+    assert_eq!(eval("(2 <= 2)"), Value::Boolean(true));
+    assert_eq!(eval("(1 > 2)"), Value::Boolean(false));
+    assert_eq!(eval("(2 >= 3)"), Value::Boolean(false));
+    assert_eq!(eval("(1 < 1.5)"), Value::Boolean(true));
+    assert_eq!(eval("(2.5 > 2)"), Value::Boolean(true));
+    assert_eq!(eval("(1 == 1.0)"), Value::Boolean(true));
+    assert_eq!(eval("(0.1 == 0.2)"), Value::Boolean(false));
+}
+
+#[test]
+fn test_arithmetic_errors() {
+    assert_eq!(eval_error("(1 / 0)").kind, ErrorKind::DivisionByZero);
+    assert_eq!(eval_error("(1 % 0)").kind, ErrorKind::DivisionByZero);
+    assert_eq!(eval_error("(1.0 / 0)").kind, ErrorKind::DivisionByZero);
+    assert_eq!(eval_error("(9223372036854775807 + 1)").kind, ErrorKind::Overflow);
+    assert_eq!(eval_error("(-9223372036854775807 - 2)").kind, ErrorKind::Overflow);
+    assert_eq!(eval_error("(4294967296 * 4294967296)").kind, ErrorKind::Overflow);
+    assert_eq!(eval_error("(2 ** 64)").kind, ErrorKind::Overflow);
+    assert_eq!(eval_error("(1 + null)").kind, ErrorKind::TypeError);
+}
+
+#[test]
+fn test_equality() {
+    assert_eq!(eval("(\"a\" == \"a\")"), Value::Boolean(true));
+    assert_eq!(eval("(\"a\" == \"b\")"), Value::Boolean(false));
+    assert_eq!(eval("(\"a\" != \"b\")"), Value::Boolean(true));
+    assert_eq!(eval("(1 != 1)"), Value::Boolean(false));
+    assert_eq!(eval("(1 != 1.5)"), Value::Boolean(true));
+    assert_eq!(eval("(null == null)"), Value::Boolean(true));
+    assert_eq!(eval("(true == false)"), Value::Boolean(false));
+    assert_eq!(eval("(null == false)"), Value::Boolean(false));
+    assert_eq!(eval("(1 == \"1\")"), Value::Boolean(false));
+    assert_eq!(eval("(1 != \"1\")"), Value::Boolean(true));
+    assert_eq!(eval("([1 [2 \"x\"]] == [1 [2.0 \"x\"]])"), Value::Boolean(true));
+    assert_eq!(eval("([1 2] == [1 2 3])"), Value::Boolean(false));
+    assert_eq!(eval("({^a 1 ^b [2]} == {^b [2] ^a 1})"), Value::Boolean(true));
+    assert_eq!(eval("({^a 1} != {^a 2})"), Value::Boolean(true));
+    assert_eq!(eval("(var a 1) ([a 2] == [1 2])"), Value::Boolean(true));
+}
+
+#[test]
+fn test_function_identity() {
+    assert_eq!(eval("(fn f _ 1) (f == f)"), Value::Boolean(true));
+    assert_eq!(eval("(fn f _ 1) (fn g _ 1) (f == g)"), Value::Boolean(false));
+    assert_eq!(eval("(fn f _ 1) (var g f) (f == g)"), Value::Boolean(true));
+    assert_eq!(eval("(fn f _ 1) (f != 1)"), Value::Boolean(true));
+}
+
+#[test]
+fn test_string_operators() {
+    assert_eq!(eval("(\"ab\" + \"cd\")"), Value::String("abcd".to_string()));
+    assert_eq!(eval("(var s \"a\") ((s + \"b\") + s)"), Value::String("aba".to_string()));
+    assert_eq!(eval("(\"abc\" < \"abd\")"), Value::Boolean(true));
+    assert_eq!(eval("(\"b\" > \"abc\")"), Value::Boolean(true));
+    assert_eq!(eval("(\"a\" <= \"a\")"), Value::Boolean(true));
+    assert_eq!(eval("(\"a\" >= \"b\")"), Value::Boolean(false));
+    assert_eq!(eval_error("(\"a\" + 1)").kind, ErrorKind::TypeError);
+    assert_eq!(eval_error("(\"a\" - \"b\")").kind, ErrorKind::TypeError);
+    assert_eq!(eval_error("(\"a\" < 1)").kind, ErrorKind::TypeError);
+}
+
+#[test]
+fn test_logical_operators() {
+    assert_eq!(eval("(true && false)"), Value::Boolean(false));
+    assert_eq!(eval("(true && true)"), Value::Boolean(true));
+    assert_eq!(eval("(false || true)"), Value::Boolean(true));
+    assert_eq!(eval("(false || false)"), Value::Boolean(false));
+    // The result is the operand that decided it
+    assert_eq!(eval("(1 && \"a\")"), Value::String("a".to_string()));
+    assert_eq!(eval("(0 && \"a\")"), Value::Integer(0));
+    assert_eq!(eval("(null || [1])"), Value::Array(vec![Value::Integer(1)]));
+    assert_eq!(eval("(2 || undefined_var)"), Value::Integer(2));
+    assert_eq!(eval("((1 < 2) && ((2 < 3) || false))"), Value::Boolean(true));
+}
+
+#[test]
+fn test_short_circuit() {
+    // The second operand would fail if it was evaluated
+    assert_eq!(eval("(false && (1 / 0))"), Value::Boolean(false));
+    assert_eq!(eval("(true || (1 / 0))"), Value::Boolean(true));
+    assert_eq!(eval_error("(true && (1 / 0))").kind, ErrorKind::DivisionByZero);
+
+    let mut runtime = Runtime::new();
+    runtime.eval("(var count 0) (fn inc _ (count = (count + 1)))").unwrap();
+    runtime.eval("(false && (inc)) (true || (inc)) (true && (inc)) (false || (inc))").unwrap();
+    assert_eq!(runtime.get_global("count"), Some(Value::Integer(2)));
+}
+
+#[test]
+fn test_not() {
+    assert_eq!(eval("(! true)"), Value::Boolean(false));
+    assert_eq!(eval("(not null)"), Value::Boolean(true));
+    assert_eq!(eval("(! \"\")"), Value::Boolean(true));
+    assert_eq!(eval("(not [1])"), Value::Boolean(false));
+    assert_eq!(eval("(! (1 > 2))"), Value::Boolean(true));
+}
+
+#[test]
+fn test_truthiness_in_conditions() {
+    let cases = [
+        ("null", false), ("false", false), ("0", false), ("0.0", false), ("\"\"", false),
+        ("[]", false), ("{}", false), ("true", true), ("1", true), ("0.5", true), ("\"a\"", true),
+        ("[0]", true), ("{^a null}", true), ("(fn f _ 1)", true),
+    ];
+    for (value, truthy) in cases.iter() {
+        let expected = if *truthy { "yes" } else { "no" };
+        let source = format!("(if {} \"yes\" else \"no\")", value);
+        assert_eq!(eval(&source), Value::String(expected.to_string()), "{}", value);
+    }
+
+    // Loops stop when the condition becomes falsy
+    assert_eq!(eval("(var i 3) (var n 0) (while i (i = (i - 1)) (n = (n + 1))) n"), Value::Integer(3));
+}
@@ -5,7 +5,7 @@ use std::collections::HashMap;
 
 use ordered_float::OrderedFloat;
 
-use gene::parser::Parser;
+use gene::parser::{Parser, PathSegment, Span};
 use gene::types::Gene;
 use gene::types::Value;
 
@@ -224,3 +224,36 @@ fn test_parse_stream() {
         assert_eq!(Parser::new("ab cd").parse(), Ok(result));
     }
 }
+
+#[test]
+fn test_parse_with_spans() {
+    let span = |start, end| Some(Span { start, end });
+    {
+        let (value, spans) = Parser::new("(f ^a [1 x] \"s\")").parse_with_spans().unwrap();
+        assert_eq!(value, gene!((f ^a [1 x] "s")));
+        assert_eq!(spans.get(&[]), span(0, 16));
+        assert_eq!(spans.get(&[PathSegment::Kind]), span(1, 2));
+        assert_eq!(spans.get(&[PathSegment::Prop("a".to_string())]), span(6, 11));
+        assert_eq!(spans.get(&[PathSegment::Prop("a".to_string()), PathSegment::Item(1)]), span(9, 10));
+        assert_eq!(spans.get(&[PathSegment::Item(0)]), span(12, 15));
+    }
+    {
+        // Streams, comments and quotes
+        let source = "a\n# comment\n  {^b `c}";
+        let (_, spans) = Parser::new(source).parse_with_spans().unwrap();
+        assert_eq!(spans.get(&[PathSegment::Item(0)]), span(0, 1));
+        assert_eq!(spans.get(&[PathSegment::Item(1)]), span(14, 21));
+        let quoted = [PathSegment::Item(1), PathSegment::Prop("b".to_string()), PathSegment::Item(0)];
+        assert_eq!(spans.get(&quoted), span(19, 20));
+    }
+    {
+        // Equal values are found in source order
+        let (value, spans) = Parser::new("(x [x] {^k x})").parse_with_spans().unwrap();
+        assert_eq!(
+            spans.find(&value, &Value::Symbol("x".to_string())),
+            vec![Span { start: 1, end: 2 }, Span { start: 4, end: 5 }, Span { start: 11, end: 12 }],
+        );
+        assert_eq!(spans.find(&value, &gene!([x])), vec![Span { start: 3, end: 6 }]);
+        assert_eq!(spans.find(&value, &Value::Integer(1)), vec![]);
+    }
+}
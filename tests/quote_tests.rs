@@ -0,0 +1,69 @@
+#[macro_use]
+extern crate gene;
+
+mod common;
+
+use gene::parser::Parser;
+use gene::types::Value;
+
+use common::{compile_errors, eval};
+
+fn parse(source: &str) -> Value {
+    Parser::new(source).parse().unwrap()
+}
+
+#[test]
+fn test_quote() {
+    assert_eq!(eval("`x"), Value::Symbol("x".to_string()));
+    assert_eq!(eval("`+"), Value::Symbol("+".to_string()));
+    assert_eq!(eval("`(service ^name \"web\" (port 80))"), gene!((service ^name "web" (port 80))));
+    assert_eq!(eval("`(a + b)"), parse("(a + b)"));
+    assert_eq!(eval("`[a {^b c}]"), gene!([a {^b c}]));
+    // %% escapes the unquote
+    assert_eq!(eval("`(a %%b)"), parse("(a %b)"));
+}
+
+#[test]
+fn test_unquote() {
+    assert_eq!(eval("(var n 1) `%n"), Value::Integer(1));
+    assert_eq!(eval("(var n \"web\") `(service ^name %n)"), gene!((service ^name "web")));
+    assert_eq!(eval("(var k `port) `(%k 80)"), gene!((port 80)));
+    assert_eq!(eval("(var x 2) `(a (% (x * 10)) [b %x] {^c %x})"), gene!((a 20 [b 2] {^c 2})));
+    assert_eq!(eval("(var x 2) `(a (b (c %x)))"), gene!((a (b (c 2)))));
+    // Unquoted values are not quoted again
+    assert_eq!(eval("(var x `(b)) `(a %x)"), gene!((a (b))));
+
+    // A new value is built every time
+    let source = "
+        (fn make n `(item ^id %n ^tags []))
+        (var a (make 1))
+        ((a .tags .0) = \"x\")
+        [(make 2) a]
+    ";
+    assert_eq!(eval(source), gene!([(item ^id 2 ^tags []) (item ^id 1 ^tags ["x"])]));
+}
+
+#[test]
+fn test_gene_form() {
+    // All parts are evaluated
+    assert_eq!(eval("(var x 1) (gene `item ^id x ^name \"a\" (x + 1) 3)"), gene!((item ^id 1 ^name "a" 2 3)));
+    assert_eq!(eval("(var k `port) (gene k 80)"), gene!((port 80)));
+    assert_eq!(eval("(gene `item)"), gene!((item)));
+
+    // Building a document
+    let source = "
+        (var doc `(services))
+        (var i 0)
+        (for [name port] in [[\"web\" 80] [\"db\" 5432]]
+            ((doc @ i) = `(service ^name %name (port %port)))
+            (i = (i + 1))
+        )
+        doc
+    ";
+    assert_eq!(
+        eval(source),
+        gene!((services (service ^name "web" (port 80)) (service ^name "db" (port 5432)))),
+    );
+
+    assert_eq!(compile_errors("(gene)"), vec!["expected (gene kind ^prop value... data...)"]);
+}
@@ -0,0 +1,229 @@
+#[macro_use]
+extern crate gene;
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::rc::Rc;
+
+use gene::runtime::{EvalError, Runtime};
+use gene::types::Value;
+use gene::vm::types::Arguments;
+use gene::vm::value::RuntimeValue;
+use gene::vm::error::{ErrorKind, RuntimeError};
+
+#[test]
+fn test_eval() {
+    let mut runtime = Runtime::new();
+    assert_eq!(runtime.eval("(1 + 2)").unwrap(), Value::Integer(3));
+    assert_eq!(runtime.eval("").unwrap(), Value::Void);
+}
+
+#[test]
+fn test_state_is_kept_between_evals() {
+    let mut runtime = Runtime::new();
+    runtime.eval("(var a 1)").unwrap();
+    runtime.eval("(fn f b (a + b))").unwrap();
+    assert_eq!(runtime.eval("(f 2)").unwrap(), Value::Integer(3));
+    runtime.eval("(a = 10)").unwrap();
+    assert_eq!(runtime.eval("(f 2)").unwrap(), Value::Integer(12));
+
+    // Errors don't lose the state
+    assert!(runtime.eval("(f b)").is_err());
+    assert_eq!(runtime.eval("a").unwrap(), Value::Integer(10));
+}
+
+#[test]
+fn test_globals() {
+    let mut runtime = Runtime::new();
+    assert_eq!(runtime.get_global("a"), None);
+
+    runtime.set_global("a", Value::Integer(1));
+    assert_eq!(runtime.eval("(a + 1)").unwrap(), Value::Integer(2));
+
+    runtime.eval("(a = 5)").unwrap();
+    assert_eq!(runtime.get_global("a"), Some(Value::Integer(5)));
+
+    runtime.eval("(var b [1 2])").unwrap();
+    assert_eq!(runtime.get_global("b"), Some(Value::Array(vec![Value::Integer(1), Value::Integer(2)])));
+}
+
+#[test]
+fn test_eval_errors() {
+    let mut runtime = Runtime::new();
+    match runtime.eval("(1 = 2)") {
+        Err(EvalError::Compile(errors)) => assert_eq!(errors.len(), 1),
+        result => panic!("unexpected result {:?}", result),
+    }
+    match runtime.eval("a") {
+        Err(EvalError::Runtime(e)) => assert_eq!(e.kind, ErrorKind::UndefinedVariable),
+        result => panic!("unexpected result {:?}", result),
+    }
+    match runtime.eval_file("does-not-exist.gene") {
+        Err(EvalError::Io(_)) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn test_parse_errors() {
+    // Malformed source is reported, the host doesn't crash
+    let sources = ["(a", "[1 2", "(a ^b)", "{^a}", "`", "\"abc", "{1}", "(a ])", ")", "1x", "(a ^", "# comment\n(a"];
+    for source in sources.iter() {
+        match Runtime::new().eval(source) {
+            Err(EvalError::Parse(_)) => (),
+            result => panic!("unexpected result {:?} for {}", result, source),
+        }
+    }
+    // A comment before the end of a collection is fine
+    assert_eq!(Runtime::new().eval("[1 # one\n]").unwrap(), gene!([1]));
+}
+
+#[test]
+fn test_eval_file() {
+    let path = env::temp_dir().join("gene_runtime_test_eval_file.gene");
+    fs::write(&path, "(var x 2) (x * 3)").unwrap();
+    let mut runtime = Runtime::new();
+    let result = runtime.eval_file(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(result.unwrap(), Value::Integer(6));
+    assert_eq!(runtime.get_global("x"), Some(Value::Integer(2)));
+}
+
+#[test]
+fn test_native_functions() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut runtime = Runtime::new();
+    {
+        let log = log.clone();
+        runtime.register_native("log", move |_vm, args| {
+            let level = args.prop("level").unwrap_or_else(|| Value::String("info".to_string()));
+            log.borrow_mut().push((level, args.get(0).unwrap()));
+            Ok(Value::Null)
+        });
+    }
+    runtime.register_native("add", |_vm, args| {
+        match (args.get(0), args.get(1)) {
+            (Some(Value::Integer(a)), Some(Value::Integer(b))) => Ok(Value::Integer(a + b)),
+            _ => Err(RuntimeError::type_error("add expects two integers")),
+        }
+    });
+
+    assert_eq!(runtime.eval("(add 1 2)").unwrap(), Value::Integer(3));
+    assert_eq!(runtime.eval("(fn f a (add a (add a 1))) (f 2)").unwrap(), Value::Integer(5));
+    assert_eq!(runtime.eval("(log \"a\")").unwrap(), Value::Null);
+    runtime.eval("(var level \"warn\") (log ^level level (add 1 2))").unwrap();
+    assert_eq!(*log.borrow(), vec![
+        (Value::String("info".to_string()), Value::String("a".to_string())),
+        (Value::String("warn".to_string()), Value::Integer(3)),
+    ]);
+
+    match runtime.eval("(fn g _ (add 1 \"x\")) (g)") {
+        Err(EvalError::Runtime(e)) => {
+            assert_eq!(e.kind, ErrorKind::TypeError);
+            assert_eq!(e.message, "add expects two integers");
+            let names: Vec<&str> = e.stack.iter().map(|frame| frame.name.as_str()).collect();
+            assert_eq!(names, vec!["g", "__default__"]);
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn test_native_functions_can_use_the_vm() {
+    let mut runtime = Runtime::new();
+    runtime.register_native("get", |vm, args| {
+        match args.get(0) {
+            Some(Value::String(name)) => Ok(vm.get_global(&name).unwrap_or(Value::Null)),
+            _ => Err(RuntimeError::type_error("get expects a name")),
+        }
+    });
+    runtime.register_native("set", |vm, args| {
+        match (args.get(0), args.get(1)) {
+            (Some(Value::String(name)), Some(value)) => {
+                vm.set_global(&name, value);
+                Ok(Value::Null)
+            }
+            _ => Err(RuntimeError::type_error("set expects a name and a value")),
+        }
+    });
+    assert_eq!(runtime.eval("(set \"a\" 1) ((get \"a\") + 1)").unwrap(), Value::Integer(2));
+}
+
+#[test]
+fn test_call() {
+    let mut runtime = Runtime::new();
+    runtime.eval("(fn add [a b] (a + b))").unwrap();
+    assert_eq!(runtime.call("add", vec![Value::Integer(1), Value::Integer(2)]).unwrap(), Value::Integer(3));
+    match runtime.call("add", vec![Value::Integer(1)]) {
+        Err(EvalError::Runtime(e)) => assert_eq!(e.kind, ErrorKind::ArityMismatch),
+        result => panic!("unexpected result {:?}", result),
+    }
+    match runtime.call("sub", vec![]) {
+        Err(EvalError::Runtime(e)) => assert_eq!(e.kind, ErrorKind::UndefinedVariable),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn test_callbacks_from_native_functions() {
+    let mut runtime = Runtime::new();
+    // (sort arr comparator) with a comparator that returns whether a < b
+    runtime.register_native("sort", |vm, args| {
+        let mut items = match args.get(0) {
+            Some(Value::Array(items)) => items,
+            _ => return Err(RuntimeError::type_error("sort expects an array")),
+        };
+        let comparator = args.data[1].clone();
+        for i in 1..items.len() {
+            let mut j = i;
+            while j > 0 {
+                let data = vec![items[j].clone(), items[j - 1].clone()];
+                if vm.call(&comparator, Arguments::from_data(data))? != Value::Boolean(true) {
+                    break;
+                }
+                items.swap(j, j - 1);
+                j -= 1;
+            }
+        }
+        Ok(Value::Array(items))
+    });
+
+    let result = runtime.eval("
+        (fn desc [a b] (b < a))
+        (fn sort_desc arr (sort arr desc))
+        (sort_desc [2 3 1])
+    ");
+    assert_eq!(result.unwrap(), Value::Array(vec![Value::Integer(3), Value::Integer(2), Value::Integer(1)]));
+
+    // Errors in callbacks carry the frames of both the callback and the caller
+    match runtime.eval("(fn bad [a b] (a < c)) (fn f _ (sort [1 2] bad)) (f)") {
+        Err(EvalError::Runtime(e)) => {
+            assert_eq!(e.kind, ErrorKind::UndefinedVariable);
+            let names: Vec<&str> = e.stack.iter().map(|frame| frame.name.as_str()).collect();
+            assert_eq!(names, vec!["bad", "f", "__default__"]);
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // The VM is usable after the error
+    assert_eq!(runtime.eval("(sort_desc [1 2])").unwrap(), Value::Array(vec![Value::Integer(2), Value::Integer(1)]));
+}
+
+#[test]
+fn test_runtime_values() {
+    let value = gene!((a ^b [1 2.5 "s" x] {^c null ^d true}));
+    assert_eq!(RuntimeValue::from(&value).to_value(), value);
+
+    let mut runtime = Runtime::new();
+    runtime.set_global("v", value.clone());
+    assert_eq!(runtime.eval("v").unwrap(), value);
+
+    // Functions come out as (fn name)
+    assert_eq!(runtime.eval("(fn f _ 1)").unwrap(), gene!((fn f)));
+    runtime.register_native("g", |_vm, _args| Ok(Value::Null));
+    assert_eq!(runtime.get_global("g"), Some(gene!((fn g))));
+
+    // Functions can be passed around
+    assert_eq!(runtime.eval("(fn call_it [h a] (h a)) (fn inc a (a + 1)) (call_it inc 1)").unwrap(), Value::Integer(2));
+}
@@ -0,0 +1,75 @@
+#[macro_use]
+extern crate gene;
+
+use gene::parser::Parser;
+use gene::template::{render, Template, TemplateError};
+use gene::types::Value;
+
+#[test]
+fn test_placeholders() {
+    let context = gene!({^name "web" ^env {^region "us"} ^ports [80 443] ^svc (svc ^id 7 "x")});
+    let template = Template::parse("[%name %env.region %ports.1 %svc.id %svc.0 %%name]").unwrap();
+    assert_eq!(template.render(&context).unwrap(), gene!(["web" "us" 443 7 "x" #(Value::Symbol("%name".to_string()))]));
+
+    let template = Template::parse("(deploy ^app %name ^image (%str \"repo/\" %name \":\" %ports.0))").unwrap();
+    assert_eq!(
+        template.render(&context).unwrap(),
+        gene!((deploy ^app "web" ^image "repo/web:80"))
+    );
+}
+
+#[test]
+fn test_loops() {
+    let context = gene!({^ports [80 443] ^labels {^a 1 ^b 2}});
+    let template = Template::parse("(service (%for p in %ports (port %p) %p))").unwrap();
+    assert_eq!(template.render(&context).unwrap(), gene!((service (port 80) 80 (port 443) 443)));
+
+    let template = Template::parse("[(%for [k v] in %labels (label ^name %k ^value %v))]").unwrap();
+    assert_eq!(
+        template.render(&context).unwrap(),
+        gene!([(label ^name "a" ^value 1) (label ^name "b" ^value 2)])
+    );
+}
+
+#[test]
+fn test_conditionals() {
+    let template = Template::parse("[(%if %debug 1 2 else 3) (%if %verbose 4)]").unwrap();
+    assert_eq!(template.render(&gene!({^debug true ^verbose 0})).unwrap(), gene!([1 2]));
+    assert_eq!(template.render(&gene!({^debug null ^verbose "v"})).unwrap(), gene!([3 4]));
+
+    // A prop whose value renders to nothing is dropped
+    let template = Template::parse("(x ^a (%if %on 1))").unwrap();
+    assert_eq!(template.render(&gene!({^on false})).unwrap(), gene!((x)));
+}
+
+#[test]
+fn test_render_to_string() {
+    let template = Template::parse("(%for x in %items (item %x))").unwrap();
+    assert_eq!(
+        template.render_to_string(&gene!({^items [1 2]})).unwrap(),
+        "(item 1)\n(item 2)"
+    );
+    assert_eq!(render(&Value::Symbol("%x".to_string()), &gene!({^x [1]})).unwrap(), gene!([1]));
+}
+
+#[test]
+fn test_errors() {
+    let parse = |s| Parser::new(s).parse().unwrap();
+    assert_eq!(
+        render(&parse("[%missing]"), &gene!({})),
+        Err(TemplateError::new("undefined placeholder %missing"))
+    );
+    assert_eq!(
+        render(&parse("(%for x %items)"), &gene!({^items []})),
+        Err(TemplateError::new("expected (%for x in collection body...)"))
+    );
+    assert_eq!(
+        render(&parse("(%for x in %n)"), &gene!({^n 1})),
+        Err(TemplateError::new("can not iterate over 1"))
+    );
+    assert_eq!(
+        render(&parse("(x ^a (%for i in %items %i))"), &gene!({^items [1 2]})),
+        Err(TemplateError::new("(%for i in %items %i) rendered 2 values where one is expected"))
+    );
+    assert_eq!(render(&Value::Null, &gene!({})), Ok(Value::Null));
+}
@@ -19,91 +19,73 @@ fn test_basic_stmts() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("1.1");
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Float(OrderedFloat(1.1)));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Float(OrderedFloat(1.1)));
     }
     {
         let mut parser = Parser::new("\"ab\"");
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::String("ab".to_string()));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::String("ab".to_string()));
     }
     {
         let mut parser = Parser::new("null");
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Null);
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Null);
     }
     {
         let mut parser = Parser::new("true");
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Boolean(true));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Boolean(true));
     }
     {
         let mut parser = Parser::new("[]");
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Array(Vec::new()));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Array(Vec::new()));
     }
     {
         let mut parser = Parser::new("[1]");
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Array(vec![Value::Integer(1)]));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Array(vec![Value::Integer(1)]));
     }
     {
         let mut parser = Parser::new("{}");
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Map(HashMap::new()));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Map(HashMap::new()));
     }
     {
         let mut parser = Parser::new("{^key 1}");
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
+        let result = vm.load_module(module).unwrap();
         assert_eq!(
-            *result,
+            result,
             Value::Map(map! {
                 "key" => Value::Integer(1),
             })
@@ -125,10 +107,8 @@ fn test_variables() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("
@@ -139,10 +119,8 @@ fn test_variables() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Array(vec![
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Array(vec![
             Value::Integer(1),
             Value::Integer(2),
             Value::Integer(3),
@@ -157,10 +135,8 @@ fn test_variables() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Array(vec![
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Array(vec![
             Value::Integer(1),
             Value::Integer(2),
             Value::Integer(3),
@@ -175,11 +151,9 @@ fn test_variables() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
+        let result = vm.load_module(module).unwrap();
         assert_eq!(
-            *result,
+            result,
             Value::Map(map! {
                 "ka" => Value::Integer(1),
                 "kb" => Value::Integer(2),
@@ -195,11 +169,9 @@ fn test_variables() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
+        let result = vm.load_module(module).unwrap();
         assert_eq!(
-            *result,
+            result,
             Value::Map(map! {
                 "ka" => Value::Integer(1),
                 "kb" => Value::Integer(3),
@@ -219,10 +191,8 @@ fn test_binary_operations() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(3));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(3));
     }
     {
         let mut parser = Parser::new("
@@ -231,10 +201,8 @@ fn test_binary_operations() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Boolean(true));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Boolean(true));
     }
 }
 
@@ -251,10 +219,8 @@ fn test_assignments() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(2));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(2));
     }
 }
 
@@ -270,10 +236,8 @@ fn test_functions() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("
@@ -282,10 +246,8 @@ fn test_functions() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("
@@ -295,10 +257,8 @@ fn test_functions() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("
@@ -308,10 +268,8 @@ fn test_functions() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(3));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(3));
     }
     {
         let mut parser = Parser::new("
@@ -322,10 +280,8 @@ fn test_functions() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("
@@ -336,10 +292,8 @@ fn test_functions() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(3));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(3));
     }
 }
 
@@ -354,10 +308,8 @@ fn test_ifs() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
     {
         let mut parser = Parser::new("
@@ -366,10 +318,8 @@ fn test_ifs() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(2));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(2));
     }
     {
         let mut parser = Parser::new("
@@ -378,10 +328,8 @@ fn test_ifs() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(2));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(2));
     }
     {
         let mut parser = Parser::new("
@@ -390,10 +338,8 @@ fn test_ifs() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(4));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(4));
     }
 }
 
@@ -412,10 +358,8 @@ fn test_loops() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(2));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(2));
     }
     {
         let mut parser = Parser::new("
@@ -429,10 +373,8 @@ fn test_loops() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
 }
 
@@ -454,9 +396,7 @@ fn test_fibonacci() {
         let parsed = parser.parse();
         let module_temp = compiler.compile(parsed.unwrap());
         let module = &module_temp.borrow();
-        let result_temp = vm.load_module(module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(8));
+        let result = vm.load_module(module).unwrap();
+        assert_eq!(result, Value::Integer(8));
     }
 }
@@ -25,12 +25,10 @@ fn test_wip() {
         ");
         let parsed = parser.parse();
         let mut compiler = Compiler::new();
-        compiler.compile(parsed.unwrap());
+        compiler.compile(parsed.unwrap()).unwrap();
         let module = compiler.module;
         dbg!(module.get_default_block());
-        let result_temp = VirtualMachine::new().load_module(&module);
-        let borrowed = result_temp.borrow();
-        let result = borrowed.downcast_ref::<Value>().unwrap();
-        assert_eq!(*result, Value::Integer(1));
+        let result = VirtualMachine::new().load_module(&module).unwrap();
+        assert_eq!(result, Value::Integer(1));
     }
 }